@@ -0,0 +1,528 @@
+//! Access control for the local server - allowed web origins and paired web apps
+//!
+//! Browsers send the page's `Origin` with every cross-origin request, so requests from pages
+//! that aren't on the allowlist are refused, whatever the request. Printing also needs a bearer
+//! token, issued once by pairing: the status window shows a short-lived code, the web app sends
+//! it to `/pair` and keeps the token it gets back. The portal can hand over a signing secret when
+//! pairing, so its print requests can be signed as well (see `signing`). The allowlist and the
+//! paired apps are stored in `access.json` in the app data dir; only a hash of each token is kept.
+
+use crate::config;
+use crate::error::HelperError;
+use chrono::{DateTime, Duration, Utc};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+/// Allowlist and paired apps currently in effect
+static ACCESS: Lazy<RwLock<AccessFile>> = Lazy::new(|| RwLock::new(load_access_file()));
+
+/// Pairing code waiting to be used, if the status window opened one
+static PAIRING: Lazy<RwLock<Option<Pairing>>> = Lazy::new(|| RwLock::new(None));
+
+/// When each paired app's recent print requests arrived, oldest first, for the rate limit
+static REQUEST_TIMES: Lazy<RwLock<HashMap<String, VecDeque<Instant>>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// When `last_used` times were last written to `access.json`, and whether newer ones are only in memory
+static LAST_USED_SAVED_AT: Lazy<Mutex<Option<Instant>>> = Lazy::new(|| Mutex::new(None));
+static LAST_USED_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Least time between writes of `access.json` that only record when apps were last used
+const LAST_USED_SAVE_SECS: u64 = 60;
+
+/// How long a pairing code can be used
+const PAIRING_CODE_MINUTES: i64 = 5;
+
+/// Wrong codes accepted before the pairing code is withdrawn
+const MAX_PAIRING_ATTEMPTS: u32 = 5;
+
+/// Shortest signing secret accepted when pairing
+const MIN_SIGNING_SECRET_LEN: usize = 32;
+
+/// Contents of the access file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AccessFile {
+    /// Web origins allowed to call the server, e.g. `https://portal.example.com`;
+    /// `https://*.example.com` allows every subdomain
+    pub allowed_origins: Vec<String>,
+    #[serde(default)]
+    pub paired_apps: Vec<PairedApp>,
+    #[serde(default)]
+    pub signing: SigningSettings,
+}
+
+/// Whether print requests must be signed, and how old a signature may be
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SigningSettings {
+    /// Refuse print requests without a valid signature
+    pub required: bool,
+    /// Largest difference between a request's timestamp and the local clock, in seconds
+    pub window_secs: u64,
+}
+
+impl Default for SigningSettings {
+    fn default() -> Self {
+        Self {
+            required: false,
+            window_secs: 300,
+        }
+    }
+}
+
+/// A web app that was paired from the status window
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PairedApp {
+    pub id: String,
+    /// Name the web app gave itself when pairing
+    pub name: String,
+    /// Origin the app was paired from; browser requests with its token must come from there
+    pub origin: Option<String>,
+    /// SHA-256 of the token, hex encoded
+    pub token_hash: String,
+    pub paired_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<DateTime<Utc>>,
+    /// Secret shared with the portal for signing print requests (HMAC-SHA256 key)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_secret: Option<String>,
+}
+
+/// A paired app as shown in the status window (without the token hash or secret)
+#[derive(Serialize, Clone, Debug)]
+pub struct PairedAppInfo {
+    pub id: String,
+    pub name: String,
+    pub origin: Option<String>,
+    pub paired_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+    /// Whether the app can sign its requests
+    pub signed: bool,
+}
+
+/// The paired app a request's token belongs to
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub app_id: String,
+    pub name: String,
+    pub signing_secret: Option<String>,
+}
+
+/// Pairing code shown in the status window
+#[derive(Serialize, Clone, Debug)]
+pub struct PairingCode {
+    pub code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Access settings for the status window
+#[derive(Serialize, Clone, Debug)]
+pub struct AccessSettings {
+    pub allowed_origins: Vec<String>,
+    pub paired_apps: Vec<PairedAppInfo>,
+    pub pairing: Option<PairingCode>,
+    pub signing: SigningSettings,
+    pub path: String,
+}
+
+struct Pairing {
+    code: PairingCode,
+    failed_attempts: u32,
+}
+
+impl Default for AccessFile {
+    /// The AnyMobile portal, and nothing paired yet
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![
+                "https://anymobile.com".to_string(),
+                "https://*.anymobile.com".to_string(),
+            ],
+            paired_apps: vec![],
+            signing: SigningSettings::default(),
+        }
+    }
+}
+
+/// Get the path of the access file
+pub fn get_access_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("anymobile-print-helper")
+        .join("access.json")
+}
+
+/// Load the access file, writing the defaults if there is no file yet
+fn load_access_file() -> AccessFile {
+    let path = get_access_path();
+
+    if !path.exists() {
+        let file = AccessFile::default();
+        if let Err(e) = write_access_file(&file) {
+            tracing::warn!("Could not write default access settings: {}", e);
+        }
+        return file;
+    }
+
+    match fs::read(&path).map_err(|e| e.to_string()).and_then(|data| {
+        serde_json::from_slice::<AccessFile>(&data).map_err(|e| e.to_string())
+    }) {
+        Ok(file) => {
+            tracing::info!(
+                "Loaded access settings from {:?} ({} origin(s), {} paired app(s))",
+                path,
+                file.allowed_origins.len(),
+                file.paired_apps.len()
+            );
+            file
+        }
+        Err(e) => {
+            // Falling back to the defaults forgets the pairings, so the web app has to pair again
+            tracing::error!("Invalid access file {:?}, using defaults: {}", path, e);
+            AccessFile::default()
+        }
+    }
+}
+
+fn write_access_file(file: &AccessFile) -> Result<(), String> {
+    let path = get_access_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create data directory: {}", e))?;
+    }
+    let data = serde_json::to_vec_pretty(file).map_err(|e| e.to_string())?;
+    // A crash halfway through a plain write would truncate the file and unpair every app
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, data)
+        .and_then(|()| fs::rename(&temp_path, &path))
+        .map_err(|e| format!("Could not save access settings: {}", e))
+}
+
+/// Update the access file in memory and on disk
+fn update_access(change: impl FnOnce(&mut AccessFile)) -> Result<(), HelperError> {
+    let mut access = ACCESS
+        .write()
+        .map_err(|_| HelperError::Internal("Access settings are unavailable".to_string()))?;
+    change(&mut access);
+    LAST_USED_PENDING.store(false, Ordering::Relaxed);
+    write_access_file(&access).map_err(HelperError::Internal)
+}
+
+/// Load the access settings at startup
+pub fn init() {
+    Lazy::force(&ACCESS);
+}
+
+/// Whether a web origin (`scheme://host[:port]`) may call the server
+pub fn is_origin_allowed(origin: &str) -> bool {
+    let origin = normalize_origin(origin);
+    ACCESS
+        .read()
+        .map(|access| access.allowed_origins.iter().any(|allowed| origin_matches(allowed, &origin)))
+        .unwrap_or(false)
+}
+
+/// Lowercase an origin and drop a trailing slash
+fn normalize_origin(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_lowercase()
+}
+
+/// Match an origin against an allowlist entry; `scheme://*.domain` matches any subdomain
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+    let allowed = normalize_origin(allowed);
+    match allowed.split_once("://*.") {
+        Some((scheme, domain)) => origin
+            .strip_prefix(scheme)
+            .and_then(|rest| rest.strip_prefix("://"))
+            .and_then(|host| host.strip_suffix(domain))
+            .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+        None => allowed == origin,
+    }
+}
+
+/// Check allowlist entries edited in the status window
+fn validate_origin(entry: &str) -> Result<(), HelperError> {
+    let invalid = |reason: &str| HelperError::InvalidOptions(format!("Origin '{}': {}", entry, reason));
+
+    // A wildcard is only allowed for the whole subdomain part
+    let concrete = entry.replacen("://*.", "://wildcard.", 1);
+    if concrete.contains('*') {
+        return Err(invalid("'*' is only allowed as '*.' at the start of the host"));
+    }
+    let url = reqwest::Url::parse(&concrete).map_err(|e| invalid(&e.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid("must start with http:// or https://"));
+    }
+    if url.path() != "/" || url.query().is_some() || url.fragment().is_some() {
+        return Err(invalid("must not have a path"));
+    }
+    Ok(())
+}
+
+/// Save the allowlist edited in the status window
+pub fn save_allowed_origins(origins: Vec<String>) -> Result<(), HelperError> {
+    let origins: Vec<String> = origins
+        .iter()
+        .map(|origin| normalize_origin(origin))
+        .filter(|origin| !origin.is_empty())
+        .collect();
+    for origin in &origins {
+        validate_origin(origin)?;
+    }
+
+    let count = origins.len();
+    update_access(|access| access.allowed_origins = origins)?;
+    tracing::info!("Allowed origins saved ({})", count);
+    Ok(())
+}
+
+/// Access settings for the status window
+pub fn get_access_settings() -> AccessSettings {
+    let access = ACCESS.read().map(|access| access.clone()).unwrap_or_default();
+    AccessSettings {
+        allowed_origins: access.allowed_origins,
+        paired_apps: access
+            .paired_apps
+            .into_iter()
+            .map(|app| PairedAppInfo {
+                id: app.id,
+                name: app.name,
+                origin: app.origin,
+                paired_at: app.paired_at,
+                last_used: app.last_used,
+                signed: app.signing_secret.is_some(),
+            })
+            .collect(),
+        pairing: current_pairing(),
+        signing: access.signing,
+        path: get_access_path().to_string_lossy().to_string(),
+    }
+}
+
+/// Signing settings in effect
+pub fn signing_settings() -> SigningSettings {
+    ACCESS.read().map(|access| access.signing.clone()).unwrap_or_default()
+}
+
+/// Turn the signature requirement on or off from the status window
+pub fn set_signing_required(required: bool) -> Result<(), HelperError> {
+    update_access(|access| access.signing.required = required)?;
+    tracing::info!("Signed print requests {}", if required { "required" } else { "optional" });
+    Ok(())
+}
+
+/// The pairing code in effect, if any and not expired
+fn current_pairing() -> Option<PairingCode> {
+    PAIRING
+        .read()
+        .ok()?
+        .as_ref()
+        .filter(|pairing| pairing.code.expires_at > Utc::now())
+        .map(|pairing| pairing.code.clone())
+}
+
+/// Open pairing with a new one-time code, replacing any earlier code
+pub fn start_pairing() -> Result<PairingCode, HelperError> {
+    let code = PairingCode {
+        code: format!("{:06}", uuid::Uuid::new_v4().as_u128() % 1_000_000),
+        expires_at: Utc::now() + Duration::minutes(PAIRING_CODE_MINUTES),
+    };
+    let mut pairing = PAIRING
+        .write()
+        .map_err(|_| HelperError::Internal("Pairing is unavailable".to_string()))?;
+    *pairing = Some(Pairing {
+        code: code.clone(),
+        failed_attempts: 0,
+    });
+    tracing::info!("Pairing opened until {}", code.expires_at.format("%H:%M:%S"));
+    Ok(code)
+}
+
+/// Withdraw the pairing code
+pub fn cancel_pairing() {
+    if let Ok(mut pairing) = PAIRING.write() {
+        *pairing = None;
+    }
+}
+
+/// Exchange a pairing code for a token, returning the new app's ID and its token
+/// The code works once; too many wrong codes withdraw it. A signing secret from the portal is
+/// kept for checking the app's signed requests.
+pub fn pair(
+    code: &str,
+    name: Option<&str>,
+    origin: Option<&str>,
+    signing_secret: Option<&str>,
+) -> Result<(String, String), HelperError> {
+    if signing_secret.is_some_and(|secret| secret.len() < MIN_SIGNING_SECRET_LEN) {
+        return Err(HelperError::InvalidRequest(format!(
+            "The signing secret must be at least {} characters",
+            MIN_SIGNING_SECRET_LEN
+        )));
+    }
+
+    {
+        let mut pairing = PAIRING
+            .write()
+            .map_err(|_| HelperError::Internal("Pairing is unavailable".to_string()))?;
+        let open = pairing.as_mut().filter(|p| p.code.expires_at > Utc::now()).ok_or_else(|| {
+            HelperError::Unauthorized("No pairing code is open; start pairing in the print helper".to_string())
+        })?;
+        if open.code.code != code.trim() {
+            open.failed_attempts += 1;
+            if open.failed_attempts >= MAX_PAIRING_ATTEMPTS {
+                *pairing = None;
+                tracing::warn!("Pairing code withdrawn after {} wrong attempts", MAX_PAIRING_ATTEMPTS);
+            }
+            return Err(HelperError::Unauthorized("Wrong pairing code".to_string()));
+        }
+        *pairing = None;
+    }
+
+    let token = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let app = PairedApp {
+        id: uuid::Uuid::new_v4().to_string(),
+        name: name
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .unwrap_or("Web app")
+            .to_string(),
+        origin: origin.map(normalize_origin),
+        token_hash: hash_token(&token),
+        paired_at: Utc::now(),
+        last_used: None,
+        signing_secret: signing_secret.map(str::to_string),
+    };
+    let id = app.id.clone();
+    tracing::info!(
+        "Paired '{}' from {}{}",
+        app.name,
+        app.origin.as_deref().unwrap_or("no origin"),
+        if app.signing_secret.is_some() { " with a signing secret" } else { "" }
+    );
+    update_access(|access| access.paired_apps.push(app))?;
+    Ok((id, token))
+}
+
+/// Forget a paired app; its token stops working immediately
+pub fn revoke_app(id: &str) -> Result<(), HelperError> {
+    let mut found = false;
+    update_access(|access| {
+        let before = access.paired_apps.len();
+        access.paired_apps.retain(|app| app.id != id);
+        found = access.paired_apps.len() != before;
+    })?;
+    if !found {
+        return Err(HelperError::InvalidRequest(format!("No paired app {}", id)));
+    }
+    tracing::info!("Revoked paired app {}", id);
+    Ok(())
+}
+
+/// Check a bearer token from a request, and that a browser sends it from the origin it was paired from
+pub fn check_token(token: Option<&str>, origin: Option<&str>) -> Result<Caller, HelperError> {
+    let token = token.filter(|t| !t.is_empty()).ok_or_else(|| {
+        HelperError::Unauthorized("A pairing token is required; pair the web app from the print helper".to_string())
+    })?;
+    let hash = hash_token(token);
+    let origin = origin.map(normalize_origin);
+
+    let mut access = ACCESS
+        .write()
+        .map_err(|_| HelperError::Internal("Access settings are unavailable".to_string()))?;
+    let app = access
+        .paired_apps
+        .iter_mut()
+        .find(|app| app.token_hash == hash)
+        .ok_or_else(|| HelperError::Unauthorized("Unknown or revoked pairing token".to_string()))?;
+    if let (Some(paired), Some(origin)) = (&app.origin, &origin) {
+        if paired != origin {
+            return Err(HelperError::Unauthorized(format!("This token was paired from {}", paired)));
+        }
+    }
+
+    app.last_used = Some(Utc::now());
+    let caller = Caller {
+        app_id: app.id.clone(),
+        name: app.name.clone(),
+        signing_secret: app.signing_secret.clone(),
+    };
+    save_last_used(&access);
+    Ok(caller)
+}
+
+/// Write a new `last_used` time, at most every `LAST_USED_SAVE_SECS`
+/// Times in between stay in memory until the next write, or until `flush_last_used` at exit.
+fn save_last_used(access: &AccessFile) {
+    let due = LAST_USED_SAVED_AT.lock().map_or(true, |mut saved_at| {
+        let due = saved_at.is_none_or(|at| at.elapsed().as_secs() >= LAST_USED_SAVE_SECS);
+        if due {
+            *saved_at = Some(Instant::now());
+        }
+        due
+    });
+    LAST_USED_PENDING.store(!due, Ordering::Relaxed);
+    if due {
+        if let Err(e) = write_access_file(access) {
+            tracing::warn!("{}", e);
+        }
+    }
+}
+
+/// Write `last_used` times that are only in memory, when the helper exits
+pub fn flush_last_used() {
+    if !LAST_USED_PENDING.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let Ok(access) = ACCESS.read() else { return };
+    if let Err(e) = write_access_file(&access) {
+        tracing::warn!("{}", e);
+    }
+}
+
+/// Count a print request against its paired app's rate limit (see `config::RateLimitSettings`)
+/// A request over the limit isn't counted; the error says how many seconds until one is allowed.
+pub fn check_rate_limit(key: &str) -> Result<(), (HelperError, u64)> {
+    let settings = &config::get_config().rate_limit;
+    if settings.max_requests == 0 {
+        return Ok(());
+    }
+    let window = std::time::Duration::from_secs(settings.per_secs);
+    let now = Instant::now();
+
+    let Ok(mut request_times) = REQUEST_TIMES.write() else {
+        return Ok(());
+    };
+    request_times.retain(|_, times| times.back().is_some_and(|last| now.duration_since(*last) < window));
+    let times = request_times.entry(key.to_string()).or_default();
+    while times.front().is_some_and(|first| now.duration_since(*first) >= window) {
+        times.pop_front();
+    }
+
+    if times.len() >= settings.max_requests as usize {
+        let wait = times.front().map_or(window, |first| window - now.duration_since(*first));
+        let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        return Err((
+            HelperError::RateLimited(format!(
+                "Too many print requests from {} (at most {} per {} seconds); try again in {} seconds",
+                key, settings.max_requests, settings.per_secs, retry_after
+            )),
+            retry_after,
+        ));
+    }
+    times.push_back(now);
+    Ok(())
+}
+
+/// SHA-256 of a token, hex encoded
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -1,6 +1,7 @@
 //! Certificate management for Windows
 //! Handles checking if cert is trusted and installing to Windows stores
 
+use crate::error::HelperError;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -42,7 +43,7 @@ pub fn invalidate_cert_cache() {
 /// Checks BOTH CurrentUser\Root AND LocalMachine\Root stores
 /// Results are cached for 30 seconds to avoid PowerShell spam
 #[cfg(target_os = "windows")]
-pub fn is_cert_trusted() -> Result<bool, String> {
+pub fn is_cert_trusted() -> Result<bool, HelperError> {
     // Check cache first
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -65,7 +66,7 @@ if ($currentUser -or $localMachine) { "true" } else { "false" }
         .args(["-ExecutionPolicy", "Bypass", "-NoProfile", "-Command", ps_script])
         .creation_flags(CREATE_NO_WINDOW)
         .output()
-        .map_err(|e| format!("Failed to run PowerShell: {}", e))?;
+        .map_err(|e| HelperError::Certificate(format!("Failed to run PowerShell: {}", e)))?;
 
     let stdout = String::from_utf8_lossy(&output.stdout).trim().to_lowercase();
     tracing::debug!("Certificate trust check result: {}", stdout);
@@ -81,11 +82,11 @@ if ($currentUser -or $localMachine) { "true" } else { "false" }
 
 /// Install certificate to CurrentUser trusted root store (no admin required)
 #[cfg(target_os = "windows")]
-pub fn install_cert_current_user() -> Result<(), String> {
+pub fn install_cert_current_user() -> Result<(), HelperError> {
     let cert_path = get_cert_path();
 
     if !cert_path.exists() {
-        return Err("Certificate not found. Please restart the application.".to_string());
+        return Err(HelperError::Certificate("Certificate not found. Please restart the application.".to_string()));
     }
 
     let cert_path_str = cert_path.to_string_lossy();
@@ -131,7 +132,7 @@ try {{
         .args(["-ExecutionPolicy", "Bypass", "-NoProfile", "-Command", &ps_script])
         .creation_flags(CREATE_NO_WINDOW)
         .output()
-        .map_err(|e| format!("Failed to run PowerShell: {}", e))?;
+        .map_err(|e| HelperError::Certificate(format!("Failed to run PowerShell: {}", e)))?;
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
@@ -147,17 +148,17 @@ try {{
     } else {
         let error_msg = if stderr.is_empty() { stdout.to_string() } else { stderr.to_string() };
         tracing::error!("Certificate installation failed: {}", error_msg);
-        Err(format!("Installation failed: {}", error_msg))
+        Err(HelperError::Certificate(format!("Installation failed: {}", error_msg)))
     }
 }
 
 /// Install certificate to LocalMachine store using elevated PowerShell (requires UAC)
 #[cfg(target_os = "windows")]
-pub fn install_cert_local_machine() -> Result<(), String> {
+pub fn install_cert_local_machine() -> Result<(), HelperError> {
     let cert_path = get_cert_path();
 
     if !cert_path.exists() {
-        return Err("Certificate not found. Please restart the application.".to_string());
+        return Err(HelperError::Certificate("Certificate not found. Please restart the application.".to_string()));
     }
 
     let cert_path_str = cert_path.to_string_lossy();
@@ -188,7 +189,7 @@ try {{
     let temp_dir = std::env::temp_dir();
     let script_path = temp_dir.join("install_cert.ps1");
     std::fs::write(&script_path, script_content)
-        .map_err(|e| format!("Failed to write script: {}", e))?;
+        .map_err(|e| HelperError::Certificate(format!("Failed to write script: {}", e)))?;
 
     tracing::info!("Running elevated certificate installation");
 
@@ -203,7 +204,7 @@ try {{
         ])
         .creation_flags(CREATE_NO_WINDOW)
         .output()
-        .map_err(|e| format!("Failed to run elevated PowerShell: {}", e))?;
+        .map_err(|e| HelperError::Certificate(format!("Failed to run elevated PowerShell: {}", e)))?;
 
     // Clean up temp file
     let _ = std::fs::remove_file(&script_path);
@@ -214,13 +215,13 @@ try {{
         invalidate_cert_cache();
         Ok(())
     } else {
-        Err("User cancelled or installation failed".to_string())
+        Err(HelperError::Certificate("User cancelled or installation failed".to_string()))
     }
 }
 
 /// Remove certificate from Windows trusted stores
 #[cfg(target_os = "windows")]
-pub fn remove_cert_from_store() -> Result<(), String> {
+pub fn remove_cert_from_store() -> Result<(), HelperError> {
     let ps_script = r#"
 $ErrorActionPreference = 'Stop'
 try {
@@ -242,7 +243,7 @@ try {
         .args(["-ExecutionPolicy", "Bypass", "-NoProfile", "-Command", ps_script])
         .creation_flags(CREATE_NO_WINDOW)
         .output()
-        .map_err(|e| format!("Failed to run PowerShell: {}", e))?;
+        .map_err(|e| HelperError::Certificate(format!("Failed to run PowerShell: {}", e)))?;
 
     let stdout = String::from_utf8_lossy(&output.stdout);
 
@@ -250,29 +251,29 @@ try {
         invalidate_cert_cache();
         Ok(())
     } else {
-        Err(format!("Failed to remove certificate: {}", stdout))
+        Err(HelperError::Certificate(format!("Failed to remove certificate: {}", stdout)))
     }
 }
 
 // Non-Windows stubs
 #[cfg(not(target_os = "windows"))]
-pub fn is_cert_trusted() -> Result<bool, String> {
+pub fn is_cert_trusted() -> Result<bool, HelperError> {
     // On macOS/Linux, we don't need to install the cert to a store
     // The browser will prompt the user to accept it
     Ok(true)
 }
 
 #[cfg(not(target_os = "windows"))]
-pub fn install_cert_current_user() -> Result<(), String> {
-    Err("Certificate store installation is only available on Windows".to_string())
+pub fn install_cert_current_user() -> Result<(), HelperError> {
+    Err(HelperError::Certificate("Certificate store installation is only available on Windows".to_string()))
 }
 
 #[cfg(not(target_os = "windows"))]
-pub fn install_cert_local_machine() -> Result<(), String> {
-    Err("Certificate store installation is only available on Windows".to_string())
+pub fn install_cert_local_machine() -> Result<(), HelperError> {
+    Err(HelperError::Certificate("Certificate store installation is only available on Windows".to_string()))
 }
 
 #[cfg(not(target_os = "windows"))]
-pub fn remove_cert_from_store() -> Result<(), String> {
-    Err("Certificate store management is only available on Windows".to_string())
+pub fn remove_cert_from_store() -> Result<(), HelperError> {
+    Err(HelperError::Certificate("Certificate store management is only available on Windows".to_string()))
 }
@@ -0,0 +1,260 @@
+//! Helper configuration - settings that apply to the whole app rather than to one printer
+//!
+//! Stored as `config.json` in the app data dir, next to the printer profiles. The file is
+//! created with the defaults on first run, read at startup and read again when the server is
+//! restarted from the status window.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// Configuration in effect, loaded from disk on first access
+static CONFIG: Lazy<RwLock<Arc<HelperConfig>>> = Lazy::new(|| RwLock::new(Arc::new(load_config())));
+
+/// How jobs reach CUPS on macOS/Linux
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintBackend {
+    /// Shell out to `lp` / `lpstat` / `lpoptions`
+    #[default]
+    Command,
+    /// Talk IPP to the CUPS scheduler directly (not affected by the system locale)
+    Ipp,
+}
+
+/// Contents of the config file
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HelperConfig {
+    /// Backend used for listing printers, submitting jobs and reading job state (macOS/Linux)
+    #[serde(default)]
+    pub print_backend: PrintBackend,
+    /// CUPS scheduler for the IPP backend: a domain socket path ("/var/run/cups/cups.sock")
+    /// or "host:port". Defaults to `$CUPS_SERVER`, then the local socket, then localhost:631.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cups_server: Option<String>,
+    /// Retrying jobs the spooler or printer didn't accept
+    #[serde(default)]
+    pub retry: RetrySettings,
+    /// Largest documents the server accepts
+    #[serde(default)]
+    pub limits: UploadLimits,
+    /// Handling of the same document sent to the same printer twice in a row
+    #[serde(default)]
+    pub duplicates: DuplicateSettings,
+    /// How many print requests each paired app may send
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
+    /// Addresses and ports the servers listen on
+    #[serde(default)]
+    pub server: ServerSettings,
+}
+
+/// How often, and how patiently, a failed hand-off to a printer is tried again
+/// Each printer (the requested one, then its profile's failover printer) gets `attempts` tries.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetrySettings {
+    /// Tries per printer, including the first; 1 disables retrying
+    pub attempts: u32,
+    /// Wait before the second try, in seconds; doubled for every try after that
+    pub initial_delay_secs: u64,
+    /// Longest wait between two tries, in seconds
+    pub max_delay_secs: u64,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_delay_secs: 2,
+            max_delay_secs: 30,
+        }
+    }
+}
+
+impl RetrySettings {
+    /// Wait after the given failed try (1 = the first)
+    pub fn delay_after(&self, attempt: u32) -> std::time::Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        let secs = self.initial_delay_secs.saturating_mul(factor).min(self.max_delay_secs);
+        std::time::Duration::from_secs(secs)
+    }
+}
+
+/// Size limits for documents sent to the server, in megabytes
+/// Larger uploads are refused with 413 while they are still being received.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct UploadLimits {
+    /// Largest single document (PDF, image or raw data), as received
+    pub max_document_mb: u64,
+    /// Largest batch request, all documents together
+    pub max_batch_mb: u64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self {
+            max_document_mb: 50,
+            max_batch_mb: 200,
+        }
+    }
+}
+
+impl UploadLimits {
+    pub fn document_bytes(&self) -> u64 {
+        self.max_document_mb.saturating_mul(1024 * 1024)
+    }
+
+    pub fn batch_bytes(&self) -> u64 {
+        self.max_batch_mb.saturating_mul(1024 * 1024)
+    }
+}
+
+/// What to do with a print job that repeats one received shortly before
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicateAction {
+    /// Answer as if it printed, without printing it again
+    #[default]
+    Dedupe,
+    /// Refuse it with 409 unless the request sets `force`
+    RequireForce,
+}
+
+/// Spotting the same job sent twice (same document, printer and options)
+/// Repeats are recorded in the job history as suppressed. Only `/print` and `/print/raw` jobs are
+/// checked: a batch is printed whole, so its documents are never dropped as repeats.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DuplicateSettings {
+    /// How long after a job an identical one counts as a repeat, in seconds; 0 disables the check
+    pub window_secs: u64,
+    pub action: DuplicateAction,
+}
+
+impl Default for DuplicateSettings {
+    fn default() -> Self {
+        Self {
+            window_secs: 30,
+            action: DuplicateAction::Dedupe,
+        }
+    }
+}
+
+/// Print requests allowed per paired app, over a sliding window
+/// Requests over the limit are refused with 429.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    /// Requests allowed within the window; 0 disables the limit
+    pub max_requests: u32,
+    /// Length of the window, in seconds
+    pub per_secs: u64,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            max_requests: 30,
+            per_secs: 60,
+        }
+    }
+}
+
+/// Where the HTTPS and HTTP servers listen
+/// The web app looks for the helper on the default ports first, so only change them when
+/// another program needs them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ServerSettings {
+    /// HTTPS server, for Safari
+    pub https: ListenerSettings,
+    /// Plain HTTP server, for Chrome, Firefox and Edge
+    pub http: ListenerSettings,
+    /// How many ports above the configured one to try when it is taken; 0 only tries the configured port
+    pub port_fallback: u16,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            https: ListenerSettings {
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 9847,
+            },
+            http: ListenerSettings {
+                address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port: 9848,
+            },
+            port_fallback: 10,
+        }
+    }
+}
+
+/// Address and port of one server
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ListenerSettings {
+    /// Address to bind; anything but a loopback address makes the helper reachable from the network
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+/// Get the path of the config file
+pub fn get_config_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("anymobile-print-helper")
+        .join("config.json")
+}
+
+/// Load the config file, writing the defaults if there is no file yet
+fn load_config() -> HelperConfig {
+    let path = get_config_path();
+
+    if !path.exists() {
+        let config = HelperConfig::default();
+        let written = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_vec_pretty(&config).unwrap_or_default()));
+        if let Err(e) = written {
+            tracing::warn!("Could not write default config: {}", e);
+        }
+        return config;
+    }
+
+    match fs::read(&path).map_err(|e| e.to_string()).and_then(|data| {
+        serde_json::from_slice::<HelperConfig>(&data).map_err(|e| e.to_string())
+    }) {
+        Ok(config) => {
+            tracing::info!("Loaded config from {:?} (print backend: {:?})", path, config.print_backend);
+            config
+        }
+        Err(e) => {
+            tracing::error!("Invalid config file {:?}, using defaults: {}", path, e);
+            HelperConfig::default()
+        }
+    }
+}
+
+/// Load the config at startup
+pub fn init() {
+    Lazy::force(&CONFIG);
+}
+
+/// Get the configuration in effect
+pub fn get_config() -> Arc<HelperConfig> {
+    CONFIG.read().map(|config| Arc::clone(&config)).unwrap_or_default()
+}
+
+/// Read the config file again; requests already being handled keep the config they started with
+pub fn reload() {
+    let config = Arc::new(load_config());
+    if let Ok(mut current) = CONFIG.write() {
+        *current = config;
+    }
+}
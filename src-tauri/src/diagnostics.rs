@@ -12,7 +12,8 @@ use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::Layer;
 
 use crate::cert_manager;
-use crate::server::{HTTPS_PORT, HTTP_PORT, PrinterInfo};
+use crate::jobs::{self, PrintJob};
+use crate::server::{self, ListenerStatus, PrinterInfo};
 use crate::printer;
 
 /// Maximum number of log entries to keep in memory
@@ -43,6 +44,17 @@ pub enum OverallStatus {
 pub struct DiagnosticStatus {
     pub https_running: bool,
     pub http_running: bool,
+    /// Ports in use, which may be fallbacks for taken ones
+    pub https_port: Option<u16>,
+    pub http_port: Option<u16>,
+    /// Why a server couldn't listen, or stopped
+    pub https_error: Option<String>,
+    pub http_error: Option<String>,
+    /// Requests each server answered since it started
+    pub https_requests: u64,
+    pub http_requests: u64,
+    /// Newest print job received, as it stands now
+    pub last_job: Option<PrintJob>,
     pub cert_exists: bool,
     pub cert_valid: bool,
     pub cert_trusted: bool,
@@ -229,8 +241,9 @@ pub fn get_diagnostic_status(version: String) -> DiagnosticStatus {
         .unwrap_or_default()
         .as_secs();
 
-    let https_running = check_port_listening(HTTPS_PORT);
-    let http_running = check_port_listening(HTTP_PORT);
+    let servers = server::server_status();
+    let https_running = servers.https.is_listening();
+    let http_running = servers.http.is_listening();
 
     let overall_status = if https_running && http_running && cert_valid {
         if cfg!(target_os = "windows") && !cert_trusted {
@@ -247,6 +260,13 @@ pub fn get_diagnostic_status(version: String) -> DiagnosticStatus {
     DiagnosticStatus {
         https_running,
         http_running,
+        https_port: servers.https.port(),
+        http_port: servers.http.port(),
+        https_error: servers.https.error,
+        http_error: servers.http.error,
+        https_requests: servers.https.requests_served,
+        http_requests: servers.http.requests_served,
+        last_job: servers.last_job_id.as_deref().and_then(jobs::get_job),
         cert_exists,
         cert_valid,
         cert_trusted,
@@ -288,8 +308,9 @@ pub fn get_certificate_info() -> CertificateInfo {
 
 /// Test connections to both endpoints
 pub async fn test_connections() -> ConnectionTestResult {
-    let https_result = test_endpoint("https", HTTPS_PORT).await;
-    let http_result = test_endpoint("http", HTTP_PORT).await;
+    let servers = server::server_status();
+    let https_result = test_endpoint(&servers.https).await;
+    let http_result = test_endpoint(&servers.http).await;
 
     // Test localhost resolution
     let localhost_resolves = std::net::ToSocketAddrs::to_socket_addrs("localhost:80")
@@ -322,13 +343,16 @@ pub async fn test_connections() -> ConnectionTestResult {
     }
 }
 
-async fn test_endpoint(protocol: &str, port: u16) -> (bool, Option<u64>) {
+async fn test_endpoint(listener: &ListenerStatus) -> (bool, Option<u64>) {
+    let Some(address) = listener.local_address() else {
+        return (false, None);
+    };
     let start = std::time::Instant::now();
 
     // Simple TCP connection test
     match tokio::time::timeout(
         std::time::Duration::from_secs(2),
-        tokio::net::TcpStream::connect(format!("127.0.0.1:{}", port)),
+        tokio::net::TcpStream::connect(address),
     )
     .await
     {
@@ -352,8 +376,27 @@ pub fn format_diagnostics_for_copy(status: &DiagnosticStatus, printers: &[Printe
     output.push_str(&format!("Uptime: {} seconds\n\n", status.uptime_seconds));
 
     output.push_str("Server Status:\n");
-    output.push_str(&format!("  HTTPS ({}): {}\n", HTTPS_PORT, if status.https_running { "Running" } else { "Stopped" }));
-    output.push_str(&format!("  HTTP ({}): {}\n", HTTP_PORT, if status.http_running { "Running" } else { "Stopped" }));
+    for (name, port, running, requests, error) in [
+        ("HTTPS", status.https_port, status.https_running, status.https_requests, &status.https_error),
+        ("HTTP", status.http_port, status.http_running, status.http_requests, &status.http_error),
+    ] {
+        let port = port.map_or_else(|| "no port".to_string(), |port| port.to_string());
+        let state = if running { "Running" } else { "Stopped" };
+        output.push_str(&format!("  {} ({}): {}, {} requests served\n", name, port, state, requests));
+        if let Some(error) = error {
+            output.push_str(&format!("    {}\n", error));
+        }
+    }
+    match &status.last_job {
+        Some(job) => output.push_str(&format!(
+            "  Last job: {} at {} ({:?}{})\n",
+            job.id,
+            job.created_at,
+            job.status,
+            job.error.as_deref().map(|e| format!(": {}", e)).unwrap_or_default()
+        )),
+        None => output.push_str("  Last job: none\n"),
+    }
 
     output.push_str("\nCertificate:\n");
     output.push_str(&format!("  Path: {}\n", status.cert_path));
@@ -389,12 +432,6 @@ fn validate_cert_files(cert_path: &PathBuf, key_path: &PathBuf) -> bool {
     }
 }
 
-/// Check if a port is being listened on
-fn check_port_listening(port: u16) -> bool {
-    // If we can't bind, something else (our server) is using it
-    std::net::TcpListener::bind(format!("127.0.0.1:{}", port)).is_err()
-}
-
 /// Format SystemTime as a readable string
 fn format_system_time(time: SystemTime) -> String {
     chrono::DateTime::<chrono::Local>::from(time)
@@ -448,11 +485,8 @@ pub fn regenerate_certificate() -> Result<(), String> {
     let _ = fs::remove_file(&cert_path);
     let _ = fs::remove_file(&key_path);
 
-    tracing::info!("Certificate files deleted. New certificate will be generated on next server start.");
-
-    // Note: The server will regenerate the certificate on next request
-    // For immediate regeneration, we'd need to call server::get_or_create_certificate()
-    // but that's private. A restart is the cleanest approach.
+    // server::reload_certificate() creates the new one and hands it to the running server
+    tracing::info!("Certificate files deleted");
 
     Ok(())
 }
@@ -0,0 +1,155 @@
+//! Errors the helper reports to the web app and the status window
+//!
+//! Each error has a stable `code` the web app can branch on, a human readable message, and the
+//! HTTP status it is answered with. Backends mostly produce plain messages internally; they are
+//! classified where a print job moves from one stage to the next. Sending to a printer is the
+//! exception: its backends say whether the printer was unreachable or refused the job, since only
+//! the first is tried again.
+
+use axum::http::StatusCode;
+use serde::Serialize;
+use std::fmt;
+
+/// An error with a stable code, serialized as `{"code": "printer-not-found", "message": "..."}`
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "code", content = "message", rename_all = "kebab-case")]
+pub enum HelperError {
+    /// The request is malformed (missing fields, bad form data or base64)
+    InvalidRequest(String),
+    /// The document can't be read (not a PDF or supported image, corrupt, empty)
+    InvalidDocument(String),
+    /// The document or request is over the configured size limit
+    DocumentTooLarge(String),
+    /// A print option is invalid or not supported by the printer
+    InvalidOptions(String),
+    /// The request has no pairing token, or one that isn't valid (or a wrong pairing code)
+    Unauthorized(String),
+    /// A print request's signature is missing, wrong, too old or already used
+    InvalidSignature(String),
+    /// The request comes from a web page whose origin isn't on the allowlist
+    OriginNotAllowed(String),
+    /// The same document went to the same printer just before; resend with `force` to print it again
+    DuplicateJob(String),
+    /// The paired app sent more print requests than the rate limit allows
+    RateLimited(String),
+    /// No printer or job with that name or ID
+    PrinterNotFound(String),
+    JobNotFound(String),
+    /// The printer or its queue didn't take the job (offline, disabled, out of paper, unreachable)
+    PrinterOffline(String),
+    /// The connection to the printer broke after part of the job was sent; what arrived may
+    /// have printed, so the job isn't sent again
+    PrintInterrupted(String),
+    /// A program the helper needs isn't installed (`lp`, Ghostscript, SumatraPDF)
+    BackendMissing(String),
+    /// Laying out or rasterizing the document failed (imposition, PDF-to-ZPL)
+    RenderFailed(String),
+    /// The job file couldn't be written to the spool directory
+    SpoolFailed(String),
+    /// Creating, trusting or removing the HTTPS certificate failed
+    Certificate(String),
+    /// Anything else
+    Internal(String),
+}
+
+impl HelperError {
+    /// Stable machine-readable code
+    pub fn code(&self) -> &'static str {
+        match self {
+            HelperError::InvalidRequest(_) => "invalid-request",
+            HelperError::InvalidDocument(_) => "invalid-document",
+            HelperError::DocumentTooLarge(_) => "document-too-large",
+            HelperError::InvalidOptions(_) => "invalid-options",
+            HelperError::Unauthorized(_) => "unauthorized",
+            HelperError::InvalidSignature(_) => "invalid-signature",
+            HelperError::OriginNotAllowed(_) => "origin-not-allowed",
+            HelperError::DuplicateJob(_) => "duplicate-job",
+            HelperError::RateLimited(_) => "rate-limited",
+            HelperError::PrinterNotFound(_) => "printer-not-found",
+            HelperError::JobNotFound(_) => "job-not-found",
+            HelperError::PrinterOffline(_) => "printer-offline",
+            HelperError::PrintInterrupted(_) => "print-interrupted",
+            HelperError::BackendMissing(_) => "backend-missing",
+            HelperError::RenderFailed(_) => "render-failed",
+            HelperError::SpoolFailed(_) => "spool-failed",
+            HelperError::Certificate(_) => "certificate",
+            HelperError::Internal(_) => "internal",
+        }
+    }
+
+    /// Human readable message
+    pub fn message(&self) -> &str {
+        match self {
+            HelperError::InvalidRequest(message)
+            | HelperError::InvalidDocument(message)
+            | HelperError::DocumentTooLarge(message)
+            | HelperError::InvalidOptions(message)
+            | HelperError::Unauthorized(message)
+            | HelperError::InvalidSignature(message)
+            | HelperError::OriginNotAllowed(message)
+            | HelperError::DuplicateJob(message)
+            | HelperError::RateLimited(message)
+            | HelperError::PrinterNotFound(message)
+            | HelperError::JobNotFound(message)
+            | HelperError::PrinterOffline(message)
+            | HelperError::PrintInterrupted(message)
+            | HelperError::BackendMissing(message)
+            | HelperError::RenderFailed(message)
+            | HelperError::SpoolFailed(message)
+            | HelperError::Certificate(message)
+            | HelperError::Internal(message) => message,
+        }
+    }
+
+    /// HTTP status the error is answered with
+    pub fn status(&self) -> StatusCode {
+        match self {
+            HelperError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            HelperError::InvalidDocument(_) | HelperError::InvalidOptions(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            HelperError::DocumentTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            HelperError::Unauthorized(_) | HelperError::InvalidSignature(_) => StatusCode::UNAUTHORIZED,
+            HelperError::OriginNotAllowed(_) => StatusCode::FORBIDDEN,
+            HelperError::DuplicateJob(_) => StatusCode::CONFLICT,
+            HelperError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            HelperError::PrinterNotFound(_) | HelperError::JobNotFound(_) => StatusCode::NOT_FOUND,
+            HelperError::PrinterOffline(_) | HelperError::PrintInterrupted(_) | HelperError::BackendMissing(_) => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            HelperError::RenderFailed(_)
+            | HelperError::SpoolFailed(_)
+            | HelperError::Certificate(_)
+            | HelperError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether trying the same hand-off again (or on another printer) might succeed: the printer
+    /// was unreachable or the spool file couldn't be written. Anything else fails the same way again.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, HelperError::PrinterOffline(_) | HelperError::SpoolFailed(_))
+    }
+
+    /// Classify a backend failure: a program that couldn't be started is a missing backend,
+    /// anything else becomes the given kind of error
+    pub fn from_backend(
+        error: Box<dyn std::error::Error + Send + Sync>,
+        otherwise: fn(String) -> HelperError,
+    ) -> HelperError {
+        if let Some(helper_error) = error.downcast_ref::<HelperError>() {
+            return helper_error.clone();
+        }
+        match error.downcast_ref::<std::io::Error>() {
+            Some(io_error) if io_error.kind() == std::io::ErrorKind::NotFound => {
+                HelperError::BackendMissing(format!("Required program not found: {}", io_error))
+            }
+            _ => otherwise(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for HelperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for HelperError {}
@@ -0,0 +1,290 @@
+//! Image print jobs - PNG, JPEG and BMP uploads
+//!
+//! An image is wrapped in a one-page PDF whose page is exactly the image's physical size, so it
+//! prints through the same per-platform paths (and printer profiles) as any other PDF. The size
+//! comes from `widthMm`, then `dpi`, then the resolution stored in the file, then 96 DPI.
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use image::metadata::Orientation;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Write};
+
+/// Resolution assumed when neither the request nor the file gives one
+const DEFAULT_DPI: f64 = 96.0;
+
+const MM_PER_INCH: f64 = 25.4;
+const POINTS_PER_INCH: f64 = 72.0;
+
+/// How big to print an image
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImagePlacement {
+    /// Image resolution, overriding what the file says
+    pub dpi: Option<f64>,
+    /// Printed width in millimetres; the height follows the aspect ratio
+    #[serde(alias = "width_mm")]
+    pub width_mm: Option<f64>,
+}
+
+impl ImagePlacement {
+    /// Apply a multipart form field by name; returns false if the field isn't an image option
+    pub fn set_field(&mut self, name: &str, text: &str) -> Result<bool, String> {
+        let key = name.replace('_', "").to_lowercase();
+        let value = || -> Result<Option<f64>, String> {
+            let text = text.trim();
+            if text.is_empty() {
+                return Ok(None);
+            }
+            text.parse()
+                .map(Some)
+                .map_err(|_| format!("Invalid value '{}' for {}", text, name))
+        };
+        match key.as_str() {
+            "dpi" => self.dpi = value()?,
+            "widthmm" => self.width_mm = value()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Check the values are usable sizes
+    pub fn validate(&self) -> Result<(), String> {
+        if self.dpi.is_some_and(|dpi| !(1.0..=10000.0).contains(&dpi)) {
+            return Err("dpi must be between 1 and 10000".to_string());
+        }
+        if self.width_mm.is_some_and(|width| !(1.0..=2000.0).contains(&width)) {
+            return Err("widthMm must be between 1 and 2000".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Decoded image plus the resolution recorded in the file (horizontal, vertical)
+struct SourceImage {
+    image: DynamicImage,
+    dpi: Option<(f64, f64)>,
+}
+
+/// Wrap a PNG, JPEG or BMP image in a one-page PDF at its physical size
+pub fn image_to_pdf(data: &[u8], placement: &ImagePlacement) -> Result<Vec<u8>, String> {
+    placement.validate()?;
+    let source = decode(data)?;
+
+    let (width_px, height_px) = (source.image.width() as f64, source.image.height() as f64);
+    let (x_dpi, y_dpi) = match (placement.width_mm, placement.dpi, source.dpi) {
+        (Some(width_mm), _, _) => {
+            let dpi = width_px / (width_mm / MM_PER_INCH);
+            (dpi, dpi)
+        }
+        (None, Some(dpi), _) => (dpi, dpi),
+        (None, None, Some(dpi)) => dpi,
+        (None, None, None) => (DEFAULT_DPI, DEFAULT_DPI),
+    };
+    let page_width = width_px / x_dpi * POINTS_PER_INCH;
+    let page_height = height_px / y_dpi * POINTS_PER_INCH;
+
+    tracing::info!(
+        "Image {}x{} px printed at {:.1}x{:.1} mm",
+        width_px,
+        height_px,
+        page_width / POINTS_PER_INCH * MM_PER_INCH,
+        page_height / POINTS_PER_INCH * MM_PER_INCH
+    );
+
+    write_pdf(&source.image, page_width, page_height)
+}
+
+/// Check the start of an upload is a PNG, JPEG or BMP image
+pub fn check_format(head: &[u8]) -> Result<ImageFormat, String> {
+    match image::guess_format(head).map_err(|_| "Unrecognized image data".to_string())? {
+        format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Bmp) => Ok(format),
+        other => Err(format!("Unsupported image format {:?} (PNG, JPEG or BMP expected)", other)),
+    }
+}
+
+/// Decode the image, applying EXIF rotation (phone photos), and read its stored resolution
+fn decode(data: &[u8]) -> Result<SourceImage, String> {
+    let format = check_format(data)?;
+    let dpi = match format {
+        ImageFormat::Png => png_dpi(data),
+        ImageFormat::Jpeg => jpeg_dpi(data),
+        _ => bmp_dpi(data),
+    };
+
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format)
+        .into_decoder()
+        .map_err(|e| format!("Could not read image: {}", e))?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let mut image =
+        DynamicImage::from_decoder(decoder).map_err(|e| format!("Could not read image: {}", e))?;
+    image.apply_orientation(orientation);
+
+    // A quarter turn swaps which way the horizontal and vertical resolutions run
+    let turned = matches!(
+        orientation,
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH
+    );
+    let dpi = dpi.map(|(x, y)| if turned { (y, x) } else { (x, y) });
+
+    Ok(SourceImage { image, dpi })
+}
+
+/// Convert a pixels-per-metre pair to DPI, ignoring unset values
+fn dpi_from_ppm(x: u32, y: u32) -> Option<(f64, f64)> {
+    (x > 0 && y > 0).then_some((x as f64 * 0.0254, y as f64 * 0.0254))
+}
+
+/// Resolution from a PNG `pHYs` chunk (only when the unit is metres)
+fn png_dpi(data: &[u8]) -> Option<(f64, f64)> {
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data.get(pos + 8..pos + 8 + length)?;
+        match kind {
+            b"pHYs" if length >= 9 && body[8] == 1 => {
+                let x = u32::from_be_bytes(body[0..4].try_into().ok()?);
+                let y = u32::from_be_bytes(body[4..8].try_into().ok()?);
+                return dpi_from_ppm(x, y);
+            }
+            b"IDAT" | b"IEND" => return None,
+            _ => {}
+        }
+        // Length, type, data and CRC
+        pos += 12 + length;
+    }
+    None
+}
+
+/// Resolution from a JPEG JFIF header (dots per inch or per centimetre)
+fn jpeg_dpi(data: &[u8]) -> Option<(f64, f64)> {
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let body = data.get(pos + 4..pos + 2 + length)?;
+        if marker == 0xE0 && body.len() >= 12 && body.starts_with(b"JFIF\0") {
+            let x = u16::from_be_bytes([body[8], body[9]]) as f64;
+            let y = u16::from_be_bytes([body[10], body[11]]) as f64;
+            return match body[7] {
+                1 if x > 0.0 && y > 0.0 => Some((x, y)),
+                2 if x > 0.0 && y > 0.0 => Some((x * 2.54, y * 2.54)),
+                // Unit 0 is only an aspect ratio
+                _ => None,
+            };
+        }
+        // Start of scan - no more headers
+        if marker == 0xDA {
+            return None;
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// Resolution from a BMP info header
+fn bmp_dpi(data: &[u8]) -> Option<(f64, f64)> {
+    let header_size = u32::from_le_bytes(data.get(14..18)?.try_into().ok()?);
+    if header_size < 40 {
+        return None;
+    }
+    let x = i32::from_le_bytes(data.get(38..42)?.try_into().ok()?);
+    let y = i32::from_le_bytes(data.get(42..46)?.try_into().ok()?);
+    dpi_from_ppm(x.max(0) as u32, y.max(0) as u32)
+}
+
+/// Write a single-page PDF with the image filling a page of the given size (in points)
+fn write_pdf(image: &DynamicImage, page_width: f64, page_height: f64) -> Result<Vec<u8>, String> {
+    // Transparent areas print as paper white
+    let (color_space, pixels) = if image.color().has_color() {
+        ("DeviceRGB", flatten_alpha(image).to_rgb8().into_raw())
+    } else {
+        ("DeviceGray", flatten_alpha(image).to_luma8().into_raw())
+    };
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&pixels)
+        .and_then(|_| encoder.flush())
+        .map_err(|e| format!("Could not compress image: {}", e))?;
+    let image_data = encoder.finish().map_err(|e| format!("Could not compress image: {}", e))?;
+
+    let content = format!(
+        "q {:.3} 0 0 {:.3} 0 0 cm /Im0 Do Q\n",
+        page_width, page_height
+    );
+
+    let objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.3} {:.3}] \
+             /Resources << /XObject << /Im0 5 0 R >> >> /Contents 4 0 R >>",
+            page_width, page_height
+        )
+        .into_bytes(),
+        stream(&format!("<< /Length {} >>", content.len()), content.as_bytes()),
+        stream(
+            &format!(
+                "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /{} \
+                 /BitsPerComponent 8 /Filter /FlateDecode /Length {} >>",
+                image.width(),
+                image.height(),
+                color_space,
+                image_data.len()
+            ),
+            &image_data,
+        ),
+    ];
+
+    let mut pdf = b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+
+    let xref_offset = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref_offset
+        )
+        .as_bytes(),
+    );
+
+    Ok(pdf)
+}
+
+/// PDF stream object from a dictionary and its data
+fn stream(dictionary: &str, data: &[u8]) -> Vec<u8> {
+    let mut object = format!("{}\nstream\n", dictionary).into_bytes();
+    object.extend_from_slice(data);
+    object.extend_from_slice(b"\nendstream");
+    object
+}
+
+/// Composite any alpha channel onto white
+fn flatten_alpha(image: &DynamicImage) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image.clone();
+    }
+    let mut rgba = image.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let alpha = pixel.0[3] as u32;
+        for channel in &mut pixel.0[..3] {
+            *channel = ((*channel as u32 * alpha + 255 * (255 - alpha)) / 255) as u8;
+        }
+        pixel.0[3] = 255;
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
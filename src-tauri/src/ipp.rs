@@ -0,0 +1,1049 @@
+//! Minimal IPP/1.1 client (RFC 8010/8011) for talking to CUPS and IPP printers without `lp`
+//!
+//! Requests are encoded by hand and sent as an HTTP/1.1 POST over TCP or the CUPS domain
+//! socket, or straight to a network printer's `ipp://` URI. Only the operations and value
+//! types the helper needs are supported. All calls block, so async code should run them
+//! with `spawn_blocking`.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+use crate::config;
+use crate::error::HelperError;
+
+// Operation IDs
+pub const PRINT_JOB: u16 = 0x0002;
+pub const GET_JOB_ATTRIBUTES: u16 = 0x0009;
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub const CUPS_GET_DEFAULT: u16 = 0x4001;
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub const CUPS_GET_PRINTERS: u16 = 0x4002;
+
+// Delimiter tags
+pub const OPERATION_GROUP: u8 = 0x01;
+pub const JOB_GROUP: u8 = 0x02;
+const END_OF_ATTRIBUTES: u8 = 0x03;
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub const PRINTER_GROUP: u8 = 0x04;
+
+// Value tags
+const TAG_INTEGER: u8 = 0x21;
+const TAG_BOOLEAN: u8 = 0x22;
+const TAG_ENUM: u8 = 0x23;
+const TAG_RESOLUTION: u8 = 0x32;
+const TAG_RANGE: u8 = 0x33;
+const TAG_BEGIN_COLLECTION: u8 = 0x34;
+const TAG_END_COLLECTION: u8 = 0x37;
+const TAG_NAME: u8 = 0x42;
+const TAG_KEYWORD: u8 = 0x44;
+const TAG_URI: u8 = 0x45;
+const TAG_CHARSET: u8 = 0x47;
+const TAG_LANGUAGE: u8 = 0x48;
+const TAG_MIME_TYPE: u8 = 0x49;
+const TAG_MEMBER_NAME: u8 = 0x4A;
+
+/// Status codes below this are successful (RFC 8011 section 5.4.15)
+const STATUS_ERROR_MIN: u16 = 0x0400;
+
+/// Server errors start here; below it (0x04xx) the server refused the request itself
+const STATUS_SERVER_ERROR_MIN: u16 = 0x0500;
+
+/// Status when the printer or job does not exist
+pub const STATUS_NOT_FOUND: u16 = 0x0406;
+
+/// Server errors for an operation or IPP version the server doesn't support at all
+const STATUS_OPERATION_NOT_SUPPORTED: u16 = 0x0501;
+const STATUS_VERSION_NOT_SUPPORTED: u16 = 0x0503;
+
+/// How long to wait for the scheduler / printer before giving up
+const IO_TIMEOUT_SECS: u64 = 60;
+
+/// Default CUPS domain socket
+#[cfg(unix)]
+const CUPS_SOCKET: &str = "/var/run/cups/cups.sock";
+
+/// A single attribute value
+#[derive(Clone, Debug)]
+pub enum IppValue {
+    Integer(i32),
+    Boolean(bool),
+    Enum(i32),
+    Range(i32, i32),
+    /// Cross-feed, feed, units (3 = dots per inch)
+    Resolution(i32, i32, u8),
+    /// Any string-valued type (keyword, name, text, uri, charset, ...) with its tag
+    String(u8, String),
+    /// Member attributes of a collection such as `media-col` (encode only)
+    Collection(Vec<IppAttribute>),
+    /// Types the helper doesn't interpret (dates, collections, out-of-band values)
+    Other(u8, Vec<u8>),
+}
+
+impl IppValue {
+    pub fn keyword(value: impl Into<String>) -> Self {
+        IppValue::String(TAG_KEYWORD, value.into())
+    }
+
+    pub fn name(value: impl Into<String>) -> Self {
+        IppValue::String(TAG_NAME, value.into())
+    }
+
+    pub fn uri(value: impl Into<String>) -> Self {
+        IppValue::String(TAG_URI, value.into())
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            IppValue::String(_, s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i32> {
+        match self {
+            IppValue::Integer(n) | IppValue::Enum(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            IppValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            IppValue::Integer(_) => TAG_INTEGER,
+            IppValue::Boolean(_) => TAG_BOOLEAN,
+            IppValue::Enum(_) => TAG_ENUM,
+            IppValue::Range(..) => TAG_RANGE,
+            IppValue::Resolution(..) => TAG_RESOLUTION,
+            IppValue::Collection(_) => TAG_BEGIN_COLLECTION,
+            IppValue::String(tag, _) | IppValue::Other(tag, _) => *tag,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            IppValue::Integer(n) | IppValue::Enum(n) => n.to_be_bytes().to_vec(),
+            IppValue::Boolean(b) => vec![*b as u8],
+            IppValue::Range(lower, upper) => [lower.to_be_bytes(), upper.to_be_bytes()].concat(),
+            IppValue::Resolution(x, y, units) => {
+                let mut bytes = [x.to_be_bytes(), y.to_be_bytes()].concat();
+                bytes.push(*units);
+                bytes
+            }
+            IppValue::String(_, s) => s.as_bytes().to_vec(),
+            IppValue::Other(_, bytes) => bytes.clone(),
+            // Members follow the begCollection value, see `write_value`
+            IppValue::Collection(_) => vec![],
+        }
+    }
+
+    fn decode(tag: u8, bytes: &[u8]) -> Self {
+        let int_at = |offset: usize| -> Option<i32> {
+            Some(i32::from_be_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+        };
+
+        let value = match tag {
+            TAG_INTEGER => int_at(0).map(IppValue::Integer),
+            TAG_ENUM => int_at(0).map(IppValue::Enum),
+            TAG_BOOLEAN => bytes.first().map(|b| IppValue::Boolean(*b != 0)),
+            TAG_RANGE => int_at(0).zip(int_at(4)).map(|(a, b)| IppValue::Range(a, b)),
+            TAG_RESOLUTION => int_at(0)
+                .zip(int_at(4))
+                .zip(bytes.get(8))
+                .map(|((x, y), units)| IppValue::Resolution(x, y, *units)),
+            0x41..=0x49 => Some(IppValue::String(tag, String::from_utf8_lossy(bytes).to_string())),
+            _ => None,
+        };
+
+        value.unwrap_or_else(|| IppValue::Other(tag, bytes.to_vec()))
+    }
+}
+
+/// A named attribute with one or more values
+#[derive(Clone, Debug)]
+pub struct IppAttribute {
+    pub name: String,
+    pub values: Vec<IppValue>,
+}
+
+impl IppAttribute {
+    pub fn new(name: impl Into<String>, value: IppValue) -> Self {
+        Self {
+            name: name.into(),
+            values: vec![value],
+        }
+    }
+
+    pub fn value(&self) -> Option<&IppValue> {
+        self.values.first()
+    }
+}
+
+/// An attribute group (operation, job, printer, ...)
+#[derive(Clone, Debug)]
+pub struct IppGroup {
+    pub tag: u8,
+    pub attributes: Vec<IppAttribute>,
+}
+
+impl IppGroup {
+    pub fn get(&self, name: &str) -> Option<&IppAttribute> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+}
+
+/// An IPP request
+pub struct IppRequest {
+    operation: u16,
+    groups: Vec<IppGroup>,
+    document: Vec<u8>,
+}
+
+impl IppRequest {
+    /// Start a request with the required operation attributes
+    pub fn new(operation: u16, printer_uri: Option<&str>) -> Self {
+        let mut request = Self {
+            operation,
+            groups: vec![IppGroup { tag: OPERATION_GROUP, attributes: vec![] }],
+            document: vec![],
+        };
+        request.operation_attr("attributes-charset", IppValue::String(TAG_CHARSET, "utf-8".to_string()));
+        request.operation_attr("attributes-natural-language", IppValue::String(TAG_LANGUAGE, "en".to_string()));
+        if let Some(uri) = printer_uri {
+            request.operation_attr("printer-uri", IppValue::uri(uri));
+        }
+        request
+    }
+
+    pub fn operation_attr(&mut self, name: &str, value: IppValue) -> &mut Self {
+        self.groups[0].attributes.push(IppAttribute::new(name, value));
+        self
+    }
+
+    /// Add `requested-attributes` so the response only carries what we need
+    pub fn requested_attributes(&mut self, names: &[&str]) -> &mut Self {
+        self.groups[0].attributes.push(IppAttribute {
+            name: "requested-attributes".to_string(),
+            values: names.iter().map(|n| IppValue::keyword(*n)).collect(),
+        });
+        self
+    }
+
+    pub fn job_attributes(&mut self, attributes: Vec<IppAttribute>) -> &mut Self {
+        if !attributes.is_empty() {
+            self.groups.push(IppGroup { tag: JOB_GROUP, attributes });
+        }
+        self
+    }
+
+    pub fn document(&mut self, data: Vec<u8>) -> &mut Self {
+        self.document = data;
+        self
+    }
+
+    pub fn encode(&self, request_id: u32) -> Vec<u8> {
+        let mut out = vec![1, 1];
+        out.extend(self.operation.to_be_bytes());
+        out.extend(request_id.to_be_bytes());
+
+        for group in &self.groups {
+            out.push(group.tag);
+            for attribute in &group.attributes {
+                write_attribute(&mut out, attribute);
+            }
+        }
+
+        out.push(END_OF_ATTRIBUTES);
+        out.extend(&self.document);
+        out
+    }
+}
+
+/// Encode an attribute; additional values repeat the tag with an empty name
+fn write_attribute(out: &mut Vec<u8>, attribute: &IppAttribute) {
+    for (i, value) in attribute.values.iter().enumerate() {
+        let name = if i == 0 { attribute.name.as_str() } else { "" };
+        write_value(out, name, value);
+    }
+}
+
+/// Encode one value; collections are followed by memberAttrName/value pairs and endCollection
+fn write_value(out: &mut Vec<u8>, name: &str, value: &IppValue) {
+    let write = |out: &mut Vec<u8>, tag: u8, name: &[u8], bytes: &[u8]| {
+        out.push(tag);
+        out.extend((name.len() as u16).to_be_bytes());
+        out.extend(name);
+        out.extend((bytes.len() as u16).to_be_bytes());
+        out.extend(bytes);
+    };
+
+    write(out, value.tag(), name.as_bytes(), &value.encode());
+
+    if let IppValue::Collection(members) = value {
+        for member in members {
+            write(out, TAG_MEMBER_NAME, &[], member.name.as_bytes());
+            for member_value in &member.values {
+                write_value(out, "", member_value);
+            }
+        }
+        write(out, TAG_END_COLLECTION, &[], &[]);
+    }
+}
+
+/// A decoded IPP response
+#[derive(Clone, Debug)]
+pub struct IppResponse {
+    pub status: u16,
+    pub groups: Vec<IppGroup>,
+}
+
+impl IppResponse {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let truncated = || "Truncated IPP response".to_string();
+        let u16_at = |offset: usize| -> Result<u16, String> {
+            let bytes = data.get(offset..offset + 2).ok_or_else(truncated)?;
+            Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+
+        let status = u16_at(2)?;
+        let mut groups: Vec<IppGroup> = Vec::new();
+        let mut pos = 8;
+
+        loop {
+            let tag = *data.get(pos).ok_or_else(truncated)?;
+            pos += 1;
+
+            if tag == END_OF_ATTRIBUTES {
+                break;
+            }
+            if tag < 0x10 {
+                groups.push(IppGroup { tag, attributes: vec![] });
+                continue;
+            }
+
+            let name_len = u16_at(pos)? as usize;
+            let name = data.get(pos + 2..pos + 2 + name_len).ok_or_else(truncated)?;
+            pos += 2 + name_len;
+            let value_len = u16_at(pos)? as usize;
+            let bytes = data.get(pos + 2..pos + 2 + value_len).ok_or_else(truncated)?;
+            pos += 2 + value_len;
+
+            let group = groups.last_mut().ok_or("IPP attribute outside a group")?;
+            let value = IppValue::decode(tag, bytes);
+            if name.is_empty() {
+                if let Some(attribute) = group.attributes.last_mut() {
+                    attribute.values.push(value);
+                }
+            } else {
+                group
+                    .attributes
+                    .push(IppAttribute::new(String::from_utf8_lossy(name).to_string(), value));
+            }
+        }
+
+        Ok(Self { status, groups })
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.status < STATUS_ERROR_MIN
+    }
+
+    /// Groups with the given tag (e.g. one printer group per printer for CUPS-Get-Printers)
+    pub fn groups(&self, tag: u8) -> impl Iterator<Item = &IppGroup> {
+        self.groups.iter().filter(move |g| g.tag == tag)
+    }
+
+    /// First attribute with this name in a group with the given tag
+    pub fn get(&self, tag: u8, name: &str) -> Option<&IppAttribute> {
+        self.groups(tag).find_map(|g| g.get(name))
+    }
+
+    /// Error description for a failed request
+    pub fn error_message(&self) -> String {
+        let message = self
+            .get(OPERATION_GROUP, "status-message")
+            .and_then(|a| a.value())
+            .and_then(|v| v.as_str())
+            .unwrap_or("no details");
+        format!("IPP error 0x{:04x}: {}", self.status, message)
+    }
+}
+
+/// Where to send IPP requests
+#[derive(Clone, Debug)]
+pub enum IppConnection {
+    Tcp { host: String, port: u16 },
+    #[cfg(unix)]
+    Socket(PathBuf),
+}
+
+impl IppConnection {
+    /// Connection to the CUPS scheduler from the config, `$CUPS_SERVER`, or the usual defaults
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    pub fn cups() -> Self {
+        let configured = config::get_config()
+            .cups_server
+            .clone()
+            .or_else(|| std::env::var("CUPS_SERVER").ok())
+            .filter(|s| !s.trim().is_empty());
+
+        match configured {
+            Some(server) => Self::parse(&server),
+            None => {
+                #[cfg(unix)]
+                if std::path::Path::new(CUPS_SOCKET).exists() {
+                    return IppConnection::Socket(PathBuf::from(CUPS_SOCKET));
+                }
+                IppConnection::Tcp { host: "localhost".to_string(), port: 631 }
+            }
+        }
+    }
+
+    /// Parse "host", "host:port" or (on Unix) an absolute socket path
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    pub fn parse(server: &str) -> Self {
+        let server = server.trim();
+
+        #[cfg(unix)]
+        if server.starts_with('/') {
+            return IppConnection::Socket(PathBuf::from(server));
+        }
+
+        match server.rsplit_once(':') {
+            Some((host, port)) if port.parse::<u16>().is_ok() && !host.ends_with(':') => IppConnection::Tcp {
+                host: host.trim_matches(['[', ']']).to_string(),
+                port: port.parse().unwrap_or(631),
+            },
+            _ => IppConnection::Tcp { host: server.to_string(), port: 631 },
+        }
+    }
+
+    /// Host name to use in `ipp://` URIs and the HTTP Host header
+    fn host_header(&self) -> String {
+        match self {
+            IppConnection::Tcp { host, port } if host.contains(':') => format!("[{}]:{}", host, port),
+            IppConnection::Tcp { host, port } => format!("{}:{}", host, port),
+            #[cfg(unix)]
+            IppConnection::Socket(_) => "localhost".to_string(),
+        }
+    }
+
+    /// Send a request to `resource` (e.g. "/printers/Zebra") and decode the response
+    pub fn send(&self, resource: &str, request: &IppRequest) -> Result<IppResponse, String> {
+        let body = request.encode(1);
+        let mut http = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/ipp\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            resource,
+            self.host_header(),
+            body.len()
+        )
+        .into_bytes();
+        http.extend(body);
+
+        let timeout = Some(Duration::from_secs(IO_TIMEOUT_SECS));
+        let raw = match self {
+            IppConnection::Tcp { host, port } => {
+                let mut stream = TcpStream::connect((host.as_str(), *port))
+                    .map_err(|e| format!("Could not connect to IPP server {}:{}: {}", host, port, e))?;
+                stream.set_read_timeout(timeout).ok();
+                stream.set_write_timeout(timeout).ok();
+                exchange(&mut stream, &http)?
+            }
+            #[cfg(unix)]
+            IppConnection::Socket(path) => {
+                let mut stream = std::os::unix::net::UnixStream::connect(path)
+                    .map_err(|e| format!("Could not connect to CUPS socket {:?}: {}", path, e))?;
+                stream.set_read_timeout(timeout).ok();
+                stream.set_write_timeout(timeout).ok();
+                exchange(&mut stream, &http)?
+            }
+        };
+
+        IppResponse::parse(&http_body(&raw)?)
+    }
+}
+
+/// Write the request and read the whole response (the server closes the connection)
+fn exchange(stream: &mut (impl Read + Write), request: &[u8]) -> Result<Vec<u8>, String> {
+    stream
+        .write_all(request)
+        .map_err(|e| format!("Failed to send IPP request: {}", e))?;
+    let mut response = Vec::new();
+    stream
+        .read_to_end(&mut response)
+        .map_err(|e| format!("Failed to read IPP response: {}", e))?;
+    Ok(response)
+}
+
+/// Check the HTTP status and return the (de-chunked) body
+fn http_body(raw: &[u8]) -> Result<Vec<u8>, String> {
+    let header_end = raw
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or("Malformed HTTP response from IPP server")?;
+    let head = String::from_utf8_lossy(&raw[..header_end]);
+    let body = &raw[header_end + 4..];
+
+    let status_line = head.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    match status {
+        "200" => {}
+        "401" | "403" => return Err(format!("IPP server refused the request ({})", status_line)),
+        _ => return Err(format!("Unexpected HTTP response from IPP server: {}", status_line)),
+    }
+
+    let chunked = head.lines().any(|line| {
+        let line = line.to_ascii_lowercase();
+        line.starts_with("transfer-encoding:") && line.contains("chunked")
+    });
+    if !chunked {
+        return Ok(body.to_vec());
+    }
+
+    let mut out = Vec::new();
+    let mut rest = body;
+    loop {
+        let line_end = rest
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or("Malformed chunked IPP response")?;
+        let size_text = String::from_utf8_lossy(&rest[..line_end]);
+        let size = usize::from_str_radix(size_text.split(';').next().unwrap_or("").trim(), 16)
+            .map_err(|_| "Malformed chunk size in IPP response")?;
+        rest = &rest[line_end + 2..];
+        if size == 0 {
+            return Ok(out);
+        }
+        out.extend(rest.get(..size).ok_or("Truncated chunked IPP response")?);
+        rest = rest.get(size + 2..).unwrap_or_default();
+    }
+}
+
+/// Turn `lp -o key=value` style options into IPP job attributes, typed the way `cupsEncodeOptions` does
+/// Unknown options (PPD options such as `MediaType` or `EPIJ_Qual`) are sent as names; CUPS maps them to the driver.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub fn job_attributes_from_options(options: &[String]) -> Vec<IppAttribute> {
+    options
+        .iter()
+        .filter_map(|option| option.split_once('='))
+        .map(|(name, value)| {
+            let values = match name {
+                "orientation-requested" | "print-quality" => {
+                    value.parse().ok().map(|n| vec![IppValue::Enum(n)])
+                }
+                "page-ranges" => crate::print_options::parse_page_ranges(value).ok().map(|ranges| {
+                    ranges
+                        .into_iter()
+                        .map(|(first, last)| IppValue::Range(first as i32, last as i32))
+                        .collect()
+                }),
+                "media" | "sides" | "print-color-mode" | "print-scaling" => {
+                    Some(vec![IppValue::keyword(value)])
+                }
+                _ => None,
+            };
+
+            let values = values.unwrap_or_else(|| {
+                let value = if value.eq_ignore_ascii_case("true") || value.eq_ignore_ascii_case("false") {
+                    IppValue::Boolean(value.eq_ignore_ascii_case("true"))
+                } else if let Ok(n) = value.parse::<i32>() {
+                    IppValue::Integer(n)
+                } else {
+                    IppValue::name(value)
+                };
+                vec![value]
+            });
+
+            IppAttribute {
+                name: name.to_string(),
+                values,
+            }
+        })
+        .collect()
+}
+
+/// A queue reported by CUPS
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+#[derive(Clone, Debug)]
+pub struct CupsPrinter {
+    pub name: String,
+    /// printer-state: 3 = idle, 4 = processing, 5 = stopped
+    pub state: i32,
+    pub accepting_jobs: bool,
+}
+
+/// Client for the local CUPS scheduler
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub struct CupsClient {
+    connection: IppConnection,
+}
+
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+impl CupsClient {
+    pub fn new() -> Self {
+        Self {
+            connection: IppConnection::cups(),
+        }
+    }
+
+    /// `ipp://` URI CUPS expects for one of its queues
+    fn printer_uri(&self, printer: &str) -> String {
+        format!("ipp://{}/printers/{}", self.connection.host_header(), printer)
+    }
+
+    /// All queues (CUPS-Get-Printers)
+    pub fn printers(&self) -> Result<Vec<CupsPrinter>, String> {
+        let mut request = IppRequest::new(CUPS_GET_PRINTERS, None);
+        request.requested_attributes(&["printer-name", "printer-state", "printer-is-accepting-jobs"]);
+
+        let response = match self.connection.send("/", &request)? {
+            // CUPS answers "not found" when there are no printers at all
+            r if r.status == STATUS_NOT_FOUND => return Ok(vec![]),
+            r if !r.is_success() => return Err(r.error_message()),
+            r => r,
+        };
+
+        Ok(response
+            .groups(PRINTER_GROUP)
+            .filter_map(|group| {
+                Some(CupsPrinter {
+                    name: group.get("printer-name")?.value()?.as_str()?.to_string(),
+                    state: group.get("printer-state").and_then(|a| a.value()?.as_int()).unwrap_or(0),
+                    accepting_jobs: group
+                        .get("printer-is-accepting-jobs")
+                        .and_then(|a| a.value()?.as_bool())
+                        .unwrap_or(true),
+                })
+            })
+            .collect())
+    }
+
+    /// The default queue (CUPS-Get-Default), if one is set
+    pub fn default_printer(&self) -> Result<Option<String>, String> {
+        let mut request = IppRequest::new(CUPS_GET_DEFAULT, None);
+        request.requested_attributes(&["printer-name"]);
+
+        let response = self.connection.send("/", &request)?;
+        if response.status == STATUS_NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.is_success() {
+            return Err(response.error_message());
+        }
+
+        Ok(response
+            .get(PRINTER_GROUP, "printer-name")
+            .and_then(|a| a.value()?.as_str())
+            .map(str::to_string))
+    }
+
+    /// Submit a document (Print-Job) and return the CUPS job number
+    pub fn print_job(
+        &self,
+        printer: &str,
+        job_name: &str,
+        document_format: &str,
+        document: Vec<u8>,
+        attributes: Vec<IppAttribute>,
+    ) -> Result<i32, HelperError> {
+        print_job(
+            &self.connection,
+            &format!("/printers/{}", printer),
+            &self.printer_uri(printer),
+            job_name,
+            document_format,
+            document,
+            attributes,
+        )
+    }
+
+    /// Current job-state of a job (Get-Job-Attributes), or None if CUPS doesn't know it
+    pub fn job_state(&self, printer: &str, job_id: i32) -> Result<Option<i32>, String> {
+        job_state(&self.connection, &format!("/printers/{}", printer), &self.printer_uri(printer), job_id)
+    }
+}
+
+/// A network printer addressed by its `ipp://host[:port]/path` URI
+#[derive(Clone, Debug)]
+pub struct PrinterUri {
+    pub uri: String,
+    connection: IppConnection,
+    resource: String,
+}
+
+/// Whether a `printer` value is an IPP URI rather than a queue name
+pub fn is_printer_uri(printer: &str) -> bool {
+    let lower = printer.trim().to_ascii_lowercase();
+    lower.starts_with("ipp://") || lower.starts_with("ipps://")
+}
+
+impl PrinterUri {
+    pub fn parse(uri: &str) -> Result<Self, String> {
+        let uri = uri.trim();
+        let (scheme, rest) = uri
+            .split_once("://")
+            .ok_or_else(|| format!("Invalid printer URI '{}'", uri))?;
+
+        if scheme.eq_ignore_ascii_case("ipps") {
+            return Err(format!("Printer URI '{}': ipps:// (IPP over TLS) is not supported, use ipp://", uri));
+        }
+        if !scheme.eq_ignore_ascii_case("ipp") {
+            return Err(format!("Invalid printer URI '{}' (expected ipp://host/ipp/print)", uri));
+        }
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/ipp/print"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => (
+                host,
+                port.parse::<u16>()
+                    .map_err(|_| format!("Invalid port in printer URI '{}'", uri))?,
+            ),
+            _ => (authority, 631),
+        };
+        let host = host.trim_matches(['[', ']']);
+        if host.is_empty() {
+            return Err(format!("Printer URI '{}' has no host", uri));
+        }
+
+        Ok(Self {
+            uri: uri.to_string(),
+            connection: IppConnection::Tcp { host: host.to_string(), port },
+            resource: path.to_string(),
+        })
+    }
+
+    /// Submit a document (Print-Job) and return the printer's job-id
+    pub fn print_job(
+        &self,
+        job_name: &str,
+        document_format: &str,
+        document: Vec<u8>,
+        attributes: Vec<IppAttribute>,
+    ) -> Result<i32, HelperError> {
+        print_job(&self.connection, &self.resource, &self.uri, job_name, document_format, document, attributes)
+    }
+
+    /// Current job-state of a job, or None if the printer no longer knows it
+    pub fn job_state(&self, job_id: i32) -> Result<Option<i32>, String> {
+        job_state(&self.connection, &self.resource, &self.uri, job_id)
+    }
+}
+
+/// Print-Job against any IPP printer; returns the job-id it assigned
+/// A server that can't be reached, or is busy or stopped, is reported as offline; a refused
+/// request (unknown printer, unsupported attribute or format) won't succeed on a retry.
+pub fn print_job(
+    connection: &IppConnection,
+    resource: &str,
+    printer_uri: &str,
+    job_name: &str,
+    document_format: &str,
+    document: Vec<u8>,
+    attributes: Vec<IppAttribute>,
+) -> Result<i32, HelperError> {
+    let mut request = IppRequest::new(PRINT_JOB, Some(printer_uri));
+    request
+        .operation_attr("requesting-user-name", IppValue::name(requesting_user_name()))
+        .operation_attr("job-name", IppValue::name(job_name))
+        .operation_attr("document-format", IppValue::String(TAG_MIME_TYPE, document_format.to_string()))
+        .job_attributes(attributes)
+        .document(document);
+
+    let response = connection.send(resource, &request).map_err(HelperError::PrinterOffline)?;
+    if !response.is_success() {
+        return Err(print_job_error(&response));
+    }
+
+    // Not worth a retry either: the job is in, so sending it again would print it twice
+    response
+        .get(JOB_GROUP, "job-id")
+        .and_then(|a| a.value()?.as_int())
+        .ok_or_else(|| HelperError::Internal("IPP server accepted the job but returned no job-id".to_string()))
+}
+
+/// Classify a failed Print-Job by its status
+fn print_job_error(response: &IppResponse) -> HelperError {
+    let message = response.error_message();
+    match response.status {
+        STATUS_NOT_FOUND => HelperError::PrinterNotFound(message),
+        STATUS_OPERATION_NOT_SUPPORTED | STATUS_VERSION_NOT_SUPPORTED => HelperError::Internal(message),
+        status if status < STATUS_SERVER_ERROR_MIN => HelperError::InvalidOptions(message),
+        // Busy, not accepting jobs, device or temporary errors
+        _ => HelperError::PrinterOffline(message),
+    }
+}
+
+/// Get-Job-Attributes job-state (3 pending .. 9 completed), or None if the job is unknown
+pub fn job_state(
+    connection: &IppConnection,
+    resource: &str,
+    printer_uri: &str,
+    job_id: i32,
+) -> Result<Option<i32>, String> {
+    let mut request = IppRequest::new(GET_JOB_ATTRIBUTES, Some(printer_uri));
+    request
+        .operation_attr("job-id", IppValue::Integer(job_id))
+        .operation_attr("requesting-user-name", IppValue::name(requesting_user_name()))
+        .requested_attributes(&["job-state"]);
+
+    let response = connection.send(resource, &request)?;
+    if response.status == STATUS_NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.is_success() {
+        return Err(response.error_message());
+    }
+
+    Ok(response.get(JOB_GROUP, "job-state").and_then(|a| a.value()?.as_int()))
+}
+
+/// User name to submit jobs as (CUPS shows it in the queue)
+fn requesting_user_name() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "anymobile-print-helper".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    /// What the stand-in server received: the HTTP resource, the decoded request and its raw body
+    type Received = (String, IppResponse, Vec<u8>);
+
+    /// Answer one IPP request on a loopback port with the response `respond` builds
+    fn serve_once(respond: impl FnOnce() -> Vec<u8> + Send + 'static) -> (IppConnection, JoinHandle<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let resource = request_line.split_whitespace().nth(1).unwrap().to_string();
+
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            // Requests share the response layout, with the operation where the status goes
+            let request = IppResponse::parse(&body).unwrap();
+            reader.get_mut().write_all(&respond()).unwrap();
+            (resource, request, body)
+        });
+
+        (IppConnection::Tcp { host: "127.0.0.1".to_string(), port }, server)
+    }
+
+    /// Encode an IPP response with the usual operation attributes followed by `groups`
+    fn ipp_response(status: u16, message: Option<&str>, groups: Vec<IppGroup>) -> Vec<u8> {
+        let mut response = IppRequest::new(status, None);
+        if let Some(message) = message {
+            // 0x41 = textWithoutLanguage
+            response.operation_attr("status-message", IppValue::String(0x41, message.to_string()));
+        }
+        response.groups.extend(groups);
+        response.encode(1)
+    }
+
+    fn http_ok(body: Vec<u8>) -> Vec<u8> {
+        let mut out = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/ipp\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        out.extend(body);
+        out
+    }
+
+    /// Same as `http_ok`, but split into chunks the way CUPS sends larger responses
+    fn http_chunked(body: Vec<u8>) -> Vec<u8> {
+        let mut out = b"HTTP/1.1 200 OK\r\nContent-Type: application/ipp\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for chunk in body.chunks(16) {
+            out.extend(format!("{:x}\r\n", chunk.len()).into_bytes());
+            out.extend(chunk);
+            out.extend(b"\r\n");
+        }
+        out.extend(b"0\r\n\r\n");
+        out
+    }
+
+    fn group(tag: u8, attributes: Vec<IppAttribute>) -> IppGroup {
+        IppGroup { tag, attributes }
+    }
+
+    fn operation_str<'a>(request: &'a IppResponse, name: &str) -> Option<&'a str> {
+        request.get(OPERATION_GROUP, name)?.value()?.as_str()
+    }
+
+    #[test]
+    fn print_job_sends_document_and_attributes() {
+        let (connection, server) = serve_once(|| {
+            let job = group(JOB_GROUP, vec![IppAttribute::new("job-id", IppValue::Integer(42))]);
+            http_ok(ipp_response(0x0000, None, vec![job]))
+        });
+
+        let job_id = print_job(
+            &connection,
+            "/ipp/print",
+            "ipp://printer.local/ipp/print",
+            "Shipping label",
+            "application/pdf",
+            b"%PDF-1.4 test".to_vec(),
+            vec![IppAttribute::new("copies", IppValue::Integer(2))],
+        );
+        assert_eq!(job_id, Ok(42));
+
+        let (resource, request, body) = server.join().unwrap();
+        assert_eq!(resource, "/ipp/print");
+        assert_eq!(request.status, PRINT_JOB);
+        assert_eq!(operation_str(&request, "attributes-charset"), Some("utf-8"));
+        assert_eq!(operation_str(&request, "printer-uri"), Some("ipp://printer.local/ipp/print"));
+        assert_eq!(operation_str(&request, "job-name"), Some("Shipping label"));
+        assert_eq!(operation_str(&request, "document-format"), Some("application/pdf"));
+        let copies = request.get(JOB_GROUP, "copies").and_then(|a| a.value()?.as_int());
+        assert_eq!(copies, Some(2));
+        assert!(body.ends_with(&[&[END_OF_ATTRIBUTES][..], b"%PDF-1.4 test"].concat()));
+    }
+
+    #[test]
+    fn print_job_reports_error_status() {
+        let (connection, server) = serve_once(|| {
+            http_ok(ipp_response(0x040a, Some("Unsupported document-format"), vec![]))
+        });
+
+        let result = print_job(&connection, "/ipp/print", "ipp://printer.local/ipp/print", "Label", "image/pwg-raster", vec![], vec![]);
+        assert_eq!(
+            result,
+            Err(HelperError::InvalidOptions("IPP error 0x040a: Unsupported document-format".to_string()))
+        );
+        server.join().unwrap();
+
+        let (connection, server) = serve_once(|| http_ok(ipp_response(0x0406, Some("No such queue"), vec![])));
+        let result = print_job(&connection, "/printers/Gone", "ipp://localhost/printers/Gone", "Label", "application/pdf", vec![], vec![]);
+        assert!(matches!(result, Err(HelperError::PrinterNotFound(_))));
+        server.join().unwrap();
+
+        let (connection, server) = serve_once(|| http_ok(ipp_response(0x0506, Some("Not accepting jobs"), vec![])));
+        let result = print_job(&connection, "/ipp/print", "ipp://printer.local/ipp/print", "Label", "application/pdf", vec![], vec![]);
+        assert!(matches!(result, Err(HelperError::PrinterOffline(_))));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn job_state_asks_for_the_job() {
+        let (connection, server) = serve_once(|| {
+            let job = group(JOB_GROUP, vec![IppAttribute::new("job-state", IppValue::Enum(5))]);
+            http_ok(ipp_response(0x0000, None, vec![job]))
+        });
+
+        assert_eq!(job_state(&connection, "/printers/Zebra", "ipp://localhost/printers/Zebra", 7), Ok(Some(5)));
+
+        let (resource, request, _) = server.join().unwrap();
+        assert_eq!(resource, "/printers/Zebra");
+        assert_eq!(request.status, GET_JOB_ATTRIBUTES);
+        let job_id = request.get(OPERATION_GROUP, "job-id").and_then(|a| a.value()?.as_int());
+        assert_eq!(job_id, Some(7));
+        let requested = request.get(OPERATION_GROUP, "requested-attributes").unwrap();
+        assert_eq!(requested.values.iter().filter_map(IppValue::as_str).collect::<Vec<_>>(), ["job-state"]);
+    }
+
+    #[test]
+    fn job_state_decodes_error_statuses() {
+        let (connection, server) = serve_once(|| http_ok(ipp_response(STATUS_NOT_FOUND, None, vec![])));
+        assert_eq!(job_state(&connection, "/ipp/print", "ipp://printer.local/ipp/print", 7), Ok(None));
+        server.join().unwrap();
+
+        let (connection, server) = serve_once(|| http_ok(ipp_response(0x0502, Some("Printer is busy"), vec![])));
+        assert_eq!(
+            job_state(&connection, "/ipp/print", "ipp://printer.local/ipp/print", 7),
+            Err("IPP error 0x0502: Printer is busy".to_string())
+        );
+        server.join().unwrap();
+
+        let (connection, server) = serve_once(|| http_ok(ipp_response(0x0400, None, vec![])));
+        assert_eq!(
+            job_state(&connection, "/ipp/print", "ipp://printer.local/ipp/print", 7),
+            Err("IPP error 0x0400: no details".to_string())
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn http_errors_are_reported() {
+        let (connection, server) = serve_once(|| b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_vec());
+        let error = job_state(&connection, "/ipp/print", "ipp://printer.local/ipp/print", 7).unwrap_err();
+        assert!(error.starts_with("IPP server refused the request"), "{}", error);
+        server.join().unwrap();
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn cups_printers_are_listed() {
+        let (connection, server) = serve_once(|| {
+            let printer = |name: &str, state: i32, accepting: bool| {
+                group(
+                    PRINTER_GROUP,
+                    vec![
+                        IppAttribute::new("printer-name", IppValue::name(name)),
+                        IppAttribute::new("printer-state", IppValue::Enum(state)),
+                        IppAttribute::new("printer-is-accepting-jobs", IppValue::Boolean(accepting)),
+                    ],
+                )
+            };
+            let printers = vec![printer("Zebra_ZD420", 3, true), printer("Office", 5, false)];
+            http_chunked(ipp_response(0x0000, None, printers))
+        });
+
+        let printers = CupsClient { connection }.printers().unwrap();
+        let printers: Vec<_> = printers.iter().map(|p| (p.name.as_str(), p.state, p.accepting_jobs)).collect();
+        assert_eq!(printers, [("Zebra_ZD420", 3, true), ("Office", 5, false)]);
+
+        let (resource, request, _) = server.join().unwrap();
+        assert_eq!(resource, "/");
+        assert_eq!(request.status, CUPS_GET_PRINTERS);
+        assert!(request.get(OPERATION_GROUP, "printer-uri").is_none());
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn cups_without_printers_is_not_an_error() {
+        let (connection, server) = serve_once(|| http_ok(ipp_response(STATUS_NOT_FOUND, None, vec![])));
+        assert!(CupsClient { connection }.printers().unwrap().is_empty());
+        server.join().unwrap();
+
+        let (connection, server) = serve_once(|| http_ok(ipp_response(0x0401, Some("Forbidden"), vec![])));
+        assert_eq!(CupsClient { connection }.printers().unwrap_err(), "IPP error 0x0401: Forbidden");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,435 @@
+//! Print job queue - records every job the helper receives and persists the history to disk
+
+use crate::error::HelperError;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Maximum number of jobs kept in history (oldest are dropped first)
+const MAX_JOB_HISTORY: usize = 500;
+
+/// Job history, loaded from disk on first access
+static JOBS: Lazy<RwLock<Vec<PrintJob>>> = Lazy::new(|| RwLock::new(load_jobs()));
+
+/// Set when the history changed since it was last written, waking the writer thread
+static SAVE_PENDING: Lazy<(Mutex<bool>, Condvar)> = Lazy::new(|| (Mutex::new(false), Condvar::new()));
+
+/// Thread that writes the history to disk, started on the first change
+static WRITER: Lazy<()> = Lazy::new(|| {
+    if let Err(e) = thread::Builder::new().name("job-history".to_string()).spawn(write_jobs_when_changed) {
+        tracing::warn!("Could not start the job history writer: {}", e);
+    }
+});
+
+/// Held while `jobs.json` is being written
+static WRITING: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+/// How long the writer waits for more changes before writing the history
+const SAVE_DELAY_MS: u64 = 500;
+
+/// Every change to a job, for following progress live (`/jobs/{id}/events`)
+static JOB_UPDATES: Lazy<broadcast::Sender<PrintJob>> = Lazy::new(|| broadcast::channel(256).0);
+
+/// Serializes print submissions so jobs reach the printer in the order they were received
+pub static PRINT_QUEUE: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+/// Lifecycle of a print job
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    /// Accepted by the HTTP server, waiting for its turn in the queue
+    Received,
+    /// Being laid out or rasterized for the printer (imposition, ZPL)
+    Rendering,
+    /// Being written to disk and handed to the print backend
+    Spooling,
+    /// Handed to the OS print spooler, waiting there
+    Submitted,
+    /// The spooler is sending the job to the printer
+    Printing,
+    /// Spooler is holding the job until it is released
+    Held,
+    /// Spooler stopped processing the job (e.g. printer error)
+    Stopped,
+    /// Spooler reports the job finished printing
+    Completed,
+    /// Job was canceled in the spooler
+    Canceled,
+    /// Spooler gave up on the job
+    Aborted,
+    /// Job could not be printed
+    Failed,
+    /// Not printed: the same document went to the same printer with the same options just before
+    Suppressed,
+}
+
+impl JobStatus {
+    /// Whether the job will not change state any more
+    pub fn is_final(self) -> bool {
+        matches!(
+            self,
+            JobStatus::Completed
+                | JobStatus::Canceled
+                | JobStatus::Aborted
+                | JobStatus::Failed
+                | JobStatus::Suppressed
+        )
+    }
+}
+
+/// A single print job record
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PrintJob {
+    pub id: String,
+    pub status: JobStatus,
+    pub printer: Option<String>,
+    pub copies: u32,
+    pub size_bytes: u64,
+    /// Job ID assigned by the OS spooler (CUPS "Printer-123" or the Windows spooler job number)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spooler_job_id: Option<String>,
+    /// Batch the job was submitted in (`/print/batch`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_id: Option<String>,
+    /// Printer that accepted the job: the requested one, or its profile's failover printer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub printed_on: Option<String>,
+    /// Times the job was offered to a printer, counting retries and failover
+    #[serde(default)]
+    pub attempts: u32,
+    pub created_at: String,
+    pub updated_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Stable code of the error that failed the job (see `HelperError`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_code: Option<String>,
+    /// Hash of the document, printer and options, for spotting the same job sent twice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// For suppressed jobs, the earlier job this one repeated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+}
+
+/// A job that repeated a recent one: its own (suppressed) record and the job it repeated
+pub struct Duplicate {
+    pub job: PrintJob,
+    pub original: PrintJob,
+}
+
+/// Get the path of the persisted job history
+fn get_jobs_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("anymobile-print-helper")
+        .join("jobs.json")
+}
+
+/// Load job history from disk
+/// Jobs that were still queued or spooling when the app exited can never finish, so they are marked failed
+fn load_jobs() -> Vec<PrintJob> {
+    let path = get_jobs_path();
+    let mut jobs: Vec<PrintJob> = match fs::read(&path) {
+        Ok(data) => serde_json::from_slice(&data).unwrap_or_else(|e| {
+            tracing::warn!("Job history at {:?} is unreadable, starting fresh: {}", path, e);
+            vec![]
+        }),
+        Err(_) => vec![],
+    };
+
+    let now = now_timestamp();
+    for job in &mut jobs {
+        if matches!(job.status, JobStatus::Received | JobStatus::Rendering | JobStatus::Spooling) {
+            job.status = JobStatus::Failed;
+            job.error = Some("Interrupted by helper restart".to_string());
+            job.updated_at = now.clone();
+        }
+    }
+
+    tracing::info!("Loaded {} job(s) from history", jobs.len());
+    jobs
+}
+
+/// Ask the writer thread to save the job history
+/// Changes come in bursts (a job moves through several states within a second), so the writer
+/// waits `SAVE_DELAY_MS` and writes them all at once, away from the request being served.
+fn save_jobs() {
+    Lazy::force(&WRITER);
+    let (pending, wake) = &*SAVE_PENDING;
+    if let Ok(mut pending) = pending.lock() {
+        *pending = true;
+        wake.notify_one();
+    }
+}
+
+/// Writer thread: wait for a change, let the burst settle, write the history
+fn write_jobs_when_changed() {
+    let (pending, wake) = &*SAVE_PENDING;
+    loop {
+        let Ok(guard) = pending.lock() else { return };
+        let Ok(guard) = wake.wait_while(guard, |pending| !*pending) else { return };
+        drop(guard);
+
+        thread::sleep(Duration::from_millis(SAVE_DELAY_MS));
+        write_pending_jobs();
+    }
+}
+
+/// Write the history now if it changed since the last write (the writer thread, and at exit)
+pub fn flush() {
+    write_pending_jobs();
+}
+
+fn write_pending_jobs() {
+    let (pending, _) = &*SAVE_PENDING;
+    match pending.lock() {
+        Ok(mut pending) if *pending => *pending = false,
+        _ => return,
+    }
+    let snapshot = match JOBS.read() {
+        Ok(jobs) => jobs.clone(),
+        Err(_) => return,
+    };
+    write_jobs_file(&snapshot);
+}
+
+/// Write job history to disk: to a temporary file first, renamed over `jobs.json`, so a crash
+/// mid-write leaves the previous history rather than a truncated file
+fn write_jobs_file(jobs: &[PrintJob]) {
+    // The writer thread and `flush` share the temporary file
+    let Ok(_writing) = WRITING.lock() else { return };
+
+    let path = get_jobs_path();
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            tracing::warn!("Could not create data directory: {}", e);
+            return;
+        }
+    }
+
+    let data = match serde_json::to_vec_pretty(jobs) {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::warn!("Could not serialize job history: {}", e);
+            return;
+        }
+    };
+    let temp_path = path.with_extension("json.tmp");
+    if let Err(e) = fs::write(&temp_path, data).and_then(|()| fs::rename(&temp_path, &path)) {
+        tracing::warn!("Could not save job history: {}", e);
+    }
+}
+
+fn now_timestamp() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+/// Record a new job in the Received state
+pub fn create_job(printer: Option<&str>, copies: u32, size_bytes: usize, batch_id: Option<&str>) -> PrintJob {
+    let job = new_job(printer, copies, size_bytes, batch_id);
+    if let Ok(mut jobs) = JOBS.write() {
+        push_job(&mut jobs, job.clone());
+    }
+    let _ = JOB_UPDATES.send(job.clone());
+
+    tracing::info!("Job {} received", job.id);
+    job
+}
+
+/// Fingerprint of a job for spotting repeats: its document (by its SHA-256 digest, as the spool
+/// computes it while receiving), printer, copies and other options
+pub fn fingerprint(document_digest: &str, printer: Option<&str>, copies: u32, options: &impl Serialize) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(document_digest);
+    hasher.update(serde_json::to_vec(&(printer, copies, options)).unwrap_or_default());
+    hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Record a new job unless the same job (by fingerprint) was received within `window`
+/// A repeat is recorded as a suppressed job pointing at the earlier one, so it shows up in the
+/// history. Failed, canceled and suppressed jobs don't count, and `force` skips the check.
+pub fn create_job_unless_duplicate(
+    printer: Option<&str>,
+    copies: u32,
+    size_bytes: usize,
+    fingerprint: &str,
+    window: chrono::Duration,
+    force: bool,
+) -> Result<PrintJob, Box<Duplicate>> {
+    let mut job = new_job(printer, copies, size_bytes, None);
+    job.fingerprint = Some(fingerprint.to_string());
+
+    let Ok(mut jobs) = JOBS.write() else {
+        return Ok(job);
+    };
+    // Jobs are kept in the order they were received, so the search can stop at the window's edge
+    let cutoff = chrono::Local::now() - window;
+    let original = if force {
+        None
+    } else {
+        jobs.iter()
+            .rev()
+            .take_while(|j| chrono::DateTime::parse_from_rfc3339(&j.created_at).is_ok_and(|created| created >= cutoff))
+            .find(|j| {
+                j.fingerprint.as_deref() == Some(fingerprint)
+                    && !matches!(
+                        j.status,
+                        JobStatus::Failed | JobStatus::Canceled | JobStatus::Aborted | JobStatus::Suppressed
+                    )
+            })
+            .cloned()
+    };
+
+    if let Some(original) = &original {
+        job.status = JobStatus::Suppressed;
+        job.duplicate_of = Some(original.id.clone());
+    }
+    push_job(&mut jobs, job.clone());
+    drop(jobs);
+    let _ = JOB_UPDATES.send(job.clone());
+
+    match original {
+        Some(original) => {
+            tracing::warn!("Job {} suppressed, it repeats job {}", job.id, original.id);
+            Err(Box::new(Duplicate { job, original }))
+        }
+        None => {
+            tracing::info!("Job {} received", job.id);
+            Ok(job)
+        }
+    }
+}
+
+/// A job record in the Received state, not stored yet
+fn new_job(printer: Option<&str>, copies: u32, size_bytes: usize, batch_id: Option<&str>) -> PrintJob {
+    let now = now_timestamp();
+    PrintJob {
+        id: Uuid::new_v4().to_string(),
+        status: JobStatus::Received,
+        printer: printer.map(str::to_string),
+        copies,
+        size_bytes: size_bytes as u64,
+        spooler_job_id: None,
+        batch_id: batch_id.map(str::to_string),
+        printed_on: None,
+        attempts: 0,
+        created_at: now.clone(),
+        updated_at: now,
+        error: None,
+        error_code: None,
+        fingerprint: None,
+        duplicate_of: None,
+    }
+}
+
+/// Add a job to the history, dropping the oldest once it is full
+fn push_job(jobs: &mut Vec<PrintJob>, job: PrintJob) {
+    if jobs.len() >= MAX_JOB_HISTORY {
+        jobs.remove(0);
+    }
+    jobs.push(job);
+    save_jobs();
+}
+
+/// Apply a change to a job and persist the history
+fn modify_job(id: &str, f: impl FnOnce(&mut PrintJob)) {
+    if let Ok(mut jobs) = JOBS.write() {
+        if let Some(job) = jobs.iter_mut().find(|j| j.id == id) {
+            f(job);
+            job.updated_at = now_timestamp();
+            let _ = JOB_UPDATES.send(job.clone());
+            save_jobs();
+        }
+    }
+}
+
+/// Move a job to a new status
+pub fn set_status(id: &str, status: JobStatus) {
+    tracing::info!("Job {} -> {:?}", id, status);
+    modify_job(id, |job| job.status = status);
+}
+
+/// Mark a job as handed to the spooler, recording the spooler's job ID if known
+pub fn set_submitted(id: &str, spooler_job_id: Option<&str>) {
+    tracing::info!("Job {} -> Submitted (spooler job {:?})", id, spooler_job_id);
+    modify_job(id, |job| {
+        job.status = JobStatus::Submitted;
+        job.spooler_job_id = spooler_job_id.map(str::to_string);
+    });
+}
+
+/// Count a try at handing the job to a printer; `error` is why the previous try failed
+pub fn record_attempt(id: &str, error: Option<&str>) {
+    modify_job(id, |job| {
+        job.attempts += 1;
+        if let Some(error) = error {
+            job.error = Some(error.to_string());
+        }
+    });
+}
+
+/// Record which printer accepted the job, clearing errors from earlier tries
+pub fn set_printed_on(id: &str, printer: Option<&str>) {
+    modify_job(id, |job| {
+        job.error = None;
+        job.printed_on = printer.map(str::to_string);
+    });
+}
+
+/// Mark a job as failed with the error that stopped it
+pub fn fail_job(id: &str, error: &HelperError) {
+    tracing::warn!("Job {} failed ({}): {}", id, error.code(), error);
+    modify_job(id, |job| {
+        job.status = JobStatus::Failed;
+        job.error = Some(error.to_string());
+        job.error_code = Some(error.code().to_string());
+    });
+}
+
+/// Receive every job change from now on
+pub fn subscribe() -> broadcast::Receiver<PrintJob> {
+    JOB_UPDATES.subscribe()
+}
+
+/// Look up a single job
+pub fn get_job(id: &str) -> Option<PrintJob> {
+    JOBS.read().ok()?.iter().find(|j| j.id == id).cloned()
+}
+
+/// Jobs that are still in the spooler, as (job ID, spooler job ID) pairs
+pub fn unfinished_spooler_jobs() -> Vec<(String, String)> {
+    let jobs = match JOBS.read() {
+        Ok(j) => j,
+        Err(_) => return vec![],
+    };
+
+    jobs.iter()
+        .filter(|j| !j.status.is_final())
+        .filter_map(|j| Some((j.id.clone(), j.spooler_job_id.clone()?)))
+        .collect()
+}
+
+/// Get recent jobs, newest first, optionally only those from one batch
+pub fn list_jobs(limit: Option<usize>, batch_id: Option<&str>) -> Vec<PrintJob> {
+    let jobs = match JOBS.read() {
+        Ok(j) => j,
+        Err(_) => return vec![],
+    };
+
+    let limit = limit.unwrap_or(100);
+    jobs.iter()
+        .rev()
+        .filter(|j| batch_id.is_none() || j.batch_id.as_deref() == batch_id)
+        .take(limit)
+        .cloned()
+        .collect()
+}
@@ -5,6 +5,18 @@ mod server;
 mod printer;
 mod cert_manager;
 mod diagnostics;
+mod jobs;
+mod profiles;
+mod print_options;
+mod config;
+mod ipp;
+mod zpl;
+mod images;
+mod pdf;
+mod spool;
+mod error;
+mod access;
+mod signing;
 
 use tauri::{
     menu::{Menu, MenuItem},
@@ -13,23 +25,7 @@ use tauri::{
 };
 use tauri_plugin_autostart::MacosLauncher;
 use tauri_plugin_updater::UpdaterExt;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-
-/// Application state shared across the app
-pub struct AppState {
-    pub server_running: bool,
-    pub last_print_job: Option<String>,
-}
-
-impl Default for AppState {
-    fn default() -> Self {
-        Self {
-            server_running: false,
-            last_print_job: None,
-        }
-    }
-}
+use error::HelperError;
 
 // =====================
 // TAURI COMMANDS
@@ -37,38 +33,38 @@ impl Default for AppState {
 
 /// Get diagnostic status
 #[tauri::command]
-async fn get_diagnostics(app: tauri::AppHandle) -> Result<diagnostics::DiagnosticStatus, String> {
+async fn get_diagnostics(app: tauri::AppHandle) -> Result<diagnostics::DiagnosticStatus, HelperError> {
     let version = app.package_info().version.to_string();
     Ok(diagnostics::get_diagnostic_status(version))
 }
 
 /// Test connection to both endpoints
 #[tauri::command]
-async fn test_connection() -> Result<diagnostics::ConnectionTestResult, String> {
+async fn test_connection() -> Result<diagnostics::ConnectionTestResult, HelperError> {
     Ok(diagnostics::test_connections().await)
 }
 
 /// Get list of available printers
 #[tauri::command]
-async fn get_printers() -> Result<Vec<server::PrinterInfo>, String> {
+async fn get_printers() -> Result<Vec<server::PrinterInfo>, HelperError> {
     Ok(diagnostics::get_printers())
 }
 
 /// Get certificate information
 #[tauri::command]
-async fn get_certificate_info() -> Result<diagnostics::CertificateInfo, String> {
+async fn get_certificate_info() -> Result<diagnostics::CertificateInfo, HelperError> {
     Ok(diagnostics::get_certificate_info())
 }
 
 /// Check if certificate is trusted on Windows
 #[tauri::command]
-fn check_cert_trusted() -> Result<bool, String> {
+fn check_cert_trusted() -> Result<bool, HelperError> {
     cert_manager::is_cert_trusted()
 }
 
 /// Install certificate to Windows store
 #[tauri::command]
-fn install_certificate(use_admin: bool) -> Result<(), String> {
+fn install_certificate(use_admin: bool) -> Result<(), HelperError> {
     if use_admin {
         cert_manager::install_cert_local_machine()
     } else {
@@ -76,17 +72,36 @@ fn install_certificate(use_admin: bool) -> Result<(), String> {
     }
 }
 
-/// Regenerate the certificate
+/// Regenerate the certificate and hand it to the running HTTPS server
+#[tauri::command]
+async fn regenerate_certificate() -> Result<String, HelperError> {
+    diagnostics::regenerate_certificate().map_err(HelperError::Certificate)?;
+    server::reload_certificate().await?;
+    Ok("New certificate created and in use.".to_string())
+}
+
+/// Start the HTTPS and HTTP servers if they aren't running
+#[tauri::command]
+async fn start_server(app: tauri::AppHandle) -> Result<(), HelperError> {
+    server::start_server(app).await
+}
+
+/// Stop the servers; print requests in progress are given a few seconds to finish
 #[tauri::command]
-fn regenerate_certificate() -> Result<String, String> {
-    diagnostics::regenerate_certificate()?;
-    Ok("Certificate deleted. Restart the app to generate a new one.".to_string())
+async fn stop_server() {
+    server::stop_server().await
+}
+
+/// Restart the servers with the config and certificate on disk
+#[tauri::command]
+async fn restart_server(app: tauri::AppHandle) -> Result<(), HelperError> {
+    server::restart_server(app).await
 }
 
 /// Open certificate folder
 #[tauri::command]
-fn open_cert_folder() -> Result<(), String> {
-    diagnostics::open_cert_folder()
+fn open_cert_folder() -> Result<(), HelperError> {
+    diagnostics::open_cert_folder().map_err(HelperError::Internal)
 }
 
 /// Get recent logs
@@ -103,13 +118,67 @@ fn clear_logs() {
 
 /// Copy diagnostics to clipboard format
 #[tauri::command]
-async fn copy_diagnostics(app: tauri::AppHandle) -> Result<String, String> {
+async fn copy_diagnostics(app: tauri::AppHandle) -> Result<String, HelperError> {
     let version = app.package_info().version.to_string();
     let status = diagnostics::get_diagnostic_status(version);
     let printers = diagnostics::get_printers();
     Ok(diagnostics::format_diagnostics_for_copy(&status, &printers))
 }
 
+/// Get printer profiles (settings applied per printer)
+#[tauri::command]
+fn get_printer_profiles() -> profiles::ProfilesFile {
+    profiles::get_profiles()
+}
+
+/// Save printer profiles edited in the status window
+#[tauri::command]
+fn save_printer_profiles(profiles: profiles::ProfilesFile) -> Result<(), HelperError> {
+    profiles::save_profiles(profiles)
+}
+
+/// Get the path of the printer profiles file
+#[tauri::command]
+fn get_printer_profiles_path() -> String {
+    profiles::get_profiles_path().to_string_lossy().to_string()
+}
+
+/// Get the origin allowlist, paired web apps and any open pairing code
+#[tauri::command]
+fn get_access_settings() -> access::AccessSettings {
+    access::get_access_settings()
+}
+
+/// Save the origin allowlist edited in the status window
+#[tauri::command]
+fn save_allowed_origins(origins: Vec<String>) -> Result<(), HelperError> {
+    access::save_allowed_origins(origins)
+}
+
+/// Require (or stop requiring) signed print requests
+#[tauri::command]
+fn set_signing_required(required: bool) -> Result<(), HelperError> {
+    access::set_signing_required(required)
+}
+
+/// Show a one-time code the web app can pair with
+#[tauri::command]
+fn start_pairing() -> Result<access::PairingCode, HelperError> {
+    access::start_pairing()
+}
+
+/// Withdraw the pairing code
+#[tauri::command]
+fn cancel_pairing() {
+    access::cancel_pairing()
+}
+
+/// Forget a paired web app
+#[tauri::command]
+fn revoke_paired_app(id: String) -> Result<(), HelperError> {
+    access::revoke_app(&id)
+}
+
 /// Get current platform
 #[tauri::command]
 fn get_platform() -> String {
@@ -124,12 +193,12 @@ fn check_ghostscript_installed() -> bool {
 
 /// Install Ghostscript for high-quality printing (Windows only)
 #[tauri::command]
-async fn install_ghostscript() -> Result<String, String> {
+async fn install_ghostscript() -> Result<String, HelperError> {
     #[cfg(target_os = "windows")]
     {
         match printer::ensure_ghostscript_available().await {
             Ok(path) => Ok(format!("Ghostscript installed successfully!")),
-            Err(e) => Err(e.to_string()),
+            Err(e) => Err(HelperError::BackendMissing(format!("Ghostscript installation failed: {}", e))),
         }
     }
     #[cfg(not(target_os = "windows"))]
@@ -154,7 +223,6 @@ fn main() {
             Some(vec!["--minimized"]),
         ))
         .plugin(tauri_plugin_updater::Builder::new().build())
-        .manage(Arc::new(Mutex::new(AppState::default())))
         .invoke_handler(tauri::generate_handler![
             get_diagnostics,
             test_connection,
@@ -163,19 +231,39 @@ fn main() {
             check_cert_trusted,
             install_certificate,
             regenerate_certificate,
+            start_server,
+            stop_server,
+            restart_server,
             open_cert_folder,
             get_recent_logs,
             clear_logs,
             copy_diagnostics,
+            get_printer_profiles,
+            save_printer_profiles,
+            get_printer_profiles_path,
+            get_access_settings,
+            save_allowed_origins,
+            set_signing_required,
+            start_pairing,
+            cancel_pairing,
+            revoke_paired_app,
             get_platform,
             check_ghostscript_installed,
             install_ghostscript
         ])
         .setup(|app| {
+            // Load printer profiles (writes the defaults on first run)
+            config::init();
+            profiles::init();
+            access::init();
+            spool::init();
+
             // Create system tray menu
             let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
             let show = MenuItem::with_id(app, "show", "Show Status", true, None::<&str>)?;
-            let menu = Menu::with_items(app, &[&show, &quit])?;
+            let restart = MenuItem::with_id(app, "restart", "Restart Server", true, None::<&str>)?;
+            let stop = MenuItem::with_id(app, "stop", "Stop Server", true, None::<&str>)?;
+            let menu = Menu::with_items(app, &[&show, &restart, &stop, &quit])?;
 
             // Create tray icon
             let _tray = TrayIconBuilder::new()
@@ -192,6 +280,17 @@ fn main() {
                             let _ = window.set_focus();
                         }
                     }
+                    "restart" => {
+                        let app_handle = app.clone();
+                        tauri::async_runtime::spawn(async move {
+                            if let Err(e) = server::restart_server(app_handle).await {
+                                tracing::error!("Failed to restart server: {}", e);
+                            }
+                        });
+                    }
+                    "stop" => {
+                        tauri::async_runtime::spawn(server::stop_server());
+                    }
                     _ => {}
                 })
                 .on_tray_icon_event(|tray, event| {
@@ -213,6 +312,10 @@ fn main() {
             // Start HTTP server in background
             let app_handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
+                // Pick up spooler jobs that were still printing when the app last exited
+                printer::resume_job_watchers();
+                tokio::spawn(spool::sweep_periodically());
+
                 if let Err(e) = server::start_server(app_handle).await {
                     tracing::error!("Failed to start HTTP server: {}", e);
                 }
@@ -266,6 +369,12 @@ fn main() {
 
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                access::flush_last_used();
+                jobs::flush();
+            }
+        });
 }
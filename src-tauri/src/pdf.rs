@@ -0,0 +1,621 @@
+//! PDF handling done in Rust, without Ghostscript - checking uploads, merging documents and N-up
+//! imposition
+//!
+//! Uploads are parsed before anything is spooled, so empty, damaged or encrypted files are
+//! rejected with a clear error instead of failing in the print backend. Merging sends a batch for
+//! one printer as a single multi-page job (some queues add a wake-up delay per job). Imposition
+//! places label pages on larger sheets. Pages are never re-rendered or scaled: they are copied,
+//! or drawn as form XObjects at 100%.
+
+use lopdf::{Dictionary, Document, Object, ObjectId, Stream};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Page attributes a page can inherit from its parent page-tree nodes
+/// They are copied onto each page because the original page tree is replaced.
+const INHERITABLE_PAGE_KEYS: [&[u8]; 4] = [b"MediaBox", b"CropBox", b"Resources", b"Rotate"];
+
+/// Points per millimetre (PDF user space is 1/72 inch)
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+
+/// Slack allowed when checking a label fits its grid cell, in points (rounding in page boxes)
+const FIT_TOLERANCE_PT: f64 = 0.5;
+
+/// Most labels placed on one sheet, whether the grid is given or worked out from the label size
+pub const MAX_LABELS_PER_SHEET: u32 = 100;
+
+/// Where a page of a merged document came from
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PageSource {
+    /// Page number in the merged document (the label), from 1
+    pub label: u32,
+    /// Index of the source document
+    pub document: usize,
+    /// Page number in the source document, from 1
+    pub page: u32,
+}
+
+/// A page's size as it appears on paper (after /Rotate), in millimetres
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PageSize {
+    pub width_mm: f64,
+    pub height_mm: f64,
+}
+
+/// What an uploaded PDF contains
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PdfInfo {
+    pub page_count: usize,
+    /// Size of each page, in order
+    pub pages: Vec<PageSize>,
+}
+
+/// Check the start of an upload (at least its first 1 KB, when it is that long) is a PDF
+pub fn check_header(head: &[u8]) -> Result<(), String> {
+    // Readers accept a little junk before the header, so look a bit further than the first byte
+    if !head[..head.len().min(1024)].windows(5).any(|window| window == b"%PDF-") {
+        return Err("Not a PDF (no %PDF header)".to_string());
+    }
+    Ok(())
+}
+
+/// Check an upload is a PDF that can be printed and read its page sizes
+pub fn inspect(data: &[u8]) -> Result<PdfInfo, String> {
+    if data.is_empty() {
+        return Err("The PDF is empty".to_string());
+    }
+    check_header(data)?;
+
+    let document = Document::load_mem(data).map_err(|e| match e {
+        lopdf::Error::Decryption(_) | lopdf::Error::InvalidPassword => {
+            "The PDF is password protected; send an unprotected copy".to_string()
+        }
+        e => format!("The PDF is damaged and can't be read: {}", e),
+    })?;
+    if document.is_encrypted() || document.was_encrypted() {
+        return Err("The PDF is encrypted; send an unprotected copy".to_string());
+    }
+
+    let pages = document.get_pages();
+    if pages.is_empty() {
+        return Err("The PDF has no pages".to_string());
+    }
+
+    let mut sizes = Vec::with_capacity(pages.len());
+    for (number, page_id) in pages {
+        let (coordinates, rotate) =
+            page_geometry(&document, page_id).map_err(|e| format!("Page {} is damaged: {}", number, e))?;
+        let width = (coordinates[2] - coordinates[0]).abs() / POINTS_PER_MM;
+        let height = (coordinates[3] - coordinates[1]).abs() / POINTS_PER_MM;
+        let (width_mm, height_mm) = if rotate % 180 == 90 { (height, width) } else { (width, height) };
+        sizes.push(PageSize { width_mm, height_mm });
+    }
+
+    Ok(PdfInfo {
+        page_count: sizes.len(),
+        pages: sizes,
+    })
+}
+
+/// A merged document and the origin of each of its pages
+pub struct MergedPdf {
+    pub data: Vec<u8>,
+    pub pages: Vec<PageSource>,
+}
+
+/// Concatenate PDFs into one document, in order
+pub fn merge_pdfs(documents: &[&[u8]]) -> Result<MergedPdf, String> {
+    let mut objects: BTreeMap<ObjectId, Object> = BTreeMap::new();
+    let mut page_ids: Vec<ObjectId> = Vec::new();
+    let mut sources: Vec<PageSource> = Vec::new();
+    let mut max_id = 1;
+
+    for (index, data) in documents.iter().enumerate() {
+        let mut document = Document::load_mem(data)
+            .map_err(|e| format!("Document {} is not a readable PDF: {}", index, e))?;
+
+        // Give every document its own object number range so they can share one file
+        document.renumber_objects_with(max_id);
+        max_id = document.max_id + 1;
+
+        let pages = document.get_pages();
+        if pages.is_empty() {
+            return Err(format!("Document {} has no pages", index));
+        }
+
+        for (number, page_id) in pages {
+            let mut page = document
+                .get_dictionary(page_id)
+                .map_err(|e| format!("Document {} page {} is invalid: {}", index, number, e))?
+                .clone();
+            for key in INHERITABLE_PAGE_KEYS {
+                if !page.has(key) {
+                    if let Some(value) = inherited_attribute(&document, page_id, key) {
+                        page.set(key, value);
+                    }
+                }
+            }
+
+            objects.insert(page_id, Object::Dictionary(page));
+            page_ids.push(page_id);
+            sources.push(PageSource {
+                label: sources.len() as u32 + 1,
+                document: index,
+                page: number,
+            });
+        }
+
+        // Everything else (content streams, fonts, images...) is carried over; the catalog,
+        // page tree and outlines are rebuilt or dropped
+        for (id, object) in document.objects {
+            let skip = match object.type_name() {
+                Ok(b"Catalog" | b"Pages" | b"Outlines" | b"Outline") => true,
+                Ok(b"Page") => objects.contains_key(&id),
+                _ => false,
+            };
+            if !skip {
+                objects.entry(id).or_insert(object);
+            }
+        }
+    }
+
+    let pages_id = (max_id, 0);
+    let catalog_id = (max_id + 1, 0);
+    for page_id in &page_ids {
+        if let Some(Object::Dictionary(page)) = objects.get_mut(page_id) {
+            page.set("Parent", pages_id);
+        }
+    }
+
+    let mut pages = Dictionary::new();
+    pages.set("Type", Object::Name(b"Pages".to_vec()));
+    pages.set("Kids", page_ids.iter().map(|id| Object::Reference(*id)).collect::<Vec<_>>());
+    pages.set("Count", page_ids.len() as i64);
+    objects.insert(pages_id, Object::Dictionary(pages));
+
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", Object::Name(b"Catalog".to_vec()));
+    catalog.set("Pages", pages_id);
+    objects.insert(catalog_id, Object::Dictionary(catalog));
+
+    let mut merged = Document::with_version("1.5");
+    merged.objects = objects;
+    merged.max_id = catalog_id.0;
+    merged.trailer.set("Root", catalog_id);
+    merged.renumber_objects();
+    merged.compress();
+
+    let mut data = Vec::new();
+    merged
+        .save_to(&mut data)
+        .map_err(|e| format!("Could not write merged PDF: {}", e))?;
+
+    Ok(MergedPdf { data, pages: sources })
+}
+
+/// Look up an attribute a page inherits from its ancestors in the page tree
+fn inherited_attribute(document: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    let mut node = document.get_dictionary(page_id).ok()?;
+    // Bounded so a malformed tree with a cycle can't loop forever
+    for _ in 0..32 {
+        let parent_id = node.get(b"Parent").and_then(Object::as_reference).ok()?;
+        node = document.get_dictionary(parent_id).ok()?;
+        if let Ok(value) = node.get(key) {
+            return Some(value.clone());
+        }
+    }
+    None
+}
+
+/// How label pages are laid out on sheets, in points
+#[derive(Clone, Debug, PartialEq)]
+pub struct SheetLayout {
+    pub sheet_width: f64,
+    pub sheet_height: f64,
+    /// Labels across and down; as many as fit the first label when None
+    pub grid: Option<(u32, u32)>,
+    pub margin: f64,
+    pub gutter: f64,
+    /// Positions already used on the first sheet (0 = start at the top left)
+    pub skip: u32,
+}
+
+impl SheetLayout {
+    /// Build a layout from millimetre measurements
+    pub fn from_mm(
+        sheet_mm: (f64, f64),
+        grid: Option<(u32, u32)>,
+        margin_mm: f64,
+        gutter_mm: f64,
+        skip: u32,
+    ) -> Self {
+        Self {
+            sheet_width: sheet_mm.0 * POINTS_PER_MM,
+            sheet_height: sheet_mm.1 * POINTS_PER_MM,
+            grid,
+            margin: margin_mm * POINTS_PER_MM,
+            gutter: gutter_mm * POINTS_PER_MM,
+            skip,
+        }
+    }
+}
+
+/// A label page turned into a form XObject, with its size as it appears on paper
+struct LabelForm {
+    id: ObjectId,
+    /// Transform from the page's own coordinates to an upright box at the origin
+    matrix: [f64; 6],
+    width: f64,
+    height: f64,
+}
+
+impl LabelForm {
+    /// Turn the label a quarter clockwise
+    fn turn(&mut self) {
+        let [a, b, c, d, e, f] = self.matrix;
+        self.matrix = [b, -a, d, -c, f, self.width - e];
+        std::mem::swap(&mut self.width, &mut self.height);
+    }
+}
+
+/// Place every page of a PDF onto sheets in a grid, at 100%
+/// Labels are centered in their cell, filling across then down, starting after `layout.skip`
+/// positions on the first sheet. A label that only fits its cell sideways is turned; one that
+/// doesn't fit either way is an error.
+pub fn impose(data: &[u8], layout: &SheetLayout) -> Result<Vec<u8>, String> {
+    let mut document = Document::load_mem(data).map_err(|e| format!("Not a readable PDF: {}", e))?;
+    let pages = document.get_pages();
+    if pages.is_empty() {
+        return Err("PDF has no pages".to_string());
+    }
+
+    let mut labels = Vec::with_capacity(pages.len());
+    for (number, page_id) in pages {
+        let label = label_form(&mut document, page_id)
+            .map_err(|e| format!("Page {} can't be imposed: {}", number, e))?;
+        labels.push(label);
+    }
+
+    let usable_width = layout.sheet_width - 2.0 * layout.margin;
+    let usable_height = layout.sheet_height - 2.0 * layout.margin;
+    let (columns, rows) = match layout.grid {
+        Some(grid) => grid,
+        None => {
+            let fit = |usable: f64, size: f64| ((usable + layout.gutter) / (size + layout.gutter)).floor().max(0.0) as u32;
+            (fit(usable_width, labels[0].width), fit(usable_height, labels[0].height))
+        }
+    };
+    if columns == 0 || rows == 0 {
+        return Err("Labels don't fit on the sheet".to_string());
+    }
+    // A tiny label or a huge sheet would otherwise mean millions of cells (or an overflow)
+    let per_sheet = columns
+        .checked_mul(rows)
+        .filter(|count| *count <= MAX_LABELS_PER_SHEET)
+        .ok_or_else(|| {
+            format!(
+                "A {}x{} grid is more than {} labels per sheet",
+                columns, rows, MAX_LABELS_PER_SHEET
+            )
+        })?;
+    let cell_width = (usable_width - (columns - 1) as f64 * layout.gutter) / columns as f64;
+    let cell_height = (usable_height - (rows - 1) as f64 * layout.gutter) / rows as f64;
+    if cell_width <= 0.0 || cell_height <= 0.0 {
+        return Err("Margins and gutters leave no room for labels".to_string());
+    }
+
+    if layout.skip >= per_sheet {
+        return Err(format!("Start position {} is past the {} positions on a sheet", layout.skip + 1, per_sheet));
+    }
+
+    let fits = |width: f64, height: f64| {
+        width <= cell_width + FIT_TOLERANCE_PT && height <= cell_height + FIT_TOLERANCE_PT
+    };
+    for (index, label) in labels.iter_mut().enumerate() {
+        if fits(label.width, label.height) {
+            continue;
+        }
+        // A 4x6 label goes sideways into a half-letter cell
+        if fits(label.height, label.width) {
+            label.turn();
+        } else {
+            return Err(format!(
+                "Page {} ({:.1}x{:.1} mm) is larger than a grid cell ({:.1}x{:.1} mm)",
+                index + 1,
+                label.width / POINTS_PER_MM,
+                label.height / POINTS_PER_MM,
+                cell_width / POINTS_PER_MM,
+                cell_height / POINTS_PER_MM
+            ));
+        }
+    }
+
+    // Group labels by sheet, each with the position of its cell
+    let slots = layout.skip as usize..layout.skip as usize + labels.len();
+    let mut sheets: Vec<Vec<(usize, f64, f64)>> = Vec::new();
+    for (index, slot) in slots.enumerate() {
+        let sheet = slot / per_sheet as usize;
+        let position = (slot % per_sheet as usize) as u32;
+        let (column, row) = (position % columns, position / columns);
+        let label = &labels[index];
+
+        // Rows count down from the top of the sheet
+        let cell_left = layout.margin + column as f64 * (cell_width + layout.gutter);
+        let cell_bottom = layout.sheet_height - layout.margin - (row + 1) as f64 * cell_height - row as f64 * layout.gutter;
+        let x = cell_left + (cell_width - label.width) / 2.0;
+        let y = cell_bottom + (cell_height - label.height) / 2.0;
+
+        if sheets.len() <= sheet {
+            sheets.push(Vec::new());
+        }
+        sheets[sheet].push((index, x, y));
+    }
+
+    // Replace the page tree with one page per sheet
+    let pages_id = document.new_object_id();
+    let mut kids = Vec::with_capacity(sheets.len());
+    for placements in &sheets {
+        let mut content = String::new();
+        let mut xobjects = Dictionary::new();
+        for &(index, x, y) in placements {
+            let label = &labels[index];
+            let name = format!("L{}", index);
+            let [a, b, c, d, e, f] = label.matrix;
+            content.push_str(&format!(
+                "q 1 0 0 1 {:.3} {:.3} cm {:.4} {:.4} {:.4} {:.4} {:.3} {:.3} cm /{} Do Q\n",
+                x, y, a, b, c, d, e, f, name
+            ));
+            xobjects.set(name, label.id);
+        }
+
+        let content_id = document.add_object(Stream::new(Dictionary::new(), content.into_bytes()));
+        let mut resources = Dictionary::new();
+        resources.set("XObject", xobjects);
+        let mut page = Dictionary::new();
+        page.set("Type", Object::Name(b"Page".to_vec()));
+        page.set("Parent", pages_id);
+        page.set(
+            "MediaBox",
+            vec![0.into(), 0.into(), Object::Real(layout.sheet_width as f32), Object::Real(layout.sheet_height as f32)],
+        );
+        page.set("Resources", resources);
+        page.set("Contents", content_id);
+        kids.push(Object::Reference(document.add_object(page)));
+    }
+
+    let mut pages = Dictionary::new();
+    pages.set("Type", Object::Name(b"Pages".to_vec()));
+    pages.set("Count", kids.len() as i64);
+    pages.set("Kids", kids);
+    document.objects.insert(pages_id, Object::Dictionary(pages));
+
+    let catalog_id = document.trailer.get(b"Root").and_then(Object::as_reference).map_err(|e| e.to_string())?;
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", Object::Name(b"Catalog".to_vec()));
+    catalog.set("Pages", pages_id);
+    document.objects.insert(catalog_id, Object::Dictionary(catalog));
+
+    // Drop the old page tree, outlines and anything else only they referenced
+    document.prune_objects();
+    document.compress();
+
+    tracing::info!(
+        "Imposed {} label(s) onto {} sheet(s), {}x{} grid starting at position {}",
+        labels.len(),
+        sheets.len(),
+        columns,
+        rows,
+        layout.skip + 1
+    );
+
+    let mut output = Vec::new();
+    document
+        .save_to(&mut output)
+        .map_err(|e| format!("Could not write imposed PDF: {}", e))?;
+    Ok(output)
+}
+
+/// A page's visible box (CropBox, else MediaBox) and its /Rotate in degrees (0-359)
+fn page_geometry(document: &Document, page_id: ObjectId) -> Result<([f64; 4], i64), String> {
+    let page_box = page_attribute(document, page_id, b"CropBox")
+        .or_else(|| page_attribute(document, page_id, b"MediaBox"))
+        .ok_or("no page size")?;
+    let coordinates = page_box
+        .as_array()
+        .ok()
+        .filter(|values| values.len() == 4)
+        .and_then(|values| values.iter().map(|v| v.as_float().ok().map(f64::from)).collect::<Option<Vec<f64>>>())
+        .ok_or("invalid page box")?;
+    let coordinates = [coordinates[0], coordinates[1], coordinates[2], coordinates[3]];
+    if coordinates[0] == coordinates[2] || coordinates[1] == coordinates[3] {
+        return Err("empty page box".to_string());
+    }
+
+    let rotate = page_attribute(document, page_id, b"Rotate")
+        .and_then(|value| value.as_i64().ok())
+        .unwrap_or(0)
+        .rem_euclid(360);
+    Ok((coordinates, rotate))
+}
+
+/// Turn a page into a form XObject drawing its content, honouring /Rotate
+fn label_form(document: &mut Document, page_id: ObjectId) -> Result<LabelForm, String> {
+    let (coordinates, rotate) = page_geometry(document, page_id)?;
+    let (left, bottom) = (coordinates[0].min(coordinates[2]), coordinates[1].min(coordinates[3]));
+    let width = (coordinates[2] - coordinates[0]).abs();
+    let height = (coordinates[3] - coordinates[1]).abs();
+
+    // Pages are drawn from their box corner; a rotated page is turned clockwise as a viewer shows it
+    let (a, b, c, d, e, f, shown_width, shown_height) = match rotate {
+        90 => (0.0, -1.0, 1.0, 0.0, 0.0, width, height, width),
+        180 => (-1.0, 0.0, 0.0, -1.0, width, height, width, height),
+        270 => (0.0, 1.0, -1.0, 0.0, height, 0.0, height, width),
+        _ => (1.0, 0.0, 0.0, 1.0, 0.0, 0.0, width, height),
+    };
+    // Fold the move of the box corner to the origin into the transform
+    let matrix = [a, b, c, d, e - a * left - c * bottom, f - b * left - d * bottom];
+
+    let content = document.get_page_content(page_id).map_err(|e| e.to_string())?;
+    let mut form = Dictionary::new();
+    form.set("Type", Object::Name(b"XObject".to_vec()));
+    form.set("Subtype", Object::Name(b"Form".to_vec()));
+    form.set(
+        "BBox",
+        vec![
+            Object::Real(coordinates[0] as f32),
+            Object::Real(coordinates[1] as f32),
+            Object::Real(coordinates[2] as f32),
+            Object::Real(coordinates[3] as f32),
+        ],
+    );
+    if let Some(resources) = page_attribute(document, page_id, b"Resources") {
+        form.set("Resources", resources);
+    }
+    let id = document.add_object(Stream::new(form, content));
+
+    Ok(LabelForm {
+        id,
+        matrix,
+        width: shown_width,
+        height: shown_height,
+    })
+}
+
+/// A page attribute, inherited from the page tree if the page doesn't set it, with references resolved
+fn page_attribute(document: &Document, page_id: ObjectId, key: &[u8]) -> Option<Object> {
+    let value = match document.get_dictionary(page_id).ok()?.get(key) {
+        Ok(value) => value.clone(),
+        Err(_) => inherited_attribute(document, page_id, key)?,
+    };
+    match value {
+        Object::Reference(id) => document.get_object(id).ok().cloned(),
+        value => Some(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LETTER_MM: (f64, f64) = (215.9, 279.4);
+
+    /// A PDF of `count` blank pages of the given size
+    fn labels_pdf(count: usize, width_mm: f64, height_mm: f64) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let mut kids = Vec::with_capacity(count);
+        for _ in 0..count {
+            let content_id = document.add_object(Stream::new(Dictionary::new(), b"0 0 m 10 10 l S".to_vec()));
+            let mut page = Dictionary::new();
+            page.set("Type", Object::Name(b"Page".to_vec()));
+            page.set("Parent", pages_id);
+            page.set(
+                "MediaBox",
+                vec![
+                    0.into(),
+                    0.into(),
+                    Object::Real((width_mm * POINTS_PER_MM) as f32),
+                    Object::Real((height_mm * POINTS_PER_MM) as f32),
+                ],
+            );
+            page.set("Contents", content_id);
+            kids.push(Object::Reference(document.add_object(page)));
+        }
+
+        let mut pages = Dictionary::new();
+        pages.set("Type", Object::Name(b"Pages".to_vec()));
+        pages.set("Count", count as i64);
+        pages.set("Kids", kids);
+        document.objects.insert(pages_id, Object::Dictionary(pages));
+        let mut catalog = Dictionary::new();
+        catalog.set("Type", Object::Name(b"Catalog".to_vec()));
+        catalog.set("Pages", pages_id);
+        let catalog_id = document.add_object(catalog);
+        document.trailer.set("Root", catalog_id);
+
+        let mut data = Vec::new();
+        document.save_to(&mut data).unwrap();
+        data
+    }
+
+    /// Where each label of each sheet was placed, in millimetres from the bottom left
+    fn placements(imposed: &[u8]) -> Vec<Vec<(f64, f64)>> {
+        let document = Document::load_mem(imposed).unwrap();
+        document
+            .get_pages()
+            .into_values()
+            .map(|page_id| {
+                let content = document.get_page_content(page_id).unwrap();
+                String::from_utf8_lossy(&content)
+                    .lines()
+                    .map(|line| {
+                        let numbers: Vec<f64> = line.split(' ').filter_map(|part| part.parse().ok()).collect();
+                        (numbers[4] / POINTS_PER_MM, numbers[5] / POINTS_PER_MM)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn assert_near(actual: (f64, f64), expected: (f64, f64)) {
+        let close = (actual.0 - expected.0).abs() < 0.1 && (actual.1 - expected.1).abs() < 0.1;
+        assert!(close, "placed at {:?}, expected {:?}", actual, expected);
+    }
+
+    #[test]
+    fn four_by_six_labels_go_two_up_on_letter() {
+        let layout = SheetLayout::from_mm(LETTER_MM, Some((1, 2)), 0.0, 0.0, 0);
+        let sheets = placements(&impose(&labels_pdf(3, 101.6, 152.4), &layout).unwrap());
+
+        // Turned sideways and centered in the top and bottom halves
+        assert_eq!(sheets.len(), 2);
+        assert_eq!(sheets[0].len(), 2);
+        assert_near(sheets[0][0], (31.75, 158.75));
+        assert_near(sheets[0][1], (31.75, 19.05));
+        assert_eq!(sheets[1].len(), 1);
+        assert_near(sheets[1][0], (31.75, 158.75));
+    }
+
+    #[test]
+    fn start_position_skips_used_labels() {
+        let layout = SheetLayout::from_mm(LETTER_MM, Some((1, 2)), 0.0, 0.0, 1);
+        let sheets = placements(&impose(&labels_pdf(2, 101.6, 152.4), &layout).unwrap());
+
+        assert_eq!(sheets.len(), 2);
+        assert_eq!(sheets[0].len(), 1);
+        assert_near(sheets[0][0], (31.75, 19.05));
+        assert_near(sheets[1][0], (31.75, 158.75));
+
+        let past_the_sheet = SheetLayout::from_mm(LETTER_MM, Some((1, 2)), 0.0, 0.0, 2);
+        assert!(impose(&labels_pdf(1, 101.6, 152.4), &past_the_sheet).is_err());
+    }
+
+    #[test]
+    fn labels_larger_than_a_cell_are_rejected() {
+        let layout = SheetLayout::from_mm(LETTER_MM, Some((2, 2)), 0.0, 0.0, 0);
+        let error = impose(&labels_pdf(1, 101.6, 152.4), &layout).unwrap_err();
+        assert!(error.contains("larger than a grid cell"), "{}", error);
+    }
+
+    #[test]
+    fn automatic_grids_are_capped() {
+        // 2x1 mm labels would make a 107x279 grid on letter
+        let layout = SheetLayout::from_mm(LETTER_MM, None, 0.0, 0.0, 0);
+        let error = impose(&labels_pdf(1, 2.0, 1.0), &layout).unwrap_err();
+        assert!(error.contains("labels per sheet"), "{}", error);
+
+        // An infinite sheet fits u32::MAX labels each way, which would overflow
+        let layout = SheetLayout::from_mm((f64::INFINITY, f64::INFINITY), None, 0.0, 0.0, 0);
+        assert!(impose(&labels_pdf(1, 101.6, 152.4), &layout).is_err());
+
+        // 4x6 labels fit two across letter, upright
+        let layout = SheetLayout::from_mm(LETTER_MM, None, 0.0, 0.0, 0);
+        let sheets = placements(&impose(&labels_pdf(2, 101.6, 152.4), &layout).unwrap());
+        assert_eq!(sheets.len(), 1);
+        assert_near(sheets[0][0], (3.175, 63.5));
+        assert_near(sheets[0][1], (111.125, 63.5));
+    }
+}
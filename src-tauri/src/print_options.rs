@@ -0,0 +1,614 @@
+//! Per-request print settings (orientation, page ranges, media, duplex, color, tray, scaling)
+//!
+//! Settings arrive as multipart text fields or JSON, are validated against the printer's
+//! reported options, and are translated to `lp -o` options (CUPS), IPP job attributes (direct
+//! IPP printing) or DEVMODE fields (Windows GDI).
+
+use crate::ipp::{IppAttribute, IppValue};
+use crate::pdf::{SheetLayout, MAX_LABELS_PER_SHEET};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Page orientation
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+/// One- or two-sided printing (IPP `sides` values)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Duplex {
+    OneSided,
+    TwoSidedLongEdge,
+    TwoSidedShortEdge,
+}
+
+/// Color or grayscale output
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorMode {
+    Color,
+    Monochrome,
+}
+
+/// How the document is scaled onto the page
+/// Labels must print at 100%, so actual size is the default.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scaling {
+    #[default]
+    ActualSize,
+    Fit,
+    Fill,
+}
+
+/// Printer language of a raw job, sent to the printer as-is
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RawFormat {
+    /// Zebra Programming Language
+    #[default]
+    Zpl,
+    /// Eltron Programming Language
+    Epl,
+    /// Epson ESC/POS (receipt printers)
+    #[serde(alias = "esc-pos")]
+    Escpos,
+}
+
+impl RawFormat {
+    /// Parse a form field value ("zpl", "epl", "escpos")
+    pub fn parse(text: &str) -> Result<Self, String> {
+        parse_value("format", text)
+    }
+
+    /// Catch obviously wrong uploads before they reach the printer
+    /// Only ZPL has a reliable marker; EPL and ESC/POS are accepted as long as they aren't a PDF.
+    pub fn check(self, scan: &RawScan) -> Result<(), String> {
+        if scan.start.starts_with(b"%PDF") {
+            return Err("Data is a PDF, not printer commands - send PDFs to /print".to_string());
+        }
+        if self == RawFormat::Zpl && !scan.label_start {
+            return Err("Data does not look like ZPL (no ^XA label start)".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What `RawFormat::check` looks at, gathered chunk by chunk while raw data is received
+/// (the format field may come after the data in a form)
+#[derive(Default)]
+pub struct RawScan {
+    start: Vec<u8>,
+    /// End of the previous chunk, so a `^XA` split across chunks is still found
+    carry: Vec<u8>,
+    label_start: bool,
+}
+
+impl RawScan {
+    pub fn new(data: &[u8]) -> Self {
+        let mut scan = Self::default();
+        scan.update(data);
+        scan
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        let wanted = 4usize.saturating_sub(self.start.len()).min(chunk.len());
+        self.start.extend_from_slice(&chunk[..wanted]);
+        if self.label_start {
+            return;
+        }
+
+        let mut seam = std::mem::take(&mut self.carry);
+        seam.extend_from_slice(&chunk[..chunk.len().min(2)]);
+        self.label_start = [seam.as_slice(), chunk].iter().any(|bytes| bytes.windows(3).any(|w| w == b"^XA"));
+        self.carry = match chunk.len() {
+            0 | 1 => seam[seam.len().saturating_sub(2)..].to_vec(),
+            n => chunk[n - 2..].to_vec(),
+        };
+    }
+}
+
+/// Optional settings for a single print request
+/// Anything left unset falls back to the printer profile / driver defaults.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PrintSettings {
+    pub orientation: Option<Orientation>,
+    /// Pages to print, e.g. "1-3,5"
+    pub page_ranges: Option<String>,
+    /// Media size name as the printer reports it, e.g. "Letter", "A4", "4x6", "w288h432"
+    pub media_size: Option<String>,
+    /// Media type as the printer reports it (CUPS), or a numeric driver media ID (Windows)
+    pub media_type: Option<String>,
+    pub duplex: Option<Duplex>,
+    pub color_mode: Option<ColorMode>,
+    /// Input tray / paper source as the printer reports it (CUPS), or a numeric bin ID (Windows)
+    pub tray: Option<String>,
+    pub scaling: Option<Scaling>,
+    /// N-up label sheet layout (off unless `sheetSize` is set)
+    #[serde(flatten)]
+    pub imposition: Imposition,
+}
+
+/// N-up imposition - label pages placed on a larger sheet in a grid, at 100% scale
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Imposition {
+    /// Sheet to place labels on, e.g. "Letter"; turns imposition on
+    pub sheet_size: Option<String>,
+    /// Columns x rows, e.g. "1x2"; as many labels as fit when unset
+    pub grid: Option<String>,
+    /// Space between the sheet edge and the grid
+    pub sheet_margin_mm: Option<f64>,
+    /// Space between labels
+    pub gutter_mm: Option<f64>,
+    /// First free position on a partly used sheet, from 1 (top left, across then down)
+    pub start_position: Option<u32>,
+}
+
+impl Imposition {
+    /// Whether labels should be imposed onto sheets
+    pub fn is_enabled(&self) -> bool {
+        self.sheet_size.is_some()
+    }
+
+    /// Sheet layout for these settings, or None when imposition is off
+    pub fn layout(&self) -> Result<Option<SheetLayout>, String> {
+        let Some(sheet_size) = &self.sheet_size else {
+            return Ok(None);
+        };
+
+        let (width_mm, height_mm, _) = media_size_dimensions(sheet_size)
+            .ok_or_else(|| format!("Unknown sheet size '{}'", sheet_size))?;
+        let grid = self.grid.as_deref().map(parse_grid).transpose()?;
+        let margin_mm = self.sheet_margin_mm.unwrap_or(0.0);
+        let gutter_mm = self.gutter_mm.unwrap_or(0.0);
+        if !margin_mm.is_finite() || !gutter_mm.is_finite() {
+            return Err("sheetMarginMm and gutterMm must be numbers".to_string());
+        }
+        if margin_mm < 0.0 || gutter_mm < 0.0 {
+            return Err("sheetMarginMm and gutterMm can't be negative".to_string());
+        }
+
+        let start_position = self.start_position.unwrap_or(1);
+        if start_position == 0 {
+            return Err("startPosition counts from 1".to_string());
+        }
+        if let Some((columns, rows)) = grid {
+            if start_position > columns * rows {
+                return Err(format!(
+                    "startPosition {} is past the {} positions of a {}x{} sheet",
+                    start_position,
+                    columns * rows,
+                    columns,
+                    rows
+                ));
+            }
+        }
+
+        Ok(Some(SheetLayout::from_mm(
+            (width_mm, height_mm),
+            grid,
+            margin_mm,
+            gutter_mm,
+            start_position - 1,
+        )))
+    }
+}
+
+/// Parse a "columns x rows" grid, e.g. "2x1"
+fn parse_grid(grid: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid grid '{}' (expected columns x rows, e.g. \"1x2\")", grid);
+    let lower = grid.trim().to_lowercase();
+    let (columns, rows) = lower.split_once('x').ok_or_else(invalid)?;
+    let columns: u32 = columns.trim().parse().map_err(|_| invalid())?;
+    let rows: u32 = rows.trim().parse().map_err(|_| invalid())?;
+    if columns == 0 || rows == 0 || columns.checked_mul(rows).filter(|n| *n <= MAX_LABELS_PER_SHEET).is_none() {
+        return Err(invalid());
+    }
+    Ok((columns, rows))
+}
+
+/// A supported value for a printer setting
+#[derive(Serialize, Clone, Debug)]
+pub struct CapabilityChoice {
+    /// Value to send back in the print options
+    pub value: String,
+    /// Human-readable name
+    pub label: String,
+}
+
+impl CapabilityChoice {
+    pub fn new(value: impl Into<String>, label: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            label: label.into(),
+        }
+    }
+
+    /// Whether a requested value refers to this choice (by value or label, ignoring case)
+    pub fn matches(&self, requested: &str) -> bool {
+        self.value.eq_ignore_ascii_case(requested) || self.label.eq_ignore_ascii_case(requested)
+    }
+}
+
+/// What a printer supports, for building label settings UI and validating requests
+#[derive(Serialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PrinterCapabilities {
+    pub printer: String,
+    pub media_sizes: Vec<CapabilityChoice>,
+    pub default_media_size: Option<String>,
+    /// Whether sizes not in `media_sizes` can be requested by dimensions (e.g. "4x6", "w288h432")
+    pub custom_media_sizes: bool,
+    pub media_types: Vec<CapabilityChoice>,
+    pub default_media_type: Option<String>,
+    /// Resolutions such as "600x600dpi"
+    pub resolutions: Vec<String>,
+    pub default_resolution: Option<String>,
+    pub trays: Vec<CapabilityChoice>,
+    pub default_tray: Option<String>,
+    pub color_supported: bool,
+    pub duplex_supported: bool,
+}
+
+/// Known media sizes: (name, width mm, height mm, Windows DMPAPER code or 0 for custom)
+const MEDIA_SIZES: &[(&str, f64, f64, i16)] = &[
+    ("letter", 215.9, 279.4, 1),
+    ("legal", 215.9, 355.6, 5),
+    ("a4", 210.0, 297.0, 9),
+    ("a5", 148.0, 210.0, 11),
+    ("a6", 105.0, 148.0, 70),
+    ("4x6", 101.6, 152.4, 0),
+    ("4x4", 101.6, 101.6, 0),
+    ("2x1", 50.8, 25.4, 0),
+];
+
+/// Parse a single text value using the same names as the JSON API
+fn parse_value<T: DeserializeOwned>(field: &str, text: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(text.trim().to_lowercase()))
+        .map_err(|_| format!("Invalid value '{}' for {}", text, field))
+}
+
+/// Parse a numeric form field; empty means "not set"
+fn parse_number<T: std::str::FromStr>(field: &str, text: &str) -> Result<Option<T>, String> {
+    non_empty(text)
+        .map(|value| value.parse().map_err(|_| format!("Invalid value '{}' for {}", value, field)))
+        .transpose()
+}
+
+/// Parse a length in millimetres; "NaN" and "inf" parse as numbers but aren't lengths
+fn parse_length(field: &str, text: &str) -> Result<Option<f64>, String> {
+    match parse_number::<f64>(field, text)? {
+        Some(value) if !value.is_finite() => Err(format!("Invalid value '{}' for {}", text.trim(), field)),
+        value => Ok(value),
+    }
+}
+
+/// Check a printer's own value (media size, media type, tray) can be passed on as one option
+/// `lp -o` splits its argument on whitespace and quotes, and SumatraPDF's settings are separated by
+/// commas, so any of these (or an `=`) would let a value add options of its own.
+fn check_option_value(field: &str, value: Option<&str>) -> Result<(), String> {
+    let Some(value) = value else {
+        return Ok(());
+    };
+    let splits = |c: char| c.is_whitespace() || c.is_control() || matches!(c, '"' | '\'' | '\\' | ',' | '=');
+    if value.chars().any(splits) {
+        return Err(format!(
+            "Invalid value '{}' for {} (spaces, quotes, commas and '=' are not allowed)",
+            value, field
+        ));
+    }
+    Ok(())
+}
+
+/// Treat empty form fields as "not set"
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+impl PrintSettings {
+    /// Apply a multipart form field; unknown field names are ignored
+    /// Accepts both camelCase and snake_case names (`pageRanges` / `page_ranges`)
+    pub fn set_field(&mut self, name: &str, text: &str) -> Result<(), String> {
+        let key = name.replace('_', "").to_lowercase();
+        match key.as_str() {
+            "orientation" => self.orientation = Some(parse_value(name, text)?),
+            "pageranges" => self.page_ranges = non_empty(text),
+            "mediasize" | "media" => self.media_size = non_empty(text),
+            "mediatype" => self.media_type = non_empty(text),
+            "duplex" | "sides" => self.duplex = Some(parse_value(name, text)?),
+            "colormode" => self.color_mode = Some(parse_value(name, text)?),
+            "tray" | "source" => self.tray = non_empty(text),
+            "scaling" => self.scaling = Some(parse_value(name, text)?),
+            "sheetsize" => self.imposition.sheet_size = non_empty(text),
+            "grid" => self.imposition.grid = non_empty(text),
+            "sheetmarginmm" => self.imposition.sheet_margin_mm = parse_length(name, text)?,
+            "guttermm" => self.imposition.gutter_mm = parse_length(name, text)?,
+            "startposition" => self.imposition.start_position = parse_number(name, text)?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check the settings are well-formed and, when the printer's capabilities are known,
+    /// that it supports them
+    pub fn validate(
+        &self,
+        printer_name: &str,
+        capabilities: Option<&PrinterCapabilities>,
+    ) -> Result<(), String> {
+        if let Some(ranges) = &self.page_ranges {
+            parse_page_ranges(ranges)?;
+        }
+        check_option_value("mediaSize", self.media_size.as_deref())?;
+        check_option_value("mediaType", self.media_type.as_deref())?;
+        check_option_value("tray", self.tray.as_deref())?;
+        if self.imposition.is_enabled() {
+            self.imposition.layout()?;
+            // Labels are placed at their real size; scaling would have to apply to the whole sheet
+            if self.scaling() != Scaling::ActualSize {
+                return Err("Imposition prints labels at 100%, so scaling must be actual-size".to_string());
+            }
+            if self.page_ranges.is_some() {
+                return Err("pageRanges can't be combined with imposition".to_string());
+            }
+        }
+
+        let Some(caps) = capabilities else {
+            return Ok(());
+        };
+
+        let check_choice = |label: &str, value: &Option<String>, choices: &[CapabilityChoice]| -> Result<(), String> {
+            let Some(value) = value else {
+                return Ok(());
+            };
+            if choices.is_empty() || choices.iter().any(|c| c.matches(value)) {
+                Ok(())
+            } else {
+                let supported: Vec<&str> = choices.iter().map(|c| c.value.as_str()).collect();
+                Err(format!(
+                    "Printer '{}' does not support {} '{}' (supported: {})",
+                    printer_name,
+                    label,
+                    value,
+                    supported.join(", ")
+                ))
+            }
+        };
+
+        // Custom sizes (e.g. 4x6 on a driver without that named size) are fine if the printer takes them
+        let custom_size_ok = caps.custom_media_sizes
+            && self.media_size.as_deref().and_then(media_size_dimensions).is_some();
+        if !custom_size_ok {
+            check_choice("media size", &self.media_size, &caps.media_sizes)?;
+        }
+        check_choice("media type", &self.media_type, &caps.media_types)?;
+        check_choice("tray", &self.tray, &caps.trays)?;
+
+        if matches!(self.duplex, Some(Duplex::TwoSidedLongEdge | Duplex::TwoSidedShortEdge)) && !caps.duplex_supported {
+            return Err(format!("Printer '{}' does not support two-sided printing", printer_name));
+        }
+
+        if self.color_mode == Some(ColorMode::Color) && !caps.color_supported {
+            return Err(format!("Printer '{}' does not support color printing", printer_name));
+        }
+
+        Ok(())
+    }
+
+    /// Scaling to use (actual size unless requested otherwise)
+    pub fn scaling(&self) -> Scaling {
+        self.scaling.unwrap_or_default()
+    }
+
+    /// The page ranges as parsed, written out again as "1-3,5" (None when unset or invalid)
+    /// Backends are given this rather than the text of the request.
+    pub fn page_list(&self) -> Option<String> {
+        let ranges = parse_page_ranges(self.page_ranges.as_deref()?).ok()?;
+        let ranges: Vec<String> = ranges
+            .iter()
+            .map(|&(first, last)| {
+                if first == last {
+                    first.to_string()
+                } else {
+                    format!("{}-{}", first, last)
+                }
+            })
+            .collect();
+        Some(ranges.join(","))
+    }
+
+    /// `lp -o` options for these settings
+    /// Added after the profile options so per-request values take precedence.
+    pub fn cups_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+
+        match self.scaling() {
+            Scaling::ActualSize => {
+                options.push("fit-to-page=false".to_string());
+                options.push("scaling=100".to_string());
+            }
+            Scaling::Fit => {
+                options.push("fit-to-page=true".to_string());
+                options.push("print-scaling=fit".to_string());
+            }
+            Scaling::Fill => {
+                options.push("print-scaling=fill".to_string());
+            }
+        }
+
+        if let Some(orientation) = self.orientation {
+            // IPP orientation-requested: 3 = portrait, 4 = landscape
+            let value = match orientation {
+                Orientation::Portrait => 3,
+                Orientation::Landscape => 4,
+            };
+            options.push(format!("orientation-requested={}", value));
+        }
+        if let Some(ranges) = self.page_list() {
+            options.push(format!("page-ranges={}", ranges));
+        }
+        if let Some(media_size) = &self.media_size {
+            options.push(format!("media={}", media_size));
+        }
+        if let Some(media_type) = &self.media_type {
+            options.push(format!("MediaType={}", media_type));
+        }
+        if let Some(duplex) = self.duplex {
+            let value = match duplex {
+                Duplex::OneSided => "one-sided",
+                Duplex::TwoSidedLongEdge => "two-sided-long-edge",
+                Duplex::TwoSidedShortEdge => "two-sided-short-edge",
+            };
+            options.push(format!("sides={}", value));
+        }
+        if let Some(color_mode) = self.color_mode {
+            let value = match color_mode {
+                ColorMode::Color => "color",
+                ColorMode::Monochrome => "monochrome",
+            };
+            options.push(format!("print-color-mode={}", value));
+        }
+        if let Some(tray) = &self.tray {
+            options.push(format!("InputSlot={}", tray));
+        }
+
+        options
+    }
+
+    /// IPP Everywhere job attributes for printing straight to a network printer
+    /// Media type and tray go in `media-col`; sizes that aren't PWG names (e.g. "4x6") are sent as dimensions.
+    pub fn ipp_attributes(&self, copies: u32) -> Vec<IppAttribute> {
+        let mut attributes = vec![IppAttribute::new("copies", IppValue::Integer(copies as i32))];
+
+        let print_scaling = match self.scaling() {
+            Scaling::ActualSize => "none",
+            Scaling::Fit => "fit",
+            Scaling::Fill => "fill",
+        };
+        attributes.push(IppAttribute::new("print-scaling", IppValue::keyword(print_scaling)));
+
+        if let Some(orientation) = self.orientation {
+            let value = match orientation {
+                Orientation::Portrait => 3,
+                Orientation::Landscape => 4,
+            };
+            attributes.push(IppAttribute::new("orientation-requested", IppValue::Enum(value)));
+        }
+        if let Some(ranges) = self.page_ranges.as_deref().and_then(|r| parse_page_ranges(r).ok()) {
+            attributes.push(IppAttribute {
+                name: "page-ranges".to_string(),
+                values: ranges
+                    .into_iter()
+                    .map(|(first, last)| IppValue::Range(first as i32, last as i32))
+                    .collect(),
+            });
+        }
+        if let Some(duplex) = self.duplex {
+            let value = match duplex {
+                Duplex::OneSided => "one-sided",
+                Duplex::TwoSidedLongEdge => "two-sided-long-edge",
+                Duplex::TwoSidedShortEdge => "two-sided-short-edge",
+            };
+            attributes.push(IppAttribute::new("sides", IppValue::keyword(value)));
+        }
+        if let Some(color_mode) = self.color_mode {
+            let value = match color_mode {
+                ColorMode::Color => "color",
+                ColorMode::Monochrome => "monochrome",
+            };
+            attributes.push(IppAttribute::new("print-color-mode", IppValue::keyword(value)));
+        }
+
+        // PWG media names ("na_letter_8.5x11in") are sent by name, anything else as dimensions
+        let mut media_col = Vec::new();
+        let mut media = None;
+        if let Some(size) = &self.media_size {
+            if size.contains('_') {
+                media_col.push(IppAttribute::new("media-size-name", IppValue::keyword(size)));
+                media = Some(size.clone());
+            } else if let Some((width_mm, height_mm, _)) = media_size_dimensions(size) {
+                // media-size is in hundredths of a millimetre
+                let dimensions = IppValue::Collection(vec![
+                    IppAttribute::new("x-dimension", IppValue::Integer((width_mm * 100.0).round() as i32)),
+                    IppAttribute::new("y-dimension", IppValue::Integer((height_mm * 100.0).round() as i32)),
+                ]);
+                media_col.push(IppAttribute::new("media-size", dimensions));
+            } else {
+                // Unknown name, let the printer decide
+                media = Some(size.clone());
+            }
+        }
+        if let Some(media_type) = &self.media_type {
+            media_col.push(IppAttribute::new("media-type", IppValue::keyword(media_type)));
+        }
+        if let Some(tray) = &self.tray {
+            media_col.push(IppAttribute::new("media-source", IppValue::keyword(tray)));
+        }
+
+        // A plain `media` keyword is the most widely supported when only the size is set
+        match media {
+            Some(name) if self.media_type.is_none() && self.tray.is_none() => {
+                attributes.push(IppAttribute::new("media", IppValue::keyword(name)));
+            }
+            _ if !media_col.is_empty() => {
+                attributes.push(IppAttribute::new("media-col", IppValue::Collection(media_col)));
+            }
+            _ => {}
+        }
+
+        attributes
+    }
+}
+
+/// Parse "1-3,5" into inclusive (first, last) page pairs
+pub fn parse_page_ranges(ranges: &str) -> Result<Vec<(u32, u32)>, String> {
+    let invalid = || format!("Invalid page ranges '{}' (expected e.g. \"1-3,5\")", ranges);
+
+    ranges
+        .split(',')
+        .map(|part| {
+            let part = part.trim();
+            let (first, last) = match part.split_once('-') {
+                Some((a, b)) => (a.trim(), b.trim()),
+                None => (part, part),
+            };
+            let first: u32 = first.parse().map_err(|_| invalid())?;
+            let last: u32 = last.parse().map_err(|_| invalid())?;
+            if first == 0 || last < first {
+                return Err(invalid());
+            }
+            Ok((first, last))
+        })
+        .collect()
+}
+
+/// Look up a media size by name: (width mm, height mm, Windows DMPAPER code or 0 for custom)
+/// Also understands CUPS custom sizes in points, e.g. "w288h432"; dimensions must be positive
+/// numbers ("NaN", "inf" and 0 are not sizes)
+pub fn media_size_dimensions(name: &str) -> Option<(f64, f64, i16)> {
+    let lower = name.trim().to_lowercase();
+
+    if let Some(&(_, width, height, code)) = MEDIA_SIZES.iter().find(|(n, ..)| *n == lower) {
+        return Some((width, height, code));
+    }
+
+    let (w, h) = lower.strip_prefix('w')?.split_once('h')?;
+    let points_to_mm = |pt: &str| {
+        pt.parse::<f64>()
+            .ok()
+            .filter(|pt| pt.is_finite() && *pt > 0.0)
+            .map(|pt| pt * 25.4 / 72.0)
+    };
+    Some((points_to_mm(w)?, points_to_mm(h)?, 0))
+}
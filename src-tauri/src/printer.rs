@@ -1,66 +1,841 @@
 //! Printer functionality - cross-platform PDF printing
 
+use crate::ipp;
+use crate::jobs::{self, JobStatus};
+use crate::pdf;
+use crate::config;
+use crate::error::HelperError;
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+use crate::config::PrintBackend;
+use crate::print_options::{PrintSettings, PrinterCapabilities, RawFormat};
+use crate::profiles;
+use crate::zpl;
 use crate::server::PrinterInfo;
+use crate::spool;
 use std::process::Command;
-use tempfile::NamedTempFile;
 use std::io::Write;
-use std::path::PathBuf;
-use uuid::Uuid;
+use std::path::{Path, PathBuf};
 
 #[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
 
+use crate::print_options::{media_size_dimensions, Orientation, Scaling};
+#[cfg(target_os = "windows")]
+use crate::print_options::{ColorMode, Duplex};
+
 /// Windows flag to hide console window
 #[cfg(target_os = "windows")]
 const CREATE_NO_WINDOW: u32 = 0x08000000;
 
 /// List available printers on the system
-pub fn list_printers() -> Result<Vec<PrinterInfo>, Box<dyn std::error::Error>> {
+pub fn list_printers() -> Result<Vec<PrinterInfo>, HelperError> {
+    #[cfg(target_os = "windows")]
+    let printers = list_printers_windows();
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    let printers = list_printers_unix();
+
+    #[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+    {
+        printers.map_err(|e| match e.downcast_ref::<std::io::Error>() {
+            Some(io_error) if io_error.kind() == std::io::ErrorKind::NotFound => {
+                HelperError::BackendMissing(format!("Printer list unavailable: {}", io_error))
+            }
+            _ => HelperError::Internal(format!("Could not list printers: {}", e)),
+        })
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+    {
+        Ok(vec![])
+    }
+}
+
+/// Check the printer exists and per-request print settings against what it reports it supports
+pub fn validate_settings(printer_name: Option<&str>, settings: &PrintSettings) -> Result<(), HelperError> {
+    // Network printers addressed by URI take IPP keywords; their capabilities aren't queried
+    if let Some(uri) = printer_name.filter(|p| ipp::is_printer_uri(p)) {
+        ipp::PrinterUri::parse(uri).map_err(|e| HelperError::InvalidOptions(e.to_string()))?;
+        return settings.validate(uri, None).map_err(HelperError::InvalidOptions);
+    }
+    check_printer_exists(printer_name)?;
+
+    // Windows drivers take numeric media type and paper source IDs
+    #[cfg(target_os = "windows")]
+    {
+        if settings.media_type.as_ref().is_some_and(|v| v.parse::<u32>().is_err()) {
+            return Err(HelperError::InvalidOptions(
+                "On Windows, mediaType must be a numeric driver media type ID".to_string(),
+            ));
+        }
+        if settings.tray.as_ref().is_some_and(|v| v.parse::<u32>().is_err()) {
+            return Err(HelperError::InvalidOptions(
+                "On Windows, tray must be a numeric paper source ID".to_string(),
+            ));
+        }
+    }
+
+    let capabilities = get_capabilities(printer_name);
+    settings
+        .validate(printer_name.unwrap_or("default"), capabilities.as_ref())
+        .map_err(HelperError::InvalidOptions)
+}
+
+/// Page and media sizes closer than this, in millimetres, count as the same size
+const MEDIA_MATCH_TOLERANCE_MM: f64 = 3.0;
+
+/// Warnings for pages that don't match the media the job will print on
+/// The media is the requested `mediaSize`, else the printer's default (loaded) media. Nothing is
+/// checked when pages are scaled to the media or laid out on sheets, or when the media size is
+/// unknown.
+pub fn media_warnings(printer_name: Option<&str>, settings: &PrintSettings, pages: &[pdf::PageSize]) -> Vec<String> {
+    if settings.scaling() != Scaling::ActualSize || settings.imposition.is_enabled() {
+        return vec![];
+    }
+    let media = match &settings.media_size {
+        Some(media) => media.clone(),
+        None => match get_capabilities(printer_name).and_then(|caps| caps.default_media_size) {
+            Some(media) => media,
+            None => return vec![],
+        },
+    };
+    let Some((media_width, media_height, _)) = media_size_dimensions(&media) else {
+        return vec![];
+    };
+
+    let same = |width: f64, height: f64| {
+        (width - media_width).abs() <= MEDIA_MATCH_TOLERANCE_MM
+            && (height - media_height).abs() <= MEDIA_MATCH_TOLERANCE_MM
+    };
+    // Either orientation matches: the driver turns the page to fit the media
+    let mismatched: Vec<usize> = pages
+        .iter()
+        .enumerate()
+        .filter(|(_, page)| !same(page.width_mm, page.height_mm) && !same(page.height_mm, page.width_mm))
+        .map(|(index, _)| index + 1)
+        .collect();
+    let Some(&first) = mismatched.first() else {
+        return vec![];
+    };
+
+    let page = &pages[first - 1];
+    vec![format!(
+        "{} of {} page(s) don't match the {} media ({:.0}x{:.0} mm): page {} is {:.0}x{:.0} mm",
+        mismatched.len(),
+        pages.len(),
+        media,
+        media_width,
+        media_height,
+        first,
+        page.width_mm,
+        page.height_mm
+    )]
+}
+
+/// Fail with printer-not-found if a named printer isn't installed
+/// `ipp://` printers aren't installed anywhere and always pass, and so does any printer when the
+/// list can't be read (the print backend reports the problem then). `socket://` printers are
+/// refused: raw TCP targets come from printer profiles only (see `raw_socket_target`).
+pub fn check_printer_exists(printer_name: Option<&str>) -> Result<(), HelperError> {
+    let Some(name) = printer_name else {
+        return Ok(());
+    };
+    if ipp::is_printer_uri(name) {
+        return Ok(());
+    }
+    if is_socket_uri(name) {
+        return Err(socket_uri_refused(name));
+    }
+
+    match list_printers() {
+        Ok(printers) if !printers.iter().any(|printer| printer.name == name) => {
+            Err(HelperError::PrinterNotFound(format!("Printer {} not found", name)))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Get what a printer supports (media sizes/types, resolutions, trays, color, duplex)
+/// `None` for the printer name means the default printer.
+/// Returns None if the printer doesn't exist or its capabilities can't be read.
+pub fn get_capabilities(printer_name: Option<&str>) -> Option<PrinterCapabilities> {
+    if printer_name.is_some_and(ipp::is_printer_uri) {
+        return None;
+    }
+
     #[cfg(target_os = "windows")]
     {
-        list_printers_windows()
+        get_capabilities_windows(printer_name)
     }
 
     #[cfg(any(target_os = "macos", target_os = "linux"))]
     {
-        list_printers_unix()
+        get_capabilities_unix(printer_name)
     }
 
     #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
     {
-        Ok(vec![])
+        let _ = printer_name;
+        None
     }
 }
 
-/// Print a PDF file
+/// Print a PDF file as part of a tracked job
+/// `pdf_path` is the job's file in the spool directory. Jobs wait in the queue (Received) until
+/// earlier jobs have been handed to the spooler. Returns the spooler's own job ID when the
+/// backend reports one (e.g. CUPS "Printer-123").
 pub async fn print_pdf(
-    pdf_data: &[u8],
+    job_id: &str,
+    pdf_path: &Path,
+    printer_name: Option<&str>,
+    copies: u32,
+    settings: &PrintSettings,
+) -> Result<Option<String>, HelperError> {
+    let _queue_slot = jobs::PRINT_QUEUE.lock().await;
+    submit_tracked(job_id, pdf_path, printer_name, copies, settings).await
+}
+
+/// One document of a batch, already recorded as a job
+pub struct BatchDocument {
+    pub job_id: String,
+    /// The job's file in the spool directory
+    pub pdf_path: PathBuf,
+    pub printer_name: Option<String>,
+    pub copies: u32,
+    pub settings: PrintSettings,
+}
+
+/// Print several PDFs back to back as one batch
+/// The queue is held for the whole batch so no other job lands between its documents. A failed
+/// document doesn't stop the rest; the result for each document is returned in order.
+pub async fn print_batch(documents: &[BatchDocument]) -> Vec<Result<Option<String>, HelperError>> {
+    let _queue_slot = jobs::PRINT_QUEUE.lock().await;
+
+    let mut results = Vec::with_capacity(documents.len());
+    for document in documents {
+        let submitted = submit_tracked(
+            &document.job_id,
+            &document.pdf_path,
+            document.printer_name.as_deref(),
+            document.copies,
+            &document.settings,
+        )
+        .await;
+        results.push(submitted);
+    }
+    results
+}
+
+/// Submit a PDF and record the outcome on its job (the caller holds the queue)
+/// The spool file of a failed job is left for the retention sweep.
+async fn submit_tracked(
+    job_id: &str,
+    pdf_path: &Path,
+    printer_name: Option<&str>,
+    copies: u32,
+    settings: &PrintSettings,
+) -> Result<Option<String>, HelperError> {
+    let submitted = match spool_pdf(job_id, pdf_path, settings).await {
+        Ok((path, settings)) => {
+            let payload = Payload::Pdf { path: &path, settings: &settings };
+            deliver(job_id, &payload, printer_name, copies).await
+        }
+        Err(e) => Err(e),
+    };
+
+    match submitted {
+        Ok(submission) => Ok(finish_submission(job_id, submission)),
+        Err(e) => {
+            jobs::fail_job(job_id, &e);
+            Err(e)
+        }
+    }
+}
+
+/// Where a submitted job ended up
+enum Submission {
+    /// Queued in a spooler (CUPS, Windows, an IPP printer), with its job ID when known
+    Spooled(Option<String>),
+    /// Written straight to the printer over a raw socket - there is nothing left to follow
+    Delivered,
+}
+
+/// Record a successful submission on the job and start following it
+/// The job's spool file is kept while the job is followed, and released otherwise: the backend
+/// has read it by the time the submission returns. Returns the spooler job ID, if any.
+fn finish_submission(job_id: &str, submission: Submission) -> Option<String> {
+    match submission {
+        Submission::Spooled(spooler_job_id) => {
+            jobs::set_submitted(job_id, spooler_job_id.as_deref());
+
+            // Follow the job until it reaches a final state
+            match spooler_job_id.as_deref().filter(|id| is_watchable_job(id)) {
+                Some(id) => {
+                    tokio::spawn(watch_spooler_job(job_id.to_string(), id.to_string()));
+                }
+                None => spool::release(job_id),
+            }
+            spooler_job_id
+        }
+        Submission::Delivered => {
+            // The printer has taken every byte; there is no spooler to ask about the job afterwards
+            jobs::set_status(job_id, JobStatus::Completed);
+            spool::release(job_id);
+            None
+        }
+    }
+}
+
+/// Impose the spooled PDF if asked, replacing the job's file with the imposed sheets
+/// Returns the path to print and the settings to print it with.
+async fn spool_pdf(
+    job_id: &str,
+    pdf_path: &Path,
+    settings: &PrintSettings,
+) -> Result<(String, PrintSettings), HelperError> {
+    let mut settings = settings.clone();
+
+    // Label pages laid out N-up on sheets are printed as the sheets, on sheet-sized media
+    let imposed = match settings.imposition.layout().map_err(HelperError::InvalidOptions)? {
+        Some(layout) => {
+            jobs::set_status(job_id, JobStatus::Rendering);
+            let data = tokio::fs::read(pdf_path)
+                .await
+                .map_err(|e| HelperError::SpoolFailed(format!("Could not read job file: {}", e)))?;
+            if settings.media_size.is_none() {
+                settings.media_size = settings.imposition.sheet_size.clone();
+            }
+            let imposed = tokio::task::spawn_blocking(move || pdf::impose(&data, &layout))
+                .await
+                .map_err(|e| HelperError::Internal(e.to_string()))?
+                .map_err(HelperError::RenderFailed)?;
+            Some(imposed)
+        }
+        None => None,
+    };
+
+    let spool_path = match imposed {
+        Some(imposed) => spool::write(job_id, "pdf", &imposed)
+            .map_err(|e| HelperError::SpoolFailed(format!("Could not write job file: {}", e)))?,
+        None => pdf_path.to_path_buf(),
+    };
+    Ok((spool_path.to_string_lossy().to_string(), settings))
+}
+
+/// What a job sends to the printer, prepared once for every printer it is offered to
+enum Payload<'a> {
+    /// A spooled PDF and the settings to print it with
+    Pdf { path: &'a str, settings: &'a PrintSettings },
+    /// Printer commands sent as they are
+    Raw(&'a [u8]),
+}
+
+/// Hand a job to its printer, trying again with backoff when the hand-off fails, then to the
+/// failover printer of the printer's profile
+/// Only transient failures (printer offline, spool file not written) are retried or failed over;
+/// other errors are returned at once.
+/// Every try is counted on the job, and the printer that took it is recorded. Retries happen with
+/// the queue held, so jobs behind this one still print in order.
+async fn deliver(
+    job_id: &str,
+    payload: &Payload<'_>,
+    printer_name: Option<&str>,
+    copies: u32,
+) -> Result<Submission, HelperError> {
+    let retry = &config::get_config().retry;
+    let failover = printer_name
+        .and_then(profiles::profile_for)
+        .and_then(|profile| profile.failover_printer)
+        .filter(|failover| Some(failover.as_str()) != printer_name);
+
+    let mut targets = vec![printer_name];
+    targets.extend(failover.as_deref().map(Some));
+
+    let mut last_error: Option<HelperError> = None;
+    for (index, target) in targets.into_iter().enumerate() {
+        let target_label = target.unwrap_or("default printer");
+        if index > 0 {
+            // The request's options were checked against the requested printer only
+            if let Payload::Pdf { settings, .. } = payload {
+                if let Err(e) = validate_settings(target, settings) {
+                    tracing::warn!("Job {} can't fail over to {}: {}", job_id, target_label, e);
+                    break;
+                }
+            }
+            tracing::warn!("Job {} failing over to {}", job_id, target_label);
+        }
+
+        // Converting for this printer isn't retried - it would fail the same way again
+        let zpl = match render_for_printer(job_id, payload, target).await {
+            Ok(zpl) => zpl,
+            Err(e) => {
+                let e = HelperError::from_backend(e, HelperError::RenderFailed);
+                tracing::warn!("Job {} can't be prepared for {}: {}", job_id, target_label, e);
+                if !e.is_transient() {
+                    return Err(e);
+                }
+                last_error = Some(e);
+                continue;
+            }
+        };
+
+        let attempts = retry.attempts.max(1);
+        for attempt in 1..=attempts {
+            let previous_error = last_error.as_ref().map(|e| e.to_string());
+            jobs::record_attempt(job_id, previous_error.as_deref());
+            jobs::set_status(job_id, JobStatus::Spooling);
+
+            // Backends return typed errors for a refused job; what's left is I/O with the printer
+            match send(payload, zpl.as_deref(), target, copies)
+                .await
+                .map_err(|e| HelperError::from_backend(e, HelperError::PrinterOffline))
+            {
+                Ok(submission) => {
+                    if index > 0 {
+                        tracing::info!("Job {} printed on failover printer {}", job_id, target_label);
+                    }
+                    jobs::set_printed_on(job_id, target);
+                    return Ok(submission);
+                }
+                // A missing backend, unknown printer or rejected option won't go away on a retry
+                Err(e) if !e.is_transient() => {
+                    tracing::warn!("Job {} failed on {}: {}", job_id, target_label, e);
+                    return Err(e);
+                }
+                Err(e) => {
+                    tracing::warn!("Job {}: try {} of {} on {} failed: {}", job_id, attempt, attempts, target_label, e);
+                    last_error = Some(e);
+                    if attempt < attempts {
+                        tokio::time::sleep(retry.delay_after(attempt)).await;
+                    }
+                }
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| HelperError::PrinterOffline("No printer accepted the job".to_string())))
+}
+
+/// Convert a PDF for printers whose profile asks for ZPL (thermal printers get the PDF
+/// rasterized and sent as printer commands); None when the printer takes the payload as it is
+async fn render_for_printer(
+    job_id: &str,
+    payload: &Payload<'_>,
     printer_name: Option<&str>,
+) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error + Send + Sync>> {
+    let Payload::Pdf { path, settings } = payload else {
+        return Ok(None);
+    };
+    let Some(zpl_settings) = printer_name
+        .filter(|p| !ipp::is_printer_uri(p))
+        .and_then(profiles::profile_for)
+        .and_then(|profile| profile.zpl)
+    else {
+        return Ok(None);
+    };
+
+    jobs::set_status(job_id, JobStatus::Rendering);
+    convert_pdf_to_zpl(path, &zpl_settings, settings).await.map(Some)
+}
+
+/// Hand the payload (or its ZPL rendering) to one printer
+async fn send(
+    payload: &Payload<'_>,
+    zpl: Option<&[u8]>,
+    printer_name: Option<&str>,
+    copies: u32,
+) -> Result<Submission, Box<dyn std::error::Error + Send + Sync>> {
+    let (path, settings) = match (payload, zpl) {
+        (Payload::Pdf { path, settings }, None) => (*path, *settings),
+        (&Payload::Raw(data), _) | (Payload::Pdf { .. }, Some(data)) => {
+            let printer = printer_name
+                .ok_or_else(|| HelperError::InvalidRequest("Raw jobs need a printer name".to_string()))?;
+            return send_raw(data, printer, copies).await;
+        }
+    };
+
+    // `ipp://` printers are sent the PDF directly, on every platform
+    if let Some(uri) = printer_name.filter(|p| ipp::is_printer_uri(p)) {
+        let spooler_job_id = print_pdf_ipp_uri(path, uri, copies, settings).await?;
+        return Ok(Submission::Spooled(spooler_job_id));
+    }
+
+    #[cfg(target_os = "windows")]
+    let spooler_job_id = print_pdf_windows(path, printer_name, copies, settings).await?;
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    let spooler_job_id = print_pdf_unix(path, printer_name, copies, settings).await?;
+
+    #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+    let spooler_job_id = {
+        let _ = (path, settings);
+        None
+    };
+
+    Ok(Submission::Spooled(spooler_job_id))
+}
+
+/// How often to ask the spooler / printer about a submitted job
+const JOB_POLL_INTERVAL_SECS: u64 = 3;
+
+/// Stop following a job that has not finished after this long (e.g. printer left offline)
+const JOB_POLL_TIMEOUT_SECS: u64 = 30 * 60;
+
+/// Whether the helper keeps following a job's state after it was handed to the spooler
+/// When it doesn't, `submitted` is the last state the job will reach.
+pub fn is_followed(job: &jobs::PrintJob) -> bool {
+    job.spooler_job_id.as_deref().is_some_and(is_watchable_job)
+}
+
+/// Whether a spooler job ID can be polled for its state on this platform
+/// CUPS IDs ("Printer-123") on macOS/Linux, and direct IPP jobs ("ipp://host/ipp/print-45") everywhere
+fn is_watchable_job(spooler_job_id: &str) -> bool {
+    cfg!(any(target_os = "macos", target_os = "linux")) || ipp::is_printer_uri(spooler_job_id)
+}
+
+/// Resume polling jobs that were still in the spooler when the helper last exited
+pub fn resume_job_watchers() {
+    for (job_id, spooler_job_id) in jobs::unfinished_spooler_jobs() {
+        if is_watchable_job(&spooler_job_id) {
+            tracing::info!("Resuming status polling for job {} ({})", job_id, spooler_job_id);
+            tokio::spawn(watch_spooler_job(job_id, spooler_job_id));
+        }
+    }
+}
+
+/// Poll the spooler until the job reaches a final state, mirroring its state onto the helper's job record
+async fn watch_spooler_job(job_id: String, spooler_job_id: String) {
+    let start = std::time::Instant::now();
+    let mut last_status = JobStatus::Submitted;
+
+    while start.elapsed().as_secs() < JOB_POLL_TIMEOUT_SECS {
+        tokio::time::sleep(tokio::time::Duration::from_secs(JOB_POLL_INTERVAL_SECS)).await;
+
+        let lookup_id = spooler_job_id.clone();
+        let status = tokio::task::spawn_blocking(move || query_spooler_job_status(&lookup_id))
+            .await
+            .ok()
+            .flatten();
+
+        let Some(status) = status else {
+            continue;
+        };
+
+        if status != last_status {
+            tracing::info!("Spooler job {} is now {:?}", spooler_job_id, status);
+            jobs::set_status(&job_id, status);
+            last_status = status;
+        }
+
+        if status.is_final() {
+            spool::release(&job_id);
+            return;
+        }
+    }
+
+    tracing::warn!("Stopped polling spooler job {} after {} seconds", spooler_job_id, JOB_POLL_TIMEOUT_SECS);
+    spool::release(&job_id);
+}
+
+/// Current state of a spooler job, or None if it can't be determined right now
+/// Job IDs are "<destination>-<number>", where the destination is a CUPS queue or an `ipp://` URI
+fn query_spooler_job_status(spooler_job_id: &str) -> Option<JobStatus> {
+    if ipp::is_printer_uri(spooler_job_id) {
+        let (uri, number) = spooler_job_id.rsplit_once('-')?;
+        let state = ipp::PrinterUri::parse(uri)
+            .and_then(|printer| printer.job_state(number.parse().map_err(|_| "Invalid job number")?))
+            .map_err(|e| tracing::warn!("Could not read state of IPP job {}: {}", spooler_job_id, e))
+            .ok()??;
+        return Some(classify_ipp_job_state(state));
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    {
+        query_cups_job_status(spooler_job_id)
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        None
+    }
+}
+
+/// Map an IPP job-state to a helper job status
+fn classify_ipp_job_state(state: i32) -> JobStatus {
+    match state {
+        4 => JobStatus::Held,      // pending-held
+        6 => JobStatus::Stopped,   // processing-stopped
+        7 => JobStatus::Canceled,
+        8 => JobStatus::Aborted,
+        9 => JobStatus::Completed,
+        5 => JobStatus::Printing,  // processing
+        _ => JobStatus::Submitted, // pending
+    }
+}
+
+// ============================================================================
+// Direct IPP printing (network printers not set up in CUPS or Windows)
+// ============================================================================
+
+/// Send the PDF straight to an IPP Everywhere printer with Print-Job
+/// Returns "<printer URI>-<job id>" so the job can be followed like a CUPS job
+async fn print_pdf_ipp_uri(
+    pdf_path: &str,
+    uri: &str,
+    copies: u32,
+    options: &PrintSettings,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let printer = ipp::PrinterUri::parse(uri).map_err(HelperError::InvalidRequest)?;
+    let document = std::fs::read(pdf_path)
+        .map_err(|e| HelperError::SpoolFailed(format!("Could not read job file: {}", e)))?;
+    let attributes = options.ipp_attributes(copies);
+
+    tracing::info!("=== DIRECT IPP PRINT ===");
+    tracing::info!("Printer URI: {}", printer.uri);
+    tracing::info!("PDF file size: {} bytes ({:.2} KB)", document.len(), document.len() as f64 / 1024.0);
+    tracing::info!("IPP job attributes: {:?}", attributes);
+
+    let job_number = tokio::task::spawn_blocking(move || {
+        printer
+            .print_job("AnyMobile label", "application/pdf", document, attributes)
+            .map(|number| format!("{}-{}", printer.uri, number))
+    })
+    .await??;
+
+    tracing::info!("IPP job ID: {}", job_number);
+    tracing::info!("=== DIRECT IPP PRINT COMPLETE ===");
+    Ok(Some(job_number))
+}
+
+// ============================================================================
+// Raw printing (ZPL/EPL/ESC-POS passthrough)
+// ============================================================================
+
+/// Default port for raw TCP printing (AppSocket / JetDirect)
+const RAW_SOCKET_PORT: u16 = 9100;
+
+/// Send printer-language data (the job's file in the spool directory) to a printer as-is, without rendering
+/// Goes over TCP to the `raw_target` of the printer's profile, otherwise to its queue in raw mode.
+/// Returns the spooler's job ID when the data went through a queue.
+pub async fn print_raw(
+    job_id: &str,
+    data_path: &Path,
+    printer_name: &str,
     copies: u32,
-) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    // Save PDF to temp file
-    let mut temp_file = NamedTempFile::with_suffix(".pdf")?;
-    temp_file.write_all(pdf_data)?;
-    let temp_path = temp_file.path().to_string_lossy().to_string();
+    format: RawFormat,
+) -> Result<Option<String>, HelperError> {
+    let _queue_slot = jobs::PRINT_QUEUE.lock().await;
+
+    let submitted = match tokio::fs::read(data_path).await {
+        Ok(data) => {
+            tracing::info!("=== RAW PRINT ({:?}) ===", format);
+            tracing::info!("Printer: {}, copies: {}, {} bytes", printer_name, copies, data.len());
+            deliver(job_id, &Payload::Raw(&data), Some(printer_name), copies).await
+        }
+        Err(e) => Err(HelperError::SpoolFailed(format!("Could not read job file: {}", e))),
+    };
+
+    match submitted {
+        Ok(submission) => Ok(finish_submission(job_id, submission)),
+        Err(e) => {
+            jobs::fail_job(job_id, &e);
+            Err(e)
+        }
+    }
+}
+
+/// Whether a `printer` value is a raw TCP target rather than a queue name
+fn is_socket_uri(printer_name: &str) -> bool {
+    printer_name.trim().to_ascii_lowercase().starts_with("socket://")
+}
+
+/// Error for a `socket://` printer given in a request (or as a failover printer)
+fn socket_uri_refused(printer_name: &str) -> HelperError {
+    HelperError::InvalidRequest(format!(
+        "{} can't be used as a printer - set it as the raw_target of a printer profile instead",
+        printer_name
+    ))
+}
+
+/// Send raw data to the printer's socket target, or to its queue in raw mode
+async fn send_raw(
+    data: &[u8],
+    printer_name: &str,
+    copies: u32,
+) -> Result<Submission, Box<dyn std::error::Error + Send + Sync>> {
+    match raw_socket_target(printer_name) {
+        Some(address) => {
+            let data = data.to_vec();
+            tokio::task::spawn_blocking(move || send_raw_socket(&address, &data, copies)).await??;
+            Ok(Submission::Delivered)
+        }
+        None if ipp::is_printer_uri(printer_name) => Err(HelperError::InvalidRequest(format!(
+            "Raw jobs can't be sent to {} - set a socket://host:9100 raw_target in its printer profile",
+            printer_name
+        ))
+        .into()),
+        None if is_socket_uri(printer_name) => Err(socket_uri_refused(printer_name).into()),
+        None => Ok(Submission::Spooled(submit_raw_to_queue(data, printer_name, copies).await?)),
+    }
+}
+
+/// `host:port` to send raw data to, from the printer's profile
+/// Only profiles (set up in the status window) name TCP targets; a `socket://` printer in a
+/// request would let any paired app send bytes to any host and port.
+fn raw_socket_target(printer_name: &str) -> Option<String> {
+    let target = profiles::profile_for(printer_name)?.raw_target?;
+
+    let address = target.trim();
+    let address = address.strip_prefix("socket://").unwrap_or(address).trim_end_matches('/');
+    if address.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+        Some(address.to_string())
+    } else {
+        Some(format!("{}:{}", address, RAW_SOCKET_PORT))
+    }
+}
+
+/// Write the data to a raw TCP port, once per copy
+/// Failing to reach the printer is worth a retry; failing once bytes have gone out isn't, since
+/// the labels that got through print and would print again.
+fn send_raw_socket(address: &str, data: &[u8], copies: u32) -> Result<(), HelperError> {
+    use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+    use std::time::Duration;
+
+    tracing::info!("Sending raw data to socket://{}", address);
+
+    let socket_address = address
+        .to_socket_addrs()
+        .map_err(|e| HelperError::PrinterOffline(format!("Could not resolve {}: {}", address, e)))?
+        .next()
+        .ok_or_else(|| HelperError::PrinterOffline(format!("Could not resolve {}", address)))?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, Duration::from_secs(10))
+        .map_err(|e| HelperError::PrinterOffline(format!("Could not connect to printer at {}: {}", address, e)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(30))).ok();
+
+    let total = data.len() * copies.max(1) as usize;
+    let mut sent = 0;
+    for _ in 0..copies.max(1) {
+        let mut rest = data;
+        while !rest.is_empty() {
+            let written = match stream.write(rest) {
+                Ok(0) => Err(std::io::Error::from(std::io::ErrorKind::WriteZero)),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                result => result,
+            };
+            match written {
+                Ok(n) => {
+                    sent += n;
+                    rest = &rest[n..];
+                }
+                Err(e) if sent == 0 => {
+                    return Err(HelperError::PrinterOffline(format!(
+                        "Failed to send data to printer at {}: {}",
+                        address, e
+                    )));
+                }
+                Err(e) => {
+                    return Err(HelperError::PrintInterrupted(format!(
+                        "Connection to printer at {} broke after {} of {} bytes: {}",
+                        address, sent, total, e
+                    )));
+                }
+            }
+        }
+    }
+    stream.flush().ok();
+    let _ = stream.shutdown(Shutdown::Write);
 
-    // Generate job ID
-    let job_id = Uuid::new_v4().to_string();
+    tracing::info!("Raw data delivered to {}", address);
+    Ok(())
+}
 
+/// Hand raw data to the printer's queue without filtering
+async fn submit_raw_to_queue(
+    data: &[u8],
+    printer_name: &str,
+    copies: u32,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
     #[cfg(target_os = "windows")]
     {
-        print_pdf_windows(&temp_path, printer_name, copies).await?;
+        let data = data.to_vec();
+        let printer = printer_name.to_string();
+        let spooler_job_id = tokio::task::spawn_blocking(move || print_raw_windows(&printer, &data, copies)).await??;
+        Ok(Some(spooler_job_id.to_string()))
     }
 
     #[cfg(any(target_os = "macos", target_os = "linux"))]
     {
-        print_pdf_unix(&temp_path, printer_name, copies).await?;
+        print_raw_unix(data, printer_name, copies).await
     }
 
-    // Keep temp file alive until print job is queued
-    // (it will be deleted when temp_file goes out of scope after a delay)
-    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+    #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+    {
+        let _ = (data, printer_name, copies);
+        Err("Raw printing is not supported on this platform".into())
+    }
+}
+
+/// Rasterize a PDF at the printer's resolution and encode each page as a ZPL label
+async fn convert_pdf_to_zpl(
+    pdf_path: &str,
+    zpl_settings: &profiles::ZplSettings,
+    options: &PrintSettings,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    // Installing Ghostscript is left to startup and the status window, not done while a job waits
+    #[cfg(target_os = "windows")]
+    let gs_path = find_ghostscript_path().ok_or_else(|| {
+        HelperError::BackendMissing("Ghostscript is not installed; install it from the status window".to_string())
+    })?;
+
+    #[cfg(not(target_os = "windows"))]
+    let gs_path = PathBuf::from("gs");
+
+    tracing::info!("=== PDF TO ZPL ({} DPI) ===", zpl_settings.dpi);
+
+    let pdf_path = pdf_path.to_string();
+    let zpl_settings = zpl_settings.clone();
+    let options = options.clone();
+
+    let zpl = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        // No anti-aliasing: grey edges would dither into ragged barcode bars
+        let png_paths = render_pdf_to_png(
+            &pdf_path,
+            &gs_path,
+            zpl_settings.dpi,
+            options.page_list().as_deref(),
+            "pnggray",
+            false,
+        )?;
+
+        let mut zpl = Vec::new();
+        let mut result = Ok(());
+        for path in &png_paths {
+            match image::open(path) {
+                Ok(page) => {
+                    let mut page = page.to_luma8();
+                    if options.orientation == Some(Orientation::Landscape) {
+                        page = image::imageops::rotate90(&page);
+                    }
+                    zpl.extend(zpl::encode_label(&page, zpl_settings.darkness).into_bytes());
+                }
+                Err(e) => {
+                    result = Err(format!("Failed to read rendered page {:?}: {}", path, e));
+                    break;
+                }
+            }
+        }
 
-    Ok(job_id)
+        for path in &png_paths {
+            let _ = std::fs::remove_file(path);
+        }
+        result?;
+
+        tracing::info!("Encoded {} page(s) as {} bytes of ZPL", png_paths.len(), zpl.len());
+        Ok(zpl)
+    })
+    .await??;
+
+    Ok(zpl)
 }
 
 // ============================================================================
@@ -132,11 +907,203 @@ fn list_printers_windows() -> Result<Vec<PrinterInfo>, Box<dyn std::error::Error
         } else {
             vec![]
         }
-    } else {
-        vec![]
+    } else {
+        vec![]
+    };
+
+    Ok(printers)
+}
+
+/// Write raw data to a printer through the spooler with the RAW datatype
+/// Returns the spooler job ID.
+#[cfg(target_os = "windows")]
+fn print_raw_windows(
+    printer_name: &str,
+    data: &[u8],
+    copies: u32,
+) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+    use windows::core::{PCWSTR, PWSTR};
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Graphics::Printing::{
+        ClosePrinter, EndDocPrinter, EndPagePrinter, OpenPrinterW, StartDocPrinterW, StartPagePrinter,
+        WritePrinter, DOC_INFO_1W,
+    };
+
+    let wide = |s: &str| -> Vec<u16> { s.encode_utf16().chain(std::iter::once(0)).collect() };
+    let printer_name_wide = wide(printer_name);
+    let mut doc_name = wide("AnyMobile label");
+    let mut datatype = wide("RAW");
+
+    unsafe {
+        let mut hprinter = HANDLE::default();
+        if OpenPrinterW(PCWSTR(printer_name_wide.as_ptr()), &mut hprinter, None).is_err() {
+            return Err(HelperError::PrinterNotFound(format!("Failed to open printer: {}", printer_name)).into());
+        }
+
+        let doc_info = DOC_INFO_1W {
+            pDocName: PWSTR(doc_name.as_mut_ptr()),
+            pOutputFile: PWSTR::null(),
+            pDatatype: PWSTR(datatype.as_mut_ptr()),
+        };
+        let job_id = StartDocPrinterW(hprinter, 1, &doc_info);
+        if job_id == 0 {
+            let _ = ClosePrinter(hprinter);
+            return Err(format!("Failed to start raw print job on {}", printer_name).into());
+        }
+
+        let mut result = Ok(job_id);
+        if StartPagePrinter(hprinter).as_bool() {
+            for copy in 0..copies.max(1) {
+                let mut written = 0u32;
+                let ok = WritePrinter(hprinter, data.as_ptr() as *const _, data.len() as u32, &mut written);
+                if !ok.as_bool() || written as usize != data.len() {
+                    let message = format!(
+                        "Failed to write raw data to {} ({} of {} bytes)",
+                        printer_name,
+                        written,
+                        data.len()
+                    );
+                    // What was written is spooled and prints; sending the job again would repeat it
+                    result = Err(if copy == 0 && written == 0 {
+                        HelperError::PrinterOffline(message)
+                    } else {
+                        HelperError::PrintInterrupted(message)
+                    }
+                    .into());
+                    break;
+                }
+            }
+            let _ = EndPagePrinter(hprinter);
+        } else {
+            result = Err(format!("Failed to start page on {}", printer_name).into());
+        }
+
+        let _ = EndDocPrinter(hprinter);
+        let _ = ClosePrinter(hprinter);
+
+        tracing::info!("Raw job {} sent to {}", job_id, printer_name);
+        result
+    }
+}
+
+/// Get the default printer name on Windows
+#[cfg(target_os = "windows")]
+fn default_printer_windows() -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let output = Command::new("powershell")
+        .args(["-Command", "(Get-WmiObject -Query \"SELECT * FROM Win32_Printer WHERE Default=$true\").Name"])
+        .creation_flags(CREATE_NO_WINDOW)
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Read printer capabilities with DeviceCapabilitiesW
+#[cfg(target_os = "windows")]
+fn get_capabilities_windows(printer_name: Option<&str>) -> Option<PrinterCapabilities> {
+    use crate::print_options::CapabilityChoice;
+    use windows::core::{PCWSTR, PWSTR};
+    use windows::Win32::Storage::Xps::{
+        DeviceCapabilitiesW, PRINTER_DEVICE_CAPABILITIES, DC_BINNAMES, DC_BINS, DC_COLORDEVICE,
+        DC_DUPLEX, DC_ENUMRESOLUTIONS, DC_MEDIATYPENAMES, DC_MEDIATYPES, DC_PAPERNAMES, DC_PAPERS,
+    };
+
+    let printer = match printer_name {
+        Some(name) => name.to_string(),
+        None => default_printer_windows().ok()?,
+    };
+    let printer_wide: Vec<u16> = printer.encode_utf16().chain(std::iter::once(0)).collect();
+
+    // Each capability is queried twice: once for the item count, once to fill the buffer
+    let count = |capability: PRINTER_DEVICE_CAPABILITIES| -> i32 {
+        unsafe {
+            DeviceCapabilitiesW(
+                PCWSTR(printer_wide.as_ptr()),
+                PCWSTR::null(),
+                capability,
+                PWSTR::null(),
+                None,
+            )
+        }
+    };
+    let fetch = |capability: PRINTER_DEVICE_CAPABILITIES, item_bytes: usize| -> Vec<u8> {
+        let n = count(capability);
+        if n <= 0 {
+            return vec![];
+        }
+        let mut buffer = vec![0u8; n as usize * item_bytes];
+        let written = unsafe {
+            DeviceCapabilitiesW(
+                PCWSTR(printer_wide.as_ptr()),
+                PCWSTR::null(),
+                capability,
+                PWSTR(buffer.as_mut_ptr() as *mut u16),
+                None,
+            )
+        };
+        buffer.truncate(written.max(0) as usize * item_bytes);
+        buffer
+    };
+    // Fixed-width, NUL-padded UTF-16 names
+    let names = |buffer: &[u8], chars: usize| -> Vec<String> {
+        buffer
+            .chunks(chars * 2)
+            .map(|chunk| {
+                let wide: Vec<u16> = chunk
+                    .chunks(2)
+                    .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                    .take_while(|&c| c != 0)
+                    .collect();
+                String::from_utf16_lossy(&wide)
+            })
+            .collect()
+    };
+    let words = |buffer: &[u8]| -> Vec<u16> {
+        buffer.chunks(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect()
+    };
+    let dwords = |buffer: &[u8]| -> Vec<u32> {
+        buffer.chunks(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
     };
 
-    Ok(printers)
+    // -1 means the printer doesn't exist (or the driver failed)
+    if count(DC_PAPERS) < 0 {
+        tracing::warn!("Could not read capabilities for printer {}", printer);
+        return None;
+    }
+
+    let media_sizes = words(&fetch(DC_PAPERS, 2))
+        .into_iter()
+        .zip(names(&fetch(DC_PAPERNAMES, 64), 64))
+        .map(|(code, name)| CapabilityChoice::new(code.to_string(), name))
+        .collect();
+    let media_types = dwords(&fetch(DC_MEDIATYPES, 4))
+        .into_iter()
+        .zip(names(&fetch(DC_MEDIATYPENAMES, 64), 64))
+        .map(|(id, name)| CapabilityChoice::new(id.to_string(), name))
+        .collect();
+    let trays = words(&fetch(DC_BINS, 2))
+        .into_iter()
+        .zip(names(&fetch(DC_BINNAMES, 24), 24))
+        .map(|(id, name)| CapabilityChoice::new(id.to_string(), name))
+        .collect();
+    let resolutions = dwords(&fetch(DC_ENUMRESOLUTIONS, 8))
+        .chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .map(|pair| format!("{}x{}dpi", pair[0], pair[1]))
+        .collect();
+
+    Some(PrinterCapabilities {
+        printer,
+        media_sizes,
+        default_media_size: None,
+        custom_media_sizes: true, // DEVMODE paper length/width
+        media_types,
+        default_media_type: None,
+        resolutions,
+        default_resolution: None,
+        trays,
+        default_tray: None,
+        color_supported: count(DC_COLORDEVICE) == 1,
+        duplex_supported: count(DC_DUPLEX) == 1,
+    })
 }
 
 /// Get the directory where Ghostscript should be stored
@@ -359,69 +1326,100 @@ async fn ensure_sumatra_available() -> Result<PathBuf, Box<dyn std::error::Error
     Ok(sumatra_path)
 }
 
-/// Render PDF to PNG using Ghostscript (high quality, 600 DPI)
-#[cfg(target_os = "windows")]
+/// Render PDF pages to PNGs using Ghostscript at the given DPI
+/// Returns one PNG per page, in page order. `page_ranges` limits which pages are rendered.
+/// `device` is the Ghostscript PNG device ("png16m" for color, "pnggray" for grayscale).
 fn render_pdf_to_png(
     pdf_path: &str,
     gs_path: &std::path::Path,
-) -> Result<PathBuf, Box<dyn std::error::Error + Send + Sync>> {
-    // Create temp output path for PNG
+    dpi: u32,
+    page_ranges: Option<&str>,
+    device: &str,
+    anti_alias: bool,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error + Send + Sync>> {
+    // Create temp output path pattern for the PNGs (one file per page)
     let temp_dir = std::env::temp_dir();
-    let png_path = temp_dir.join(format!("print_{}.png", uuid::Uuid::new_v4()));
+    let file_prefix = format!("print_{}_", uuid::Uuid::new_v4());
+    let png_pattern = temp_dir.join(format!("{}%03d.png", file_prefix));
 
-    tracing::info!("Rendering PDF to PNG at 600 DPI...");
+    tracing::info!("Rendering PDF to PNG at {} DPI...", dpi);
     tracing::info!("  PDF: {}", pdf_path);
-    tracing::info!("  PNG: {:?}", png_path);
+    tracing::info!("  PNG: {:?}", png_pattern);
 
-    let args = vec![
+    let alpha_bits = if anti_alias { 4 } else { 1 };
+    let mut args = vec![
         "-dBATCH".to_string(),
         "-dNOPAUSE".to_string(),
-        "-dNOSAFER".to_string(),
-        "-sDEVICE=png16m".to_string(),      // 24-bit RGB PNG
-        "-r600".to_string(),                 // 600 DPI - matches our print quality
-        "-dTextAlphaBits=4".to_string(),     // Anti-aliasing for text
-        "-dGraphicsAlphaBits=4".to_string(), // Anti-aliasing for graphics
-        format!("-sOutputFile={}", png_path.to_string_lossy()),
-        pdf_path.to_string(),
+        "-dSAFER".to_string(), // Uploaded PDFs are untrusted: no file access beyond the output
+        format!("-sDEVICE={}", device),
+        format!("-r{}", dpi),                         // Matches the DPI we print at
+        format!("-dTextAlphaBits={}", alpha_bits),     // Anti-aliasing for text
+        format!("-dGraphicsAlphaBits={}", alpha_bits), // Anti-aliasing for graphics
+        format!("-sOutputFile={}", png_pattern.to_string_lossy()),
     ];
+    if let Some(ranges) = page_ranges {
+        args.push(format!("-sPageList={}", ranges));
+    }
+    args.push(pdf_path.to_string());
 
-    let output = Command::new(gs_path)
-        .args(&args)
-        .creation_flags(CREATE_NO_WINDOW)
-        .output()?;
+    let mut command = Command::new(gs_path);
+    command.args(&args);
+    #[cfg(target_os = "windows")]
+    command.creation_flags(CREATE_NO_WINDOW);
+
+    let output = command
+        .output()
+        .map_err(|e| {
+            let message = format!("Could not run Ghostscript ({}): {}", gs_path.display(), e);
+            if e.kind() == std::io::ErrorKind::NotFound {
+                HelperError::BackendMissing(message)
+            } else {
+                HelperError::RenderFailed(message)
+            }
+        })?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
         return Err(format!("Ghostscript render failed: {}", stderr).into());
     }
 
-    if !png_path.exists() {
+    let mut png_paths: Vec<PathBuf> = std::fs::read_dir(&temp_dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().starts_with(&file_prefix))
+                .unwrap_or(false)
+        })
+        .collect();
+    png_paths.sort();
+
+    if png_paths.is_empty() {
         return Err("Ghostscript did not create PNG output".into());
     }
 
-    tracing::info!("PNG rendered successfully");
-    Ok(png_path)
+    tracing::info!("Rendered {} page(s) successfully", png_paths.len());
+    Ok(png_paths)
 }
 
-/// Print image using Windows GDI with custom DEVMODE (includes media type!)
+/// Print images using Windows GDI with custom DEVMODE (includes media type!)
+/// Each image becomes one page of a single spooler job.
 /// This is the key function - CreateDC accepts our DEVMODE directly
 #[cfg(target_os = "windows")]
-fn print_image_with_devmode(
-    image_path: &std::path::Path,
+fn print_images_with_devmode(
+    image_paths: &[PathBuf],
     printer_name: &str,
     copies: u32,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    settings: &profiles::WindowsSettings,
+    options: &PrintSettings,
+) -> Result<i32, Box<dyn std::error::Error + Send + Sync>> {
     use windows::core::PCWSTR;
     use windows::Win32::Foundation::HANDLE;
-    use windows::Win32::Graphics::Gdi::{
-        CreateDCW, DeleteDC, SetStretchBltMode, StretchDIBits, GetDeviceCaps,
-        BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, HALFTONE, SRCCOPY,
-        HORZRES, VERTRES, LOGPIXELSX, LOGPIXELSY, DEVMODEW, RGBQUAD,
-    };
+    use windows::Win32::Graphics::Gdi::{CreateDCW, DeleteDC, DEVMODEW};
     use windows::Win32::Graphics::Printing::{
         ClosePrinter, DocumentPropertiesW, OpenPrinterW,
     };
-    use windows::Win32::Storage::Xps::{StartDocW, StartPage, EndPage, EndDoc, DOCINFOW};
+    use windows::Win32::Storage::Xps::{StartDocW, EndDoc, DOCINFOW};
 
     // DEVMODE flags (constants)
     const DM_OUT_BUFFER: u32 = 2;
@@ -430,17 +1428,38 @@ fn print_image_with_devmode(
     const DM_YRESOLUTION: u32 = 0x2000;
     const DM_MEDIATYPE: u32 = 0x08000000;
     const DM_COPIES: u32 = 0x0100;
+    const DM_DEFAULTSOURCE: u32 = 0x0200;
+    const DM_ORIENTATION: u32 = 0x0001;
+    const DM_PAPERSIZE: u32 = 0x0002;
+    const DM_PAPERLENGTH: u32 = 0x0004;
+    const DM_PAPERWIDTH: u32 = 0x0008;
+    const DM_COLOR: u32 = 0x0800;
+    const DM_DUPLEX: u32 = 0x1000;
 
     tracing::info!("=== PRINTING WITH CUSTOM DEVMODE ===");
-    tracing::info!("Image: {:?}", image_path);
+    tracing::info!("Pages: {}", image_paths.len());
     tracing::info!("Printer: {}", printer_name);
     tracing::info!("Copies: {}", copies);
-
-    // Load the image
-    let img = image::open(image_path)?;
-    let rgb_img = img.to_rgb8();
-    let (width, height) = rgb_img.dimensions();
-    tracing::info!("Image dimensions: {}x{} pixels", width, height);
+    tracing::info!("Settings: {:?}", settings);
+    tracing::info!("Options: {:?}", options);
+
+    // Per-request overrides of the profile's media type and paper source (numeric driver IDs)
+    let media_type = match &options.media_type {
+        Some(value) => Some(
+            value
+                .parse::<u32>()
+                .map_err(|_| HelperError::InvalidOptions(format!("Invalid Windows media type ID: {}", value)))?,
+        ),
+        None => settings.media_type,
+    };
+    let tray = match &options.tray {
+        Some(value) => Some(
+            value
+                .parse::<u32>()
+                .map_err(|_| HelperError::InvalidOptions(format!("Invalid Windows paper source ID: {}", value)))?,
+        ),
+        None => settings.tray,
+    };
 
     // Convert printer name to wide string
     let printer_name_wide: Vec<u16> = printer_name.encode_utf16().chain(std::iter::once(0)).collect();
@@ -455,7 +1474,7 @@ fn print_image_with_devmode(
         );
 
         if result.is_err() {
-            return Err(format!("Failed to open printer: {}", printer_name).into());
+            return Err(HelperError::PrinterNotFound(format!("Failed to open printer: {}", printer_name)).into());
         }
 
         tracing::info!("Opened printer handle");
@@ -511,26 +1530,101 @@ fn print_image_with_devmode(
         // Read and modify dmFields at offset 72
         let dm_fields_ptr = dm_bytes.add(72) as *mut u32;
         let mut dm_fields = std::ptr::read_unaligned(dm_fields_ptr);
-        dm_fields |= DM_PRINTQUALITY | DM_YRESOLUTION | DM_MEDIATYPE | DM_COPIES;
-        std::ptr::write_unaligned(dm_fields_ptr, dm_fields);
+        dm_fields |= DM_PRINTQUALITY | DM_YRESOLUTION | DM_COPIES;
+        if media_type.is_some() {
+            dm_fields |= DM_MEDIATYPE;
+        }
+        if tray.is_some() {
+            dm_fields |= DM_DEFAULTSOURCE;
+        }
 
         // Set dmCopies at offset 86
         let dm_copies_ptr = dm_bytes.add(86) as *mut i16;
         std::ptr::write_unaligned(dm_copies_ptr, copies as i16);
 
+        // Set dmDefaultSource at offset 88
+        if let Some(tray) = tray {
+            let dm_default_source_ptr = dm_bytes.add(88) as *mut i16;
+            std::ptr::write_unaligned(dm_default_source_ptr, tray as i16);
+        }
+
         // Set dmPrintQuality at offset 90
         let dm_print_quality_ptr = dm_bytes.add(90) as *mut i16;
-        std::ptr::write_unaligned(dm_print_quality_ptr, 600);
+        std::ptr::write_unaligned(dm_print_quality_ptr, settings.dpi as i16);
 
         // Set dmYResolution at offset 96
         let dm_y_resolution_ptr = dm_bytes.add(96) as *mut i16;
-        std::ptr::write_unaligned(dm_y_resolution_ptr, 600);
+        std::ptr::write_unaligned(dm_y_resolution_ptr, settings.dpi as i16);
 
         // Set dmMediaType at offset 196 - THIS IS THE KEY SETTING!
-        let dm_media_type_ptr = dm_bytes.add(196) as *mut u32;
-        std::ptr::write_unaligned(dm_media_type_ptr, 258); // Premium Presentation Matte
+        if let Some(media_type) = media_type {
+            let dm_media_type_ptr = dm_bytes.add(196) as *mut u32;
+            std::ptr::write_unaligned(dm_media_type_ptr, media_type);
+        }
+
+        // Per-request settings
+        // Set dmOrientation at offset 76 (1 = portrait, 2 = landscape)
+        if let Some(orientation) = options.orientation {
+            let value: i16 = match orientation {
+                Orientation::Portrait => 1,
+                Orientation::Landscape => 2,
+            };
+            std::ptr::write_unaligned(dm_bytes.add(76) as *mut i16, value);
+            dm_fields |= DM_ORIENTATION;
+        }
+
+        // Set dmPaperSize at offset 78, or a custom dmPaperLength/dmPaperWidth (80/82, tenths of a mm)
+        if let Some(media_size) = &options.media_size {
+            // Numeric values are DMPAPER codes, as reported by /printers/{name}/capabilities
+            let dimensions = match media_size.parse::<i16>() {
+                Ok(paper_code) if paper_code > 0 => Some((0.0, 0.0, paper_code)),
+                _ => media_size_dimensions(media_size),
+            };
+            match dimensions {
+                Some((_, _, paper_code)) if paper_code != 0 => {
+                    std::ptr::write_unaligned(dm_bytes.add(78) as *mut i16, paper_code);
+                    dm_fields |= DM_PAPERSIZE;
+                }
+                Some((width_mm, height_mm, _)) => {
+                    std::ptr::write_unaligned(dm_bytes.add(78) as *mut i16, 0);
+                    std::ptr::write_unaligned(dm_bytes.add(80) as *mut i16, (height_mm * 10.0).round() as i16);
+                    std::ptr::write_unaligned(dm_bytes.add(82) as *mut i16, (width_mm * 10.0).round() as i16);
+                    dm_fields |= DM_PAPERLENGTH | DM_PAPERWIDTH;
+                }
+                None => tracing::warn!("Unknown media size '{}', using driver default", media_size),
+            }
+        }
+
+        // Set dmColor at offset 92 (1 = monochrome, 2 = color)
+        if let Some(color_mode) = options.color_mode {
+            let value: i16 = match color_mode {
+                ColorMode::Monochrome => 1,
+                ColorMode::Color => 2,
+            };
+            std::ptr::write_unaligned(dm_bytes.add(92) as *mut i16, value);
+            dm_fields |= DM_COLOR;
+        }
+
+        // Set dmDuplex at offset 94 (1 = simplex, 2 = long edge, 3 = short edge)
+        if let Some(duplex) = options.duplex {
+            let value: i16 = match duplex {
+                Duplex::OneSided => 1,
+                Duplex::TwoSidedLongEdge => 2,
+                Duplex::TwoSidedShortEdge => 3,
+            };
+            std::ptr::write_unaligned(dm_bytes.add(94) as *mut i16, value);
+            dm_fields |= DM_DUPLEX;
+        }
+
+        std::ptr::write_unaligned(dm_fields_ptr, dm_fields);
 
-        tracing::info!("Set DEVMODE: 600 DPI, MediaType=258 (Premium Matte), Copies={}", copies);
+        tracing::info!(
+            "Set DEVMODE: {} DPI, MediaType={:?}, Source={:?}, Copies={}",
+            settings.dpi,
+            media_type,
+            tray,
+            copies
+        );
 
         // Step 5: Validate DEVMODE via DocumentProperties (merge with driver)
         let result = DocumentPropertiesW(
@@ -553,7 +1647,7 @@ fn print_image_with_devmode(
             PCWSTR::null(),
             PCWSTR(printer_name_wide.as_ptr()),
             PCWSTR::null(),
-            Some(devmode_ptr),  // <-- THIS passes our media type 258!
+            Some(devmode_ptr),  // <-- THIS passes our media type!
         );
 
         if hdc.is_invalid() {
@@ -580,123 +1674,164 @@ fn print_image_with_devmode(
 
         tracing::info!("Started print job ID: {}", job_id);
 
-        // Step 8: Start page
-        if StartPage(hdc) <= 0 {
-            EndDoc(hdc);
-            let _ = DeleteDC(hdc);
-            return Err("Failed to start page".into());
-        }
-
-        // Step 9: Get printer page size in pixels
-        let page_width = GetDeviceCaps(hdc, HORZRES);
-        let page_height = GetDeviceCaps(hdc, VERTRES);
-        let dpi_x = GetDeviceCaps(hdc, LOGPIXELSX);
-        let dpi_y = GetDeviceCaps(hdc, LOGPIXELSY);
-
-        tracing::info!("Printer page: {}x{} pixels at {}x{} DPI", page_width, page_height, dpi_x, dpi_y);
-
-        // Step 10: Calculate ACTUAL SIZE print dimensions
-        // Image was rendered at 600 DPI, convert to printer DPI for actual size
-        let print_width = (width as i32 * dpi_x) / 600;
-        let print_height = (height as i32 * dpi_y) / 600;
-
-        // CENTER the image on the page
-        let dest_x = (page_width - print_width) / 2;
-        let dest_y = (page_height - print_height) / 2;
-
-        tracing::info!("Print size: {}x{} pixels (actual size at {} DPI)", print_width, print_height, dpi_x);
-        tracing::info!("Centered at: ({}, {})", dest_x, dest_y);
-
-        // Create BITMAPINFO
-        // Windows DIB is BGR, bottom-up by default
-        let bmi = BITMAPINFO {
-            bmiHeader: BITMAPINFOHEADER {
-                biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
-                biWidth: width as i32,
-                biHeight: -(height as i32), // Negative = top-down
-                biPlanes: 1,
-                biBitCount: 24,
-                biCompression: BI_RGB.0,
-                biSizeImage: 0,
-                biXPelsPerMeter: 0,
-                biYPelsPerMeter: 0,
-                biClrUsed: 0,
-                biClrImportant: 0,
-            },
-            bmiColors: [RGBQUAD::default()],
-        };
-
-        // Convert RGB to BGR for Windows
-        let mut bgr_data: Vec<u8> = Vec::with_capacity((width * height * 3) as usize);
-        for pixel in rgb_img.pixels() {
-            bgr_data.push(pixel[2]); // B
-            bgr_data.push(pixel[1]); // G
-            bgr_data.push(pixel[0]); // R
-        }
-
-        // Pad rows to 4-byte boundary (Windows requirement)
-        let row_size = ((width * 3 + 3) / 4) * 4;
-        let mut padded_data: Vec<u8> = Vec::with_capacity((row_size * height) as usize);
-        for y in 0..height {
-            let row_start = (y * width * 3) as usize;
-            let row_end = row_start + (width * 3) as usize;
-            padded_data.extend_from_slice(&bgr_data[row_start..row_end]);
-            // Add padding bytes
-            for _ in 0..(row_size - width * 3) {
-                padded_data.push(0);
+        // Step 8: Draw each image as its own page
+        for (index, image_path) in image_paths.iter().enumerate() {
+            tracing::info!("Printing page {} of {}: {:?}", index + 1, image_paths.len(), image_path);
+            if let Err(e) = draw_image_page(hdc, image_path, settings.dpi, options.scaling()) {
+                EndDoc(hdc);
+                let _ = DeleteDC(hdc);
+                return Err(e);
             }
         }
 
-        // Set stretch mode for quality
-        SetStretchBltMode(hdc, HALFTONE);
-
-        // Step 11: Draw image to printer DC (centered, actual size)
-        let result = StretchDIBits(
-            hdc,
-            dest_x,                 // dest x (centered)
-            dest_y,                 // dest y (centered)
-            print_width,            // dest width (actual size)
-            print_height,           // dest height (actual size)
-            0,                      // src x
-            0,                      // src y
-            width as i32,           // src width
-            height as i32,          // src height
-            Some(padded_data.as_ptr() as *const std::ffi::c_void),
-            &bmi,
-            DIB_RGB_COLORS,
-            SRCCOPY,
-        );
-
-        if result == 0 {
-            EndPage(hdc);
-            EndDoc(hdc);
-            let _ = DeleteDC(hdc);
-            return Err("StretchDIBits failed".into());
-        }
-
-        tracing::info!("StretchDIBits drew {} scan lines", result);
-
-        // Step 12: End page and document
-        EndPage(hdc);
+        // Step 9: End document
         EndDoc(hdc);
         let _ = DeleteDC(hdc);
 
         tracing::info!("=== PRINT JOB SENT SUCCESSFULLY ===");
+
+        Ok(job_id)
+    }
+}
+
+/// Draw one image onto a new page of the printer DC
+/// `image_dpi` is the resolution the image was rendered at; actual size keeps it at 100%.
+#[cfg(target_os = "windows")]
+unsafe fn draw_image_page(
+    hdc: windows::Win32::Graphics::Gdi::HDC,
+    image_path: &std::path::Path,
+    image_dpi: u32,
+    scaling: Scaling,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use windows::Win32::Graphics::Gdi::{
+        SetStretchBltMode, StretchDIBits, GetDeviceCaps,
+        BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, HALFTONE, SRCCOPY,
+        HORZRES, VERTRES, LOGPIXELSX, LOGPIXELSY, RGBQUAD,
+    };
+    use windows::Win32::Storage::Xps::{StartPage, EndPage};
+
+    // Load the image
+    let img = image::open(image_path)?;
+    let rgb_img = img.to_rgb8();
+    let (width, height) = rgb_img.dimensions();
+    tracing::info!("Image dimensions: {}x{} pixels", width, height);
+
+    // Start page
+    if StartPage(hdc) <= 0 {
+        return Err("Failed to start page".into());
+    }
+
+    // Get printer page size in pixels
+    let page_width = GetDeviceCaps(hdc, HORZRES);
+    let page_height = GetDeviceCaps(hdc, VERTRES);
+    let dpi_x = GetDeviceCaps(hdc, LOGPIXELSX);
+    let dpi_y = GetDeviceCaps(hdc, LOGPIXELSY);
+
+    tracing::info!("Printer page: {}x{} pixels at {}x{} DPI", page_width, page_height, dpi_x, dpi_y);
+
+    // Calculate ACTUAL SIZE print dimensions
+    // Image was rendered at image_dpi, convert to printer DPI for actual size
+    let actual_width = (width as i64 * dpi_x as i64) as f64 / image_dpi as f64;
+    let actual_height = (height as i64 * dpi_y as i64) as f64 / image_dpi as f64;
+
+    // Fit shrinks/grows to the printable area, fill covers it (cropping the overflow)
+    let scale = match scaling {
+        Scaling::ActualSize => 1.0,
+        Scaling::Fit => (page_width as f64 / actual_width).min(page_height as f64 / actual_height),
+        Scaling::Fill => (page_width as f64 / actual_width).max(page_height as f64 / actual_height),
+    };
+    let print_width = (actual_width * scale).round() as i32;
+    let print_height = (actual_height * scale).round() as i32;
+
+    // CENTER the image on the page
+    let dest_x = (page_width - print_width) / 2;
+    let dest_y = (page_height - print_height) / 2;
+
+    tracing::info!("Print size: {}x{} pixels ({:?} at {} DPI)", print_width, print_height, scaling, dpi_x);
+    tracing::info!("Centered at: ({}, {})", dest_x, dest_y);
+
+    // Create BITMAPINFO
+    // Windows DIB is BGR, bottom-up by default
+    let bmi = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: width as i32,
+            biHeight: -(height as i32), // Negative = top-down
+            biPlanes: 1,
+            biBitCount: 24,
+            biCompression: BI_RGB.0,
+            biSizeImage: 0,
+            biXPelsPerMeter: 0,
+            biYPelsPerMeter: 0,
+            biClrUsed: 0,
+            biClrImportant: 0,
+        },
+        bmiColors: [RGBQUAD::default()],
+    };
+
+    // Convert RGB to BGR for Windows
+    let mut bgr_data: Vec<u8> = Vec::with_capacity((width * height * 3) as usize);
+    for pixel in rgb_img.pixels() {
+        bgr_data.push(pixel[2]); // B
+        bgr_data.push(pixel[1]); // G
+        bgr_data.push(pixel[0]); // R
+    }
+
+    // Pad rows to 4-byte boundary (Windows requirement)
+    let row_size = ((width * 3 + 3) / 4) * 4;
+    let mut padded_data: Vec<u8> = Vec::with_capacity((row_size * height) as usize);
+    for y in 0..height {
+        let row_start = (y * width * 3) as usize;
+        let row_end = row_start + (width * 3) as usize;
+        padded_data.extend_from_slice(&bgr_data[row_start..row_end]);
+        // Add padding bytes
+        for _ in 0..(row_size - width * 3) {
+            padded_data.push(0);
+        }
+    }
+
+    // Set stretch mode for quality
+    SetStretchBltMode(hdc, HALFTONE);
+
+    // Draw image to printer DC (centered)
+    let result = StretchDIBits(
+        hdc,
+        dest_x,                 // dest x (centered)
+        dest_y,                 // dest y (centered)
+        print_width,            // dest width
+        print_height,           // dest height
+        0,                      // src x
+        0,                      // src y
+        width as i32,           // src width
+        height as i32,          // src height
+        Some(padded_data.as_ptr() as *const std::ffi::c_void),
+        &bmi,
+        DIB_RGB_COLORS,
+        SRCCOPY,
+    );
+
+    if result == 0 {
+        EndPage(hdc);
+        return Err("StretchDIBits failed".into());
     }
 
+    tracing::info!("StretchDIBits drew {} scan lines", result);
+
+    EndPage(hdc);
     Ok(())
 }
 
 /// Print PDF using Ghostscript to render + Windows GDI with custom DEVMODE
-/// This approach passes our DEVMODE (with media type 258) directly to CreateDC
+/// This approach passes our DEVMODE (with the profile's media type) directly to CreateDC
 /// No admin rights needed - no SetPrinter call!
 #[cfg(target_os = "windows")]
 async fn print_pdf_ghostscript(
     pdf_path: &str,
     printer_name: Option<&str>,
     copies: u32,
+    options: &PrintSettings,
     gs_path: &std::path::Path,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
     tracing::info!("=== WINDOWS PRINT (GDI with Custom DEVMODE) ===");
     tracing::info!("PDF path: {}", pdf_path);
     tracing::info!("Printer: {:?}", printer_name);
@@ -706,35 +1841,46 @@ async fn print_pdf_ghostscript(
     // Get printer name (use default if not specified)
     let printer = match printer_name {
         Some(name) => name.to_string(),
-        None => {
-            let output = Command::new("powershell")
-                .args(["-Command", "(Get-WmiObject -Query \"SELECT * FROM Win32_Printer WHERE Default=$true\").Name"])
-                .creation_flags(CREATE_NO_WINDOW)
-                .output()?;
-            String::from_utf8_lossy(&output.stdout).trim().to_string()
-        }
+        None => default_printer_windows()?,
     };
 
     tracing::info!("Using printer: {}", printer);
 
-    // Step 1: Render PDF to high-quality PNG using Ghostscript
-    tracing::info!("Step 1: Rendering PDF to PNG at 600 DPI...");
-    let png_path = render_pdf_to_png(pdf_path, gs_path)?;
-
-    // Step 2: Print PNG using Windows GDI with our DEVMODE
-    // This is the key - CreateDC accepts our DEVMODE with media type 258!
-    tracing::info!("Step 2: Printing PNG with custom DEVMODE (media type 258)...");
-    let result = print_image_with_devmode(&png_path, &printer, copies);
+    let settings = match profiles::profile_for(&printer) {
+        Some(profile) => {
+            tracing::info!("Applying printer profile '{}'", profile.name);
+            profile.windows_settings()
+        }
+        None => profiles::WindowsSettings::default(),
+    };
 
-    // Clean up temp PNG
-    if let Err(e) = std::fs::remove_file(&png_path) {
-        tracing::warn!("Failed to clean up temp PNG: {}", e);
+    // Step 1: Render PDF to high-quality PNGs using Ghostscript
+    tracing::info!("Step 1: Rendering PDF to PNG at {} DPI...", settings.dpi);
+    let png_paths = render_pdf_to_png(
+        pdf_path,
+        gs_path,
+        settings.dpi,
+        options.page_list().as_deref(),
+        "png16m", // 24-bit RGB PNG
+        true,
+    )?;
+
+    // Step 2: Print PNGs using Windows GDI with our DEVMODE
+    // This is the key - CreateDC accepts our DEVMODE with the media type!
+    tracing::info!("Step 2: Printing PNG with custom DEVMODE (media type {:?})...", settings.media_type);
+    let result = print_images_with_devmode(&png_paths, &printer, copies, &settings, options);
+
+    // Clean up temp PNGs
+    for png_path in &png_paths {
+        if let Err(e) = std::fs::remove_file(png_path) {
+            tracing::warn!("Failed to clean up temp PNG: {}", e);
+        }
     }
 
-    result?;
+    let spooler_job_id = result?;
 
     tracing::info!("=== WINDOWS PRINT COMPLETE ===");
-    Ok(())
+    Ok(Some(spooler_job_id.to_string()))
 }
 
 /// Print PDF using SumatraPDF (fallback - lower quality, ignores DEVMODE)
@@ -743,14 +1889,17 @@ async fn print_pdf_sumatra(
     pdf_path: &str,
     printer_name: Option<&str>,
     copies: u32,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    options: &PrintSettings,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
     tracing::info!("=== WINDOWS PRINT (SumatraPDF fallback) ===");
     tracing::info!("PDF path: {}", pdf_path);
     tracing::info!("Printer: {:?}", printer_name);
     tracing::info!("Copies: {}", copies);
 
     // Ensure SumatraPDF is available (download if needed)
-    let sumatra_path = ensure_sumatra_available().await?;
+    let sumatra_path = ensure_sumatra_available()
+        .await
+        .map_err(|e| HelperError::BackendMissing(format!("SumatraPDF is not available: {}", e)))?;
     tracing::info!("SumatraPDF path: {:?}", sumatra_path);
 
     // Build print command
@@ -766,9 +1915,9 @@ async fn print_pdf_sumatra(
         args.insert(0, "-print-to-default".to_string());
     }
 
-    // Add settings for multiple copies and quality
+    // Add settings for multiple copies, scaling and per-request options
     args.push("-print-settings".to_string());
-    args.push(format!("{}x,noscale", copies));
+    args.push(sumatra_print_settings(copies, options));
     args.push("-silent".to_string());
     args.push(pdf_path.to_string());
 
@@ -790,8 +1939,64 @@ async fn print_pdf_sumatra(
         return Err(err_msg.into());
     }
 
+    // SumatraPDF does not report the spooler job ID
     tracing::info!("=== WINDOWS PRINT COMPLETE (SumatraPDF) ===");
-    Ok(())
+    Ok(None)
+}
+
+/// Build SumatraPDF's -print-settings value ("1-3,2x,noscale,landscape,...")
+#[cfg(target_os = "windows")]
+fn sumatra_print_settings(copies: u32, options: &PrintSettings) -> String {
+    let mut settings = Vec::new();
+
+    if let Some(ranges) = options.page_list() {
+        settings.push(ranges);
+    }
+    settings.push(format!("{}x", copies));
+    settings.push(
+        match options.scaling() {
+            Scaling::ActualSize => "noscale",
+            // SumatraPDF has no fill mode - fit is the closest
+            Scaling::Fit | Scaling::Fill => "fit",
+        }
+        .to_string(),
+    );
+    if let Some(orientation) = options.orientation {
+        settings.push(
+            match orientation {
+                Orientation::Portrait => "portrait",
+                Orientation::Landscape => "landscape",
+            }
+            .to_string(),
+        );
+    }
+    if let Some(duplex) = options.duplex {
+        settings.push(
+            match duplex {
+                Duplex::OneSided => "simplex",
+                Duplex::TwoSidedLongEdge => "duplexlong",
+                Duplex::TwoSidedShortEdge => "duplexshort",
+            }
+            .to_string(),
+        );
+    }
+    if let Some(color_mode) = options.color_mode {
+        settings.push(
+            match color_mode {
+                ColorMode::Color => "color",
+                ColorMode::Monochrome => "monochrome",
+            }
+            .to_string(),
+        );
+    }
+    if let Some(media_size) = &options.media_size {
+        settings.push(format!("paper={}", media_size));
+    }
+    if let Some(tray) = &options.tray {
+        settings.push(format!("bin={}", tray));
+    }
+
+    settings.join(",")
 }
 
 /// Main Windows print function - uses Ghostscript if available, falls back to SumatraPDF
@@ -800,15 +2005,16 @@ async fn print_pdf_windows(
     pdf_path: &str,
     printer_name: Option<&str>,
     copies: u32,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    options: &PrintSettings,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
     // Check if Ghostscript is installed (was downloaded at app startup)
     if let Some(gs_path) = find_ghostscript_path() {
         tracing::info!("Using Ghostscript for high-quality printing");
-        print_pdf_ghostscript(pdf_path, printer_name, copies, &gs_path).await
+        print_pdf_ghostscript(pdf_path, printer_name, copies, options, &gs_path).await
     } else {
         tracing::warn!("Ghostscript not installed, using SumatraPDF (lower quality)");
         tracing::warn!("For best print quality, please restart the app and accept the Ghostscript installation prompt");
-        print_pdf_sumatra(pdf_path, printer_name, copies).await
+        print_pdf_sumatra(pdf_path, printer_name, copies, options).await
     }
 }
 
@@ -818,6 +2024,10 @@ async fn print_pdf_windows(
 
 #[cfg(any(target_os = "macos", target_os = "linux"))]
 fn list_printers_unix() -> Result<Vec<PrinterInfo>, Box<dyn std::error::Error>> {
+    if config::get_config().print_backend == PrintBackend::Ipp {
+        return list_printers_ipp();
+    }
+
     let output = Command::new("lpstat")
         .args(["-p", "-d"])
         .output()?;
@@ -871,43 +2081,40 @@ async fn print_pdf_unix(
     pdf_path: &str,
     printer_name: Option<&str>,
     copies: u32,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    options: &PrintSettings,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut cups_options = Vec::new();
+
+    // Apply the printer's profile (resolution, media type, quality, tray, driver options)
+    if let Some(printer) = printer_name {
+        match profiles::profile_for(printer) {
+            Some(profile) => {
+                let options = profile.cups_options();
+                tracing::info!("Applying printer profile '{}': {:?}", profile.name, options);
+                cups_options.extend(options);
+            }
+            None => tracing::info!("No printer profile matches '{}', using driver defaults", printer),
+        }
+    }
+
+    // Per-request settings go last so they override the profile
+    // (scaling defaults to actual size: fit-to-page=false, scaling=100)
+    cups_options.extend(options.cups_options());
+
+    if config::get_config().print_backend == PrintBackend::Ipp {
+        return print_pdf_ipp(pdf_path, printer_name, copies, cups_options).await;
+    }
+
     let mut args = vec![
         "-n".to_string(),
         copies.to_string(),
-        "-o".to_string(),
-        "fit-to-page=false".to_string(),
-        "-o".to_string(),
-        "scaling=100".to_string(),
     ];
+    for option in cups_options {
+        args.push("-o".to_string());
+        args.push(option);
+    }
 
-    // Apply printer-specific high-quality settings for labels
     if let Some(printer) = printer_name {
-        let printer_lower = printer.to_lowercase();
-
-        if printer_lower.contains("epson") {
-            // Epson-specific: 1200 DPI, highest quality, premium matte paper
-            // ET-3830 supports up to 5760×1440 DPI, so 1200 is well within range
-            args.extend([
-                "-o".to_string(), "Resolution=1200x1200dpi".to_string(),
-                "-o".to_string(), "EPIJ_Qual=307".to_string(),
-                "-o".to_string(), "EPIJ_Medi=12".to_string(),  // Premium Presentation Paper Matte
-            ]);
-            tracing::info!("Applying Epson high-quality settings: 1200dpi, quality=307, matte paper");
-        } else if printer_lower.contains("hp") || printer_lower.contains("laserjet") {
-            // HP-specific: use labels media type
-            args.extend([
-                "-o".to_string(), "MediaType=labels".to_string(),
-            ]);
-            tracing::info!("Applying HP settings: labels media type");
-        } else {
-            // Generic fallback: try common CUPS high quality option
-            args.extend([
-                "-o".to_string(), "print-quality=5".to_string(),
-            ]);
-            tracing::info!("Applying generic high-quality setting");
-        }
-
         args.push("-d".to_string());
         args.push(printer.to_string());
     }
@@ -922,15 +2129,417 @@ async fn print_pdf_unix(
     tracing::info!("Full lp command: lp {}", args.join(" "));
     tracing::info!("Executing lp with args: {:?}", args);
 
-    let status = Command::new("lp")
+    let output = Command::new("lp")
         .args(&args)
-        .status()?;
+        .output()?;
 
-    if !status.success() {
-        tracing::error!("lp print command failed with status: {:?}", status);
-        return Err("lp print command failed".into());
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        tracing::error!("lp print command failed with status: {:?}: {}", output.status, stderr.trim());
+        return Err(lp_error("lp print command failed", &stderr).into());
+    }
+
+    // lp prints "request id is Printer-123 (1 file(s))"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let cups_job_id = parse_lp_request_id(&stdout);
+    match &cups_job_id {
+        Some(id) => tracing::info!("CUPS job ID: {}", id),
+        None => tracing::warn!("Could not find CUPS job ID in lp output: {}", stdout.trim()),
     }
 
     tracing::info!("=== LINUX/macOS PRINT COMPLETE ===");
-    Ok(())
+    Ok(cups_job_id)
+}
+
+/// Submit raw data to a CUPS queue (`lp -o raw`, or application/vnd.cups-raw with the IPP backend)
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+async fn print_raw_unix(
+    data: &[u8],
+    printer_name: &str,
+    copies: u32,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    if config::get_config().print_backend == PrintBackend::Ipp {
+        let data = data.to_vec();
+        let printer = printer_name.to_string();
+        let cups_job_id = tokio::task::spawn_blocking(move || -> Result<String, HelperError> {
+            let attributes = vec![ipp::IppAttribute::new("copies", ipp::IppValue::Integer(copies as i32))];
+            let number = ipp::CupsClient::new().print_job(
+                &printer,
+                "AnyMobile label",
+                "application/vnd.cups-raw",
+                data,
+                attributes,
+            )?;
+            Ok(format!("{}-{}", printer, number))
+        })
+        .await??;
+        return Ok(Some(cups_job_id));
+    }
+
+    use std::process::Stdio;
+
+    let args = [
+        "-d", printer_name,
+        "-n", &copies.to_string(),
+        "-o", "raw",
+        "-t", "AnyMobile label",
+    ];
+    tracing::info!("Executing lp with args: {:?} (data on stdin)", args);
+
+    let mut child = Command::new("lp")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(data)?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        tracing::error!("lp raw print failed with status: {:?}: {}", output.status, stderr.trim());
+        return Err(lp_error("lp raw print failed", &stderr).into());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let cups_job_id = parse_lp_request_id(&stdout);
+    tracing::info!("CUPS job ID: {:?}", cups_job_id);
+    Ok(cups_job_id)
+}
+
+/// Classify a failed `lp` from what it printed: only a scheduler that can't be reached or a queue
+/// that is stopped or not accepting jobs is worth another try
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn lp_error(what: &str, stderr: &str) -> HelperError {
+    let message = format!("{}: {}", what, stderr.trim());
+    let lower = stderr.to_ascii_lowercase();
+    if ["does not exist", "unknown destination", "no default destination"]
+        .iter()
+        .any(|text| lower.contains(text))
+    {
+        HelperError::PrinterNotFound(message)
+    } else if ["unable to connect", "not responding", "not accepting jobs", "stopped", "disabled", "paused"]
+        .iter()
+        .any(|text| lower.contains(text))
+    {
+        HelperError::PrinterOffline(message)
+    } else {
+        HelperError::InvalidOptions(message)
+    }
+}
+
+/// List printers through the CUPS IPP API (printer-state is not localized, unlike `lpstat` text)
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn list_printers_ipp() -> Result<Vec<PrinterInfo>, Box<dyn std::error::Error>> {
+    let client = ipp::CupsClient::new();
+    let default_printer = client.default_printer().unwrap_or_else(|e| {
+        tracing::warn!("Could not read the default printer from CUPS: {}", e);
+        None
+    });
+
+    let printers = client
+        .printers()?
+        .into_iter()
+        .map(|printer| {
+            let status = match printer.state {
+                _ if !printer.accepting_jobs => "stopped",
+                3 => "ready",
+                4 => "busy",
+                5 => "stopped",
+                _ => "unknown",
+            };
+            PrinterInfo {
+                is_default: default_printer.as_deref() == Some(printer.name.as_str()),
+                name: printer.name,
+                status: status.to_string(),
+            }
+        })
+        .collect();
+
+    Ok(printers)
+}
+
+/// Submit the PDF to CUPS with an IPP Print-Job request
+/// Returns the job ID in the same "<destination>-<number>" form `lp` prints
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+async fn print_pdf_ipp(
+    pdf_path: &str,
+    printer_name: Option<&str>,
+    copies: u32,
+    cups_options: Vec<String>,
+) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+    let document = std::fs::read(pdf_path)?;
+    let printer_name = printer_name.map(str::to_string);
+
+    tracing::info!("=== LINUX/macOS PRINT (CUPS IPP) ===");
+    tracing::info!("PDF file size: {} bytes ({:.2} KB)", document.len(), document.len() as f64 / 1024.0);
+    tracing::info!("IPP job options: copies={} {:?}", copies, cups_options);
+
+    let cups_job_id = tokio::task::spawn_blocking(move || -> Result<String, HelperError> {
+        let client = ipp::CupsClient::new();
+        let printer = match printer_name {
+            Some(name) => name,
+            None => client
+                .default_printer()
+                .map_err(HelperError::PrinterOffline)?
+                .ok_or_else(|| {
+                    HelperError::PrinterNotFound("No printer specified and CUPS has no default printer".to_string())
+                })?,
+        };
+
+        let mut attributes = vec![ipp::IppAttribute::new("copies", ipp::IppValue::Integer(copies as i32))];
+        attributes.extend(ipp::job_attributes_from_options(&cups_options));
+
+        let number = client.print_job(&printer, "AnyMobile label", "application/pdf", document, attributes)?;
+        Ok(format!("{}-{}", printer, number))
+    })
+    .await??;
+
+    tracing::info!("CUPS job ID: {}", cups_job_id);
+    tracing::info!("=== LINUX/macOS PRINT COMPLETE ===");
+    Ok(Some(cups_job_id))
+}
+
+/// Read printer capabilities from `lpoptions -l` (driver options) and `lpoptions` (printer attributes)
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn get_capabilities_unix(printer_name: Option<&str>) -> Option<PrinterCapabilities> {
+    use crate::print_options::CapabilityChoice;
+
+    let run_lpoptions = |list_options: bool| -> Option<String> {
+        let mut command = Command::new("lpoptions");
+        if let Some(printer) = printer_name {
+            command.args(["-p", printer]);
+        }
+        if list_options {
+            command.arg("-l");
+        }
+        let output = command.output().ok()?;
+        if !output.status.success() {
+            tracing::warn!(
+                "lpoptions failed for {:?}: {}",
+                printer_name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).to_string())
+    };
+
+    let options = parse_lpoptions(&run_lpoptions(true)?);
+    let attributes = run_lpoptions(false).unwrap_or_default();
+
+    let find = |name: &str| options.iter().find(|o| o.name.eq_ignore_ascii_case(name));
+    let choices = |name: &str| -> Vec<CapabilityChoice> {
+        find(name)
+            .map(|o| o.choices.iter().map(|c| CapabilityChoice::new(c.as_str(), c.as_str())).collect())
+            .unwrap_or_default()
+    };
+    let default = |name: &str| find(name).and_then(|o| o.default.clone());
+
+    // printer-type bits from the CUPS API: 0x4 = color, 0x10 = duplex
+    let printer_type = attributes
+        .split_whitespace()
+        .find_map(|attr| attr.strip_prefix("printer-type="))
+        .and_then(|value| value.parse::<u32>().ok())
+        .unwrap_or(0);
+
+    let color_supported = match find("ColorModel") {
+        Some(option) => option.choices.iter().any(|c| {
+            let c = c.to_lowercase();
+            !(c.contains("gray") || c.contains("grey") || c.contains("black") || c.contains("mono"))
+        }),
+        None => printer_type & 0x4 != 0,
+    };
+    let duplex_supported = match find("Duplex") {
+        Some(option) => option.choices.iter().any(|c| !c.eq_ignore_ascii_case("none")),
+        None => printer_type & 0x10 != 0,
+    };
+
+    // `lpstat -d` prints "system default destination: NAME"
+    let printer = match printer_name {
+        Some(name) => name.to_string(),
+        None => Command::new("lpstat")
+            .arg("-d")
+            .output()
+            .ok()
+            .and_then(|output| {
+                let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+                stdout.split_once(':').map(|(_, name)| name.trim().to_string())
+            })
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "default".to_string()),
+    };
+
+    Some(PrinterCapabilities {
+        printer,
+        media_sizes: choices("PageSize"),
+        default_media_size: default("PageSize"),
+        // PPDs that accept custom sizes list a "Custom.WIDTHxHEIGHT" choice
+        custom_media_sizes: find("PageSize").is_some_and(|o| o.choices.iter().any(|c| c.starts_with("Custom."))),
+        media_types: choices("MediaType"),
+        default_media_type: default("MediaType"),
+        resolutions: find("Resolution").map(|o| o.choices.clone()).unwrap_or_default(),
+        default_resolution: default("Resolution"),
+        trays: choices("InputSlot"),
+        default_tray: default("InputSlot"),
+        color_supported,
+        duplex_supported,
+    })
+}
+
+/// A driver option reported by `lpoptions -l`
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+struct LpOption {
+    name: String,
+    choices: Vec<String>,
+    default: Option<String>,
+}
+
+/// Parse `lpoptions -l` output into options and their choices
+/// Lines look like "PageSize/Media Size: *Letter Legal A4 w288h432" (the `*` marks the default)
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn parse_lpoptions(stdout: &str) -> Vec<LpOption> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let (key, values) = line.split_once(':')?;
+            let name = key.split('/').next()?.trim().to_string();
+            let mut choices = Vec::new();
+            let mut default = None;
+            for value in values.split_whitespace() {
+                match value.strip_prefix('*') {
+                    Some(choice) => {
+                        default = Some(choice.to_string());
+                        choices.push(choice.to_string());
+                    }
+                    None => choices.push(value.to_string()),
+                }
+            }
+            Some(LpOption { name, choices, default })
+        })
+        .collect()
+}
+
+/// Extract the CUPS job ID from `lp` output ("request id is Printer-123 (1 file(s))")
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn parse_lp_request_id(stdout: &str) -> Option<String> {
+    stdout
+        .lines()
+        .find_map(|line| line.split("request id is ").nth(1))
+        .and_then(|rest| rest.split_whitespace().next())
+        .map(str::to_string)
+}
+
+/// Look up a job's state with `lpstat -W not-completed/completed -l`
+/// Uses the IPP job-state-reasons keywords printed on the "Alerts:" line, which are not localized
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn query_cups_job_status(cups_job_id: &str) -> Option<JobStatus> {
+    // Job IDs are "<destination>-<number>"
+    let (destination, number) = cups_job_id.rsplit_once('-')?;
+
+    if config::get_config().print_backend == PrintBackend::Ipp {
+        let state = ipp::CupsClient::new()
+            .job_state(destination, number.parse().ok()?)
+            .map_err(|e| tracing::warn!("Could not read state of CUPS job {}: {}", cups_job_id, e))
+            .ok()??;
+        return Some(classify_ipp_job_state(state));
+    }
+
+    for which in ["not-completed", "completed"] {
+        let output = Command::new("lpstat")
+            .args(["-W", which, "-l", "-o", destination])
+            .output()
+            .ok()?;
+
+        if !output.status.success() {
+            continue;
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if let Some(reasons) = find_lpstat_job_reasons(&stdout, cups_job_id) {
+            return Some(classify_cups_job(which == "completed", &reasons));
+        }
+    }
+
+    None
+}
+
+/// Find a job's block in `lpstat -l -o` output and return its "Alerts:" keywords
+/// Returns None if the job is not listed
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn find_lpstat_job_reasons(stdout: &str, cups_job_id: &str) -> Option<Vec<String>> {
+    let mut lines = stdout.lines();
+
+    // Job lines start at column 0, details for that job are indented below it
+    lines.find(|line| line.split_whitespace().next() == Some(cups_job_id))?;
+
+    let mut reasons = Vec::new();
+    for line in lines {
+        if !line.starts_with(char::is_whitespace) {
+            break;
+        }
+        if let Some((_, alerts)) = line.trim().split_once("Alerts:") {
+            reasons.extend(alerts.split_whitespace().map(str::to_string));
+        }
+    }
+
+    Some(reasons)
+}
+
+/// Map a CUPS job's state reasons to a helper job status
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn classify_cups_job(completed: bool, reasons: &[String]) -> JobStatus {
+    let has = |needle: &str| reasons.iter().any(|r| r.contains(needle));
+
+    if completed {
+        if has("canceled") {
+            JobStatus::Canceled
+        } else if has("aborted") {
+            JobStatus::Aborted
+        } else {
+            JobStatus::Completed
+        }
+    } else if has("job-hold") || has("held") {
+        JobStatus::Held
+    } else if has("job-stopped") {
+        JobStatus::Stopped
+    } else if has("job-printing") {
+        JobStatus::Printing
+    } else {
+        JobStatus::Submitted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn refused_jobs_are_not_retried() {
+        // Raw data can't go to an ipp:// printer; trying again (or elsewhere) wouldn't change that
+        let job = jobs::create_job(Some("ipp://printer.invalid/ipp/print"), 1, 6, None);
+        let result = deliver(&job.id, &Payload::Raw(b"^XA^XZ"), Some("ipp://printer.invalid/ipp/print"), 1).await;
+
+        assert!(matches!(result, Err(HelperError::InvalidRequest(_))));
+        assert_eq!(jobs::get_job(&job.id).map(|job| job.attempts), Some(1));
+    }
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn lp_errors_are_classified() {
+        let error = |stderr| lp_error("lp print command failed", stderr);
+        assert!(matches!(error("lp: The printer or class does not exist."), HelperError::PrinterNotFound(_)));
+        assert!(matches!(error("lp: Error - unknown destination \"Zebra\"."), HelperError::PrinterNotFound(_)));
+        assert!(matches!(
+            error("lp: Destination \"Zebra\" is not accepting jobs."),
+            HelperError::PrinterOffline(_)
+        ));
+        assert!(matches!(error("lp: Unable to connect to server."), HelperError::PrinterOffline(_)));
+        assert!(matches!(
+            error("lp: Unsupported document-format \"application/pdf\"."),
+            HelperError::InvalidOptions(_)
+        ));
+    }
 }
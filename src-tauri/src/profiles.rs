@@ -0,0 +1,364 @@
+//! Printer profiles - per-printer print settings loaded from a user-editable JSON file
+//!
+//! Replaces the hard-coded Epson/HP branches. The file lives in the app data dir
+//! (`printer-profiles.json`) and is reloaded automatically when it changes on disk.
+
+use crate::error::HelperError;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/// Profiles currently in effect, with the modification time of the file they came from
+static PROFILES: Lazy<RwLock<LoadedProfiles>> = Lazy::new(|| RwLock::new(LoadedProfiles::load()));
+
+struct LoadedProfiles {
+    file: ProfilesFile,
+    modified: Option<SystemTime>,
+}
+
+/// Contents of the profiles file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProfilesFile {
+    pub profiles: Vec<PrinterProfile>,
+}
+
+/// Print settings for printers whose name matches one of `patterns`
+/// The first matching profile in the file wins.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PrinterProfile {
+    /// Display name for the status window
+    pub name: String,
+    /// Case-insensitive printer name patterns: plain text matches anywhere in the name, `*` is a wildcard
+    pub patterns: Vec<String>,
+    /// Print resolution in DPI (CUPS `Resolution=NxNdpi`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<u32>,
+    /// Media type (CUPS `MediaType=`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    /// IPP print quality: 3 = draft, 4 = normal, 5 = high (CUPS `print-quality=`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u32>,
+    /// Input tray (CUPS `InputSlot=`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tray: Option<String>,
+    /// Extra `lp -o key=value` options, e.g. driver-specific Epson settings
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cups_options: BTreeMap<String, String>,
+    /// Where raw jobs (ZPL/EPL/ESC-POS from /print/raw) go: `socket://host[:port]` sends them over
+    /// TCP (port 9100 by default); unset sends them to the printer's own queue in raw mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_target: Option<String>,
+    /// Convert PDFs to ZPL graphics and send them raw instead of printing through the driver
+    /// (for Zebra and other ZPL thermal printers)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zpl: Option<ZplSettings>,
+    /// Printer that takes over jobs this printer still refuses after every retry (a printer name
+    /// or `ipp://` URI); only the requested printer's failover is used, not the failover's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failover_printer: Option<String>,
+    /// Settings for the Windows GDI path (defaults to 600 DPI, media type 258)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub windows: Option<WindowsSettings>,
+}
+
+/// DEVMODE settings used when printing through Windows GDI
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WindowsSettings {
+    /// Render and print resolution (dmPrintQuality / dmYResolution)
+    #[serde(default = "default_windows_dpi")]
+    pub dpi: u32,
+    /// Driver media type ID (dmMediaType), e.g. 258 = Epson Premium Presentation Matte
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<u32>,
+    /// Paper source ID (dmDefaultSource)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tray: Option<u32>,
+}
+
+/// PDF-to-ZPL conversion settings
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ZplSettings {
+    /// Printhead resolution: 203 (8 dots/mm) or 300 (12 dots/mm)
+    #[serde(default = "default_zpl_dpi")]
+    pub dpi: u32,
+    /// Darkness adjustment sent as `^MD` (-30 to 30)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub darkness: Option<i32>,
+}
+
+fn default_zpl_dpi() -> u32 {
+    203
+}
+
+fn default_windows_dpi() -> u32 {
+    600
+}
+
+impl Default for WindowsSettings {
+    fn default() -> Self {
+        Self {
+            dpi: default_windows_dpi(),
+            media_type: Some(258),
+            tray: None,
+        }
+    }
+}
+
+impl Default for ProfilesFile {
+    /// Built-in profiles, matching the settings the helper used before profiles existed
+    fn default() -> Self {
+        Self {
+            profiles: vec![
+                PrinterProfile {
+                    // ET-3830 supports up to 5760x1440 DPI, so 1200 is well within range
+                    name: "Epson - premium matte labels".to_string(),
+                    patterns: vec!["epson".to_string()],
+                    resolution: Some(1200),
+                    media_type: None,
+                    quality: None,
+                    tray: None,
+                    cups_options: BTreeMap::from([
+                        ("EPIJ_Qual".to_string(), "307".to_string()),
+                        ("EPIJ_Medi".to_string(), "12".to_string()), // Premium Presentation Paper Matte
+                    ]),
+                    raw_target: None,
+                    zpl: None,
+                    failover_printer: None,
+                    windows: None,
+                },
+                PrinterProfile {
+                    name: "HP - labels".to_string(),
+                    patterns: vec!["hp".to_string(), "laserjet".to_string()],
+                    resolution: None,
+                    media_type: Some("labels".to_string()),
+                    quality: None,
+                    tray: None,
+                    cups_options: BTreeMap::new(),
+                    raw_target: None,
+                    zpl: None,
+                    failover_printer: None,
+                    windows: None,
+                },
+                PrinterProfile {
+                    name: "Generic - high quality".to_string(),
+                    patterns: vec!["*".to_string()],
+                    resolution: None,
+                    media_type: None,
+                    quality: Some(5),
+                    tray: None,
+                    cups_options: BTreeMap::new(),
+                    raw_target: None,
+                    zpl: None,
+                    failover_printer: None,
+                    windows: None,
+                },
+            ],
+        }
+    }
+}
+
+impl PrinterProfile {
+    /// Whether this profile applies to the given printer
+    pub fn matches(&self, printer_name: &str) -> bool {
+        let name = printer_name.to_lowercase();
+        self.patterns
+            .iter()
+            .any(|pattern| pattern_matches(&pattern.to_lowercase(), &name))
+    }
+
+    /// `lp -o` options for this profile, in a stable order
+    pub fn cups_options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+
+        if let Some(dpi) = self.resolution {
+            options.push(format!("Resolution={}x{}dpi", dpi, dpi));
+        }
+        if let Some(media_type) = &self.media_type {
+            options.push(format!("MediaType={}", media_type));
+        }
+        if let Some(quality) = self.quality {
+            options.push(format!("print-quality={}", quality));
+        }
+        if let Some(tray) = &self.tray {
+            options.push(format!("InputSlot={}", tray));
+        }
+        for (key, value) in &self.cups_options {
+            options.push(format!("{}={}", key, value));
+        }
+
+        options
+    }
+
+    /// Windows GDI settings, falling back to the built-in defaults
+    #[cfg(target_os = "windows")]
+    pub fn windows_settings(&self) -> WindowsSettings {
+        self.windows.clone().unwrap_or_default()
+    }
+}
+
+/// Match a lowercase pattern against a lowercase printer name
+/// Patterns without `*` match anywhere in the name (like the old substring checks)
+fn pattern_matches(pattern: &str, name: &str) -> bool {
+    if !pattern.contains('*') {
+        return name.contains(pattern);
+    }
+
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut rest = name;
+
+    for (i, part) in parts.iter().enumerate() {
+        if part.is_empty() {
+            continue;
+        }
+        if i == 0 {
+            // Pattern does not start with `*` - name must start with this part
+            match rest.strip_prefix(part) {
+                Some(r) => rest = r,
+                None => return false,
+            }
+        } else if i == parts.len() - 1 {
+            // Pattern does not end with `*` - name must end with this part
+            return rest.ends_with(part);
+        } else {
+            match rest.find(part) {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+    }
+
+    true
+}
+
+/// Get the path of the profiles file
+pub fn get_profiles_path() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("anymobile-print-helper")
+        .join("printer-profiles.json")
+}
+
+fn file_modified_time() -> Option<SystemTime> {
+    fs::metadata(get_profiles_path()).and_then(|m| m.modified()).ok()
+}
+
+impl LoadedProfiles {
+    /// Load profiles from disk, writing the built-in defaults if there is no file yet
+    fn load() -> Self {
+        let path = get_profiles_path();
+
+        if !path.exists() {
+            let file = ProfilesFile::default();
+            if let Err(e) = write_profiles_file(&file) {
+                tracing::warn!("Could not write default printer profiles: {}", e);
+            } else {
+                tracing::info!("Wrote default printer profiles to {:?}", path);
+            }
+            return Self { file, modified: file_modified_time() };
+        }
+
+        let modified = file_modified_time();
+        let file = match fs::read(&path).map_err(|e| e.to_string()).and_then(|data| {
+            serde_json::from_slice::<ProfilesFile>(&data).map_err(|e| e.to_string())
+        }) {
+            Ok(file) => {
+                tracing::info!("Loaded {} printer profile(s) from {:?}", file.profiles.len(), path);
+                file
+            }
+            Err(e) => {
+                tracing::error!("Invalid printer profiles file {:?}, using built-in defaults: {}", path, e);
+                ProfilesFile::default()
+            }
+        };
+
+        Self { file, modified }
+    }
+}
+
+fn write_profiles_file(file: &ProfilesFile) -> Result<(), String> {
+    let path = get_profiles_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create data directory: {}", e))?;
+    }
+    let data = serde_json::to_vec_pretty(file).map_err(|e| e.to_string())?;
+    // Replaced in one step, as jobs.json is
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, data)
+        .and_then(|()| fs::rename(&temp_path, &path))
+        .map_err(|e| format!("Could not save printer profiles: {}", e))
+}
+
+/// Load profiles at startup
+pub fn init() {
+    Lazy::force(&PROFILES);
+}
+
+/// Reload the profiles file if it changed on disk since it was last read
+fn reload_if_changed() {
+    let modified = file_modified_time();
+    let stale = PROFILES
+        .read()
+        .map(|loaded| loaded.modified != modified)
+        .unwrap_or(false);
+
+    if stale {
+        tracing::info!("Printer profiles changed on disk, reloading");
+        if let Ok(mut loaded) = PROFILES.write() {
+            *loaded = LoadedProfiles::load();
+        }
+    }
+}
+
+/// Get all profiles currently in effect
+pub fn get_profiles() -> ProfilesFile {
+    reload_if_changed();
+    PROFILES
+        .read()
+        .map(|loaded| loaded.file.clone())
+        .unwrap_or_default()
+}
+
+/// Validate and save profiles edited in the status window
+pub fn save_profiles(file: ProfilesFile) -> Result<(), HelperError> {
+    for profile in &file.profiles {
+        if profile.patterns.iter().all(|p| p.trim().is_empty()) {
+            return Err(HelperError::InvalidOptions(format!("Profile '{}' needs at least one printer name pattern", profile.name)));
+        }
+        if let Some(zpl) = &profile.zpl {
+            if !(100..=600).contains(&zpl.dpi) {
+                return Err(HelperError::InvalidOptions(format!("Profile '{}': ZPL dpi must be between 100 and 600", profile.name)));
+            }
+            if zpl.darkness.is_some_and(|d| !(-30..=30).contains(&d)) {
+                return Err(HelperError::InvalidOptions(format!("Profile '{}': ZPL darkness must be between -30 and 30", profile.name)));
+            }
+        }
+        if profile.failover_printer.as_ref().is_some_and(|p| p.trim().is_empty()) {
+            return Err(HelperError::InvalidOptions(format!("Profile '{}': failover printer can't be empty", profile.name)));
+        }
+    }
+
+    write_profiles_file(&file).map_err(HelperError::Internal)?;
+
+    if let Ok(mut loaded) = PROFILES.write() {
+        *loaded = LoadedProfiles {
+            file,
+            modified: file_modified_time(),
+        };
+    }
+
+    tracing::info!("Printer profiles saved");
+    Ok(())
+}
+
+/// Find the profile for a printer (first match wins)
+pub fn profile_for(printer_name: &str) -> Option<PrinterProfile> {
+    get_profiles()
+        .profiles
+        .into_iter()
+        .find(|profile| profile.matches(printer_name))
+}
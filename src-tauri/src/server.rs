@@ -1,39 +1,130 @@
 //! HTTP/HTTPS Server for receiving print jobs from the web app
 
 use axum::{
-    body::Bytes,
-    extract::{Multipart, State},
-    http::StatusCode,
-    response::Json,
+    extract::multipart::{Field, MultipartError},
+    extract::rejection::JsonRejection,
+    extract::{DefaultBodyLimit, Extension, Multipart, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Json, Response},
     routing::{get, post},
     Router,
 };
 use axum_server::tls_rustls::RustlsConfig;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use rcgen::{CertifiedKey, generate_simple_self_signed};
+use futures_util::stream::{self, Stream};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use once_cell::sync::Lazy;
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, RwLock};
 use std::path::PathBuf;
 use std::fs;
 use tauri::AppHandle;
-use tower_http::cors::{Any, CorsLayer};
+use tokio::sync::broadcast;
+use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
 
+use crate::access::{self, Caller};
+use crate::config::{self, DuplicateAction};
+use crate::error::HelperError;
+use crate::images::{self, ImagePlacement};
+use crate::jobs;
+use crate::pdf::{self, PageSource, PdfInfo};
+use crate::print_options::{PrintSettings, PrinterCapabilities, RawFormat, RawScan};
 use crate::printer;
+use crate::signing::{self, SignedContent};
+use crate::spool;
 
-/// Server configuration
-pub const HTTPS_PORT: u16 = 9847;
-pub const HTTP_PORT: u16 = 9848;
+/// What the servers are doing, published by the server tasks for /ping and the status window
+static SERVER_STATUS: Lazy<RwLock<ServerStatus>> = Lazy::new(|| RwLock::new(ServerStatus::default()));
+
+/// The running servers, kept so they can be stopped, restarted and given a new certificate
+static RUNNING: Lazy<tokio::sync::Mutex<Option<RunningServers>>> = Lazy::new(|| tokio::sync::Mutex::new(None));
+
+/// How long stopping the servers waits for requests in progress
+const SHUTDOWN_GRACE_SECS: u64 = 5;
 
 /// Server state
 struct ServerState {
     app_handle: AppHandle,
 }
 
+/// Handles of the running servers
+struct RunningServers {
+    handles: Vec<axum_server::Handle>,
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+    /// Shared with the HTTPS server, so a new certificate can be swapped in
+    tls_config: RustlsConfig,
+}
+
+/// State of the HTTPS and HTTP servers
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ServerStatus {
+    pub https: ListenerStatus,
+    pub http: ListenerStatus,
+    /// Newest print job received by either server
+    pub last_job_id: Option<String>,
+}
+
+/// Whether a server is up
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ListenerState {
+    /// Not started yet, or stopped from the status window
+    #[default]
+    Stopped,
+    Listening,
+    /// Couldn't listen, or stopped on its own (see `error`)
+    Failed,
+}
+
+/// Where a server is listening, or why it isn't
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ListenerStatus {
+    pub state: ListenerState,
+    /// Address and port in use; the port may be a fallback for a taken one
+    pub address: Option<SocketAddr>,
+    /// Why the server couldn't listen, or stopped
+    pub error: Option<String>,
+    /// Requests answered since the server started
+    pub requests_served: u64,
+}
+
+impl ListenerStatus {
+    pub fn is_listening(&self) -> bool {
+        self.state == ListenerState::Listening
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.address.map(|address| address.port())
+    }
+
+    /// Address to connect to on this machine (loopback when listening on all interfaces)
+    pub fn local_address(&self) -> Option<SocketAddr> {
+        self.address.map(|address| match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), address.port()),
+            IpAddr::V6(ip) if ip.is_unspecified() => SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), address.port()),
+            _ => address,
+        })
+    }
+}
+
 /// Response for /ping endpoint
 #[derive(Serialize)]
 struct PingResponse {
     app: &'static str,
     version: String,
     printers: Vec<PrinterInfo>,
+    /// Ports the servers listen on, which differ from 9847/9848 when those were taken
+    ports: PingPorts,
+}
+
+#[derive(Serialize)]
+struct PingPorts {
+    https: Option<u16>,
+    http: Option<u16>,
 }
 
 /// Printer information
@@ -51,16 +142,252 @@ struct PrintResponse {
     success: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
+    /// Stable error code (`printer-not-found`, `invalid-document`, ...) next to the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "jobId")]
     job_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "spoolerJobId")]
+    spooler_job_id: Option<String>,
+    /// Page count and sizes of the document
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<PdfInfo>,
+    /// Problems that didn't stop the job, e.g. pages that don't match the loaded media
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    /// For a repeated job that wasn't printed again, the job it repeated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "duplicateOf")]
+    duplicate_of: Option<String>,
+}
+
+impl PrintResponse {
+    /// Failed request that never became a job
+    fn rejected(error: HelperError) -> (StatusCode, Json<PrintResponse>) {
+        Self::failed(None, error)
+    }
+
+    /// Failed request, with the job it was recorded as if it got that far
+    fn failed(job_id: Option<String>, error: HelperError) -> (StatusCode, Json<PrintResponse>) {
+        (
+            error.status(),
+            Json(PrintResponse {
+                success: false,
+                error: Some(error.to_string()),
+                code: Some(error.code()),
+                job_id,
+                spooler_job_id: None,
+                document: None,
+                warnings: vec![],
+                duplicate_of: None,
+            }),
+        )
+    }
+
+    /// Repeated job answered as printed, without printing it again
+    fn deduplicated(duplicate: jobs::Duplicate, document: Option<PdfInfo>, warnings: Vec<String>) -> PrintResponse {
+        PrintResponse {
+            success: true,
+            error: None,
+            code: None,
+            job_id: Some(duplicate.job.id),
+            spooler_job_id: duplicate.original.spooler_job_id,
+            document,
+            warnings,
+            duplicate_of: Some(duplicate.original.id),
+        }
+    }
+
+    /// Repeated job refused until it is sent again with `force`
+    fn duplicate_refused(duplicate: jobs::Duplicate) -> (StatusCode, Json<PrintResponse>) {
+        let error = HelperError::DuplicateJob(format!(
+            "The same document was sent to this printer at {} (job {}); send it with force to print it again",
+            duplicate.original.created_at, duplicate.original.id
+        ));
+        let (status, Json(mut response)) = Self::failed(Some(duplicate.job.id), error);
+        response.duplicate_of = Some(duplicate.original.id);
+        (status, Json(response))
+    }
 }
 
 /// Print request options
-#[derive(Deserialize, Default)]
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
 struct PrintOptions {
     printer: Option<String>,
     copies: Option<u32>,
+    #[serde(flatten)]
+    settings: PrintSettings,
+    /// Print size for image uploads
+    #[serde(flatten)]
+    image: ImagePlacement,
+    /// Answer 202 as soon as the job is recorded and print in the background
+    #[serde(default, rename = "async")]
+    run_async: bool,
+    /// Print even if the same job was just received (see `config::DuplicateSettings`)
+    #[serde(default)]
+    force: bool,
+}
+
+impl PrintOptions {
+    /// Apply a multipart form field: printer, copies, print settings (orientation, pageRanges,
+    /// mediaSize, ...) or image size
+    fn set_field(&mut self, name: &str, text: &str) -> Result<(), String> {
+        match name {
+            "printer" => self.printer = Some(text.to_string()),
+            "copies" => self.copies = parse_copies(text)?,
+            "async" => self.run_async = is_true(text),
+            "force" => self.force = is_true(text),
+            _ => {
+                if !self.image.set_field(name, text)? {
+                    self.settings.set_field(name, text)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A yes/no form field
+fn is_true(text: &str) -> bool {
+    matches!(text.trim().to_lowercase().as_str(), "true" | "1" | "yes")
+}
+
+/// A copies form field; empty means "not set"
+fn parse_copies(text: &str) -> Result<Option<u32>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    text.parse()
+        .map(Some)
+        .map_err(|_| format!("Invalid value '{}' for copies", text))
+}
+
+/// JSON body for /print/json - same options as the multipart form, PDF or image as base64
+#[derive(Deserialize)]
+struct JsonPrintRequest {
+    pdf: Option<String>,
+    image: Option<String>,
+    #[serde(flatten)]
+    options: PrintOptions,
+}
+
+/// JSON body for /print/raw/json - printer commands (ZPL/EPL/ESC-POS) as base64
+#[derive(Deserialize)]
+struct JsonRawPrintRequest {
+    data: String,
+    printer: Option<String>,
+    copies: Option<u32>,
+    format: Option<RawFormat>,
+    #[serde(default)]
+    force: bool,
+}
+
+/// JSON body for /print/batch/json
+/// Top-level options apply to every document; each document can override them.
+#[derive(Deserialize)]
+struct JsonBatchRequest {
+    documents: Vec<serde_json::Map<String, serde_json::Value>>,
+    /// Print all documents as one merged job
+    #[serde(default)]
+    merge: bool,
+    #[serde(flatten)]
+    defaults: serde_json::Map<String, serde_json::Value>,
+}
+
+/// One document of a JSON batch: a base64 PDF or image, or a URL to fetch it from
+#[derive(Deserialize)]
+struct JsonBatchDocument {
+    pdf: Option<String>,
+    image: Option<String>,
+    url: Option<String>,
+    #[serde(flatten)]
+    options: PrintOptions,
+}
+
+/// Where a batch document's data comes from; uploads are already in the spool directory
+enum BatchSource {
+    Pdf(spool::SpoolFile),
+    Image(spool::SpoolFile),
+    Url(String),
+}
+
+/// A checked batch document ready to print: its spooled PDF and options
+type PreparedDocument = (spool::SpoolFile, PrintOptions);
+
+/// Response for /print/batch
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchResponse {
+    /// Whether every document was submitted
+    success: bool,
+    batch_id: String,
+    results: Vec<BatchItemResult>,
+    /// For merged batches, which document page each page (label) of the job came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page_map: Option<Vec<PageSource>>,
+}
+
+/// Outcome of one batch document, in request order
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BatchItemResult {
+    index: usize,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    job_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spooler_job_id: Option<String>,
+    /// Page count and sizes of this document (before merging)
+    document: PdfInfo,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// Most documents accepted in one batch
+const MAX_BATCH_DOCUMENTS: usize = 200;
+
+/// How long to wait for a batch document given by URL
+const DOCUMENT_DOWNLOAD_TIMEOUT_SECS: u64 = 30;
+
+/// Most redirects followed when fetching a batch document
+const MAX_DOCUMENT_REDIRECTS: usize = 5;
+
+/// Room for form fields and JSON options on top of the documents in a request body
+const REQUEST_OVERHEAD_BYTES: u64 = 1024 * 1024;
+
+/// JSON body for /pair
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PairRequest {
+    code: String,
+    /// Name shown for the app in the status window
+    name: Option<String>,
+    /// Secret from the portal for signing print requests
+    signing_secret: Option<String>,
+}
+
+/// Response for /pair
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PairResponse {
+    app_id: String,
+    /// Send as `Authorization: Bearer <token>` on print requests
+    token: String,
+}
+
+/// Query parameters for /jobs
+#[derive(Deserialize, Default)]
+struct JobsQuery {
+    limit: Option<usize>,
+    /// Only jobs from this batch
+    batch: Option<String>,
 }
 
 /// Get the path to store certificates
@@ -123,50 +450,359 @@ fn get_or_create_certificate() -> Result<(Vec<u8>, Vec<u8>), Box<dyn std::error:
 }
 
 /// Start both HTTPS and HTTP servers
-pub async fn start_server(app_handle: AppHandle) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// Each server runs as its own task; one that can't listen is reported (and its error returned)
+/// while the other keeps running. Does nothing if the servers are already running.
+pub async fn start_server(app_handle: AppHandle) -> Result<(), HelperError> {
+    let mut running = RUNNING.lock().await;
+    if running.is_some() {
+        return Ok(());
+    }
+    let app = router(app_handle);
+
+    // Get or create SSL certificate
+    let (cert_pem, key_pem) = get_or_create_certificate().map_err(|e| HelperError::Certificate(e.to_string()))?;
+
+    // Configure TLS
+    let tls_config = RustlsConfig::from_pem(cert_pem, key_pem)
+        .await
+        .map_err(|e| HelperError::Certificate(format!("Could not load the certificate: {}", e)))?;
+
+    // HTTPS binds first; its fallback skips the HTTP port so the two servers don't swap places
+    let settings = &config::get_config().server;
+    let https_listener = bind_listener("HTTPS", &settings.https, settings.port_fallback, &[settings.http.port]);
+    let https_port = https_listener.as_ref().ok().and_then(|l| l.local_addr().ok()).map(|a| a.port());
+    let http_listener = bind_listener("HTTP", &settings.http, settings.port_fallback, https_port.as_slice());
+    update_status(|status| {
+        status.https = listener_status(&https_listener);
+        status.http = listener_status(&http_listener);
+    });
+
+    let mut servers = RunningServers {
+        handles: vec![],
+        tasks: vec![],
+        tls_config: tls_config.clone(),
+    };
+    let mut errors = vec![];
+
+    // Start HTTPS server on primary port (for Safari)
+    match https_listener {
+        Ok(listener) => {
+            let handle = axum_server::Handle::new();
+            let https_app = app
+                .clone()
+                .layer(middleware::from_fn(|request, next| count_request(request, next, |status| &mut status.https)));
+            let server = axum_server::from_tcp_rustls(listener, tls_config)
+                .handle(handle.clone())
+                .serve(https_app.into_make_service());
+            servers.handles.push(handle);
+            servers.tasks.push(tokio::spawn(serve("HTTPS", server, |status| &mut status.https)));
+        }
+        Err(e) => errors.push(e),
+    }
+
+    // Start HTTP fallback server on secondary port (for Windows/Chrome/Firefox)
+    match http_listener {
+        Ok(listener) => {
+            let handle = axum_server::Handle::new();
+            let http_app =
+                app.layer(middleware::from_fn(|request, next| count_request(request, next, |status| &mut status.http)));
+            let server = axum_server::from_tcp(listener)
+                .handle(handle.clone())
+                .serve(http_app.into_make_service());
+            servers.handles.push(handle);
+            servers.tasks.push(tokio::spawn(serve("HTTP", server, |status| &mut status.http)));
+        }
+        Err(e) => errors.push(e),
+    }
+
+    if !servers.tasks.is_empty() {
+        *running = Some(servers);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(HelperError::Internal(errors.join("; ")))
+    }
+}
+
+/// Stop both servers, giving requests in progress a few seconds to finish
+pub async fn stop_server() {
+    let mut running = RUNNING.lock().await;
+    let Some(servers) = running.take() else {
+        return;
+    };
+    for handle in &servers.handles {
+        handle.graceful_shutdown(Some(std::time::Duration::from_secs(SHUTDOWN_GRACE_SECS)));
+    }
+    for task in servers.tasks {
+        let _ = task.await;
+    }
+    update_status(|status| {
+        status.https = ListenerStatus::default();
+        status.http = ListenerStatus::default();
+    });
+    tracing::info!("Servers stopped");
+}
+
+/// Stop the servers, read the config again and start them
+/// Picks up changed ports and limits, and a certificate that was replaced on disk.
+pub async fn restart_server(app_handle: AppHandle) -> Result<(), HelperError> {
+    stop_server().await;
+    config::reload();
+    tracing::info!("Restarting servers");
+    start_server(app_handle).await
+}
+
+/// Load the certificate on disk into the running HTTPS server, creating one if there is none
+/// Connections made from then on get the new certificate; nothing is restarted.
+pub async fn reload_certificate() -> Result<(), HelperError> {
+    let running = RUNNING.lock().await;
+    let (cert_pem, key_pem) = get_or_create_certificate().map_err(|e| HelperError::Certificate(e.to_string()))?;
+    if let Some(servers) = running.as_ref() {
+        servers
+            .tls_config
+            .reload_from_pem(cert_pem, key_pem)
+            .await
+            .map_err(|e| HelperError::Certificate(format!("Could not load the certificate: {}", e)))?;
+        tracing::info!("HTTPS server now uses the new certificate");
+    }
+    Ok(())
+}
+
+/// Run a server until it is stopped, recording why if it stops on its own
+async fn serve(
+    name: &'static str,
+    server: impl std::future::Future<Output = std::io::Result<()>>,
+    listener: fn(&mut ServerStatus) -> &mut ListenerStatus,
+) {
+    if let Err(e) = server.await {
+        tracing::error!("{} server stopped: {}", name, e);
+        update_status(|status| {
+            let listener = listener(status);
+            listener.state = ListenerState::Failed;
+            listener.address = None;
+            listener.error = Some(format!("{} server stopped: {}", name, e));
+        });
+    }
+}
+
+/// All routes, with CORS, the origin check and the request limits from the config
+fn router(app_handle: AppHandle) -> Router {
     let state = Arc::new(ServerState { app_handle });
 
-    // Build CORS layer - permissive for local desktop app
+    // Only pages on the allowlist get CORS answers; other origins are refused by check_origin.
+    // Request headers are mirrored because `*` doesn't cover `Authorization`.
     let cors = CorsLayer::new()
-        .allow_origin(Any)
+        .allow_origin(AllowOrigin::predicate(|origin, _| {
+            origin.to_str().is_ok_and(access::is_origin_allowed)
+        }))
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(AllowHeaders::mirror_request());
+
+    // Request bodies are capped at the document limit (batches at the batch limit), as base64
+    let limits = &config::get_config().limits;
+    let document_limit = DefaultBodyLimit::max(body_limit(limits.document_bytes()));
+    let batch_limit = DefaultBodyLimit::max(body_limit(limits.batch_bytes()));
 
-    // Build router
-    let app: Router = Router::new()
+    // Printing needs the token of a paired web app
+    let print_routes = Router::new()
+        .route("/print", post(handle_print).layer(document_limit))
+        .route("/print/json", post(handle_print_json).layer(document_limit))
+        .route("/print/batch", post(handle_print_batch).layer(batch_limit))
+        .route("/print/batch/json", post(handle_print_batch_json).layer(batch_limit))
+        .route("/print/raw", post(handle_print_raw).layer(document_limit))
+        .route("/print/raw/json", post(handle_print_raw_json).layer(document_limit))
+        .route_layer(middleware::from_fn(rate_limit))
+        .route_layer(middleware::from_fn(require_token));
+
+    Router::new()
         .route("/ping", get(handle_ping))
+        .route("/pair", post(handle_pair))
         .route("/printers", get(handle_printers))
-        .route("/print", post(handle_print))
+        .route("/printers/:name/capabilities", get(handle_printer_capabilities))
+        .merge(print_routes)
+        .route("/jobs", get(handle_jobs))
+        .route("/jobs/:id", get(handle_job))
+        .route("/jobs/:id/events", get(handle_job_events))
+        .layer(middleware::from_fn(check_origin))
         .layer(cors)
-        .with_state(state);
+        .with_state(state)
+}
 
-    // Get or create SSL certificate
-    let (cert_pem, key_pem) = get_or_create_certificate()?;
+/// Where the servers are listening, or why they aren't
+pub fn server_status() -> ServerStatus {
+    SERVER_STATUS.read().map(|status| status.clone()).unwrap_or_default()
+}
 
-    // Configure TLS
-    let tls_config = RustlsConfig::from_pem(cert_pem, key_pem).await?;
+fn update_status(change: impl FnOnce(&mut ServerStatus)) {
+    if let Ok(mut status) = SERVER_STATUS.write() {
+        change(&mut status);
+    }
+}
 
-    // Clone app for HTTP server
-    let http_app = app.clone();
+fn listener_status(listener: &Result<std::net::TcpListener, String>) -> ListenerStatus {
+    match listener {
+        Ok(listener) => ListenerStatus {
+            state: ListenerState::Listening,
+            address: listener.local_addr().ok(),
+            ..Default::default()
+        },
+        Err(e) => ListenerStatus {
+            state: ListenerState::Failed,
+            error: Some(e.clone()),
+            ..Default::default()
+        },
+    }
+}
 
-    // Start HTTP fallback server on secondary port (for Windows/Chrome/Firefox)
-    tokio::spawn(async move {
-        let http_addr = format!("127.0.0.1:{}", HTTP_PORT);
-        if let Ok(listener) = tokio::net::TcpListener::bind(&http_addr).await {
-            tracing::info!("HTTP server listening on {}", http_addr);
-            let _ = axum::serve(listener, http_app).await;
+/// Count a request against the server that answered it
+async fn count_request(
+    request: Request,
+    next: Next,
+    listener: fn(&mut ServerStatus) -> &mut ListenerStatus,
+) -> Response {
+    let response = next.run(request).await;
+    update_status(|status| listener(status).requests_served += 1);
+    response
+}
+
+/// Note the newest job for the status window
+fn set_last_job(job_id: &str) {
+    update_status(|status| status.last_job_id = Some(job_id.to_string()));
+}
+
+/// Bind a server's configured port, or the first free one of the `fallback` ports above it
+/// `skip` holds ports kept for the other server. Only a taken port moves on to the next one; a
+/// failure the next port won't fix (no permission, an address this machine doesn't have) is
+/// reported right away.
+fn bind_listener(
+    name: &str,
+    settings: &config::ListenerSettings,
+    fallback: u16,
+    skip: &[u16],
+) -> Result<std::net::TcpListener, String> {
+    if !settings.address.is_loopback() {
+        tracing::warn!("{} server binds {}, so it can be reached from the network", name, settings.address);
+    }
+
+    let last_port = settings.port.saturating_add(fallback);
+    let mut error = None;
+    for port in (settings.port..=last_port).filter(|port| !skip.contains(port)) {
+        match std::net::TcpListener::bind(SocketAddr::new(settings.address, port)) {
+            Ok(listener) => {
+                if port == settings.port {
+                    tracing::info!("{} server listening on {}:{}", name, settings.address, port);
+                } else {
+                    tracing::warn!(
+                        "{} port {} is in use, {} server listening on {}:{} instead",
+                        name, settings.port, name, settings.address, port
+                    );
+                }
+                return Ok(listener);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => error = Some(e),
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
         }
-    });
+    }
 
-    // Start HTTPS server on primary port (for Safari)
-    let https_addr = format!("127.0.0.1:{}", HTTPS_PORT);
-    tracing::info!("Starting HTTPS server on {}", https_addr);
+    let ports = if last_port > settings.port {
+        format!("ports {}-{}", settings.port, last_port)
+    } else {
+        format!("port {}", settings.port)
+    };
+    let reason = match error {
+        Some(e) if e.kind() == std::io::ErrorKind::AddrInUse => "already in use by another program".to_string(),
+        Some(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            "permission denied (ports below 1024 need administrator rights)".to_string()
+        }
+        Some(e) if e.kind() == std::io::ErrorKind::AddrNotAvailable => {
+            format!("{} is not an address of this computer", settings.address)
+        }
+        Some(e) => e.to_string(),
+        None => "no port left to try".to_string(),
+    };
+    let message = format!("{} server can't listen on {} {}: {}", name, settings.address, ports, reason);
+    tracing::error!("{}", message);
+    Err(message)
+}
 
-    axum_server::bind_rustls(https_addr.parse()?, tls_config)
-        .serve(app.into_make_service())
-        .await?;
+/// Refuse requests from web pages whose origin isn't on the allowlist
+/// CORS alone doesn't stop a page from sending a form POST, only from reading the answer.
+/// Requests without an `Origin` (curl, local tools) are let through.
+async fn check_origin(request: Request, next: Next) -> Response {
+    if let Some(origin) = request.headers().get(header::ORIGIN) {
+        let origin = origin.to_str().unwrap_or_default();
+        if !access::is_origin_allowed(origin) {
+            tracing::warn!("Refused {} {} from origin {}", request.method(), request.uri().path(), origin);
+            return error_json(HelperError::OriginNotAllowed(format!("Origin {} is not allowed", origin))).into_response();
+        }
+    }
+    next.run(request).await
+}
 
-    Ok(())
+/// Refuse print requests without the bearer token of a paired web app
+/// The app the token belongs to is passed on to the handler, which checks the request's signature.
+async fn require_token(mut request: Request, next: Next) -> Response {
+    let token = bearer_token(request.headers());
+    let origin = request.headers().get(header::ORIGIN).and_then(|o| o.to_str().ok());
+    match access::check_token(token, origin) {
+        Ok(caller) => {
+            request.extensions_mut().insert(caller);
+            next.run(request).await
+        }
+        Err(e) => {
+            tracing::warn!("Refused {}: {}", request.uri().path(), e);
+            PrintResponse::rejected(e).into_response()
+        }
+    }
+}
+
+/// Refuse print requests over the paired app's rate limit with 429 and a `Retry-After`
+/// Runs after the token check, so requests without a valid token don't use up the limit. The
+/// limit is kept per paired app, whatever origin it sends from; requests on routes without a
+/// token are counted by their origin.
+async fn rate_limit(request: Request, next: Next) -> Response {
+    let key = match request.extensions().get::<Caller>() {
+        Some(caller) => format!("app {}", caller.app_id),
+        None => match request.headers().get(header::ORIGIN).and_then(|o| o.to_str().ok()) {
+            Some(origin) => origin.to_string(),
+            None => "local tools".to_string(),
+        },
+    };
+    match access::check_rate_limit(&key) {
+        Ok(()) => next.run(request).await,
+        Err((e, retry_after)) => {
+            tracing::warn!("Refused {}: {}", request.uri().path(), e);
+            let mut response = PrintResponse::rejected(e).into_response();
+            response.headers_mut().insert(header::RETRY_AFTER, header::HeaderValue::from(retry_after));
+            response
+        }
+    }
+}
+
+/// Token from an `Authorization: Bearer <token>` header
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Handle /pair - exchange the pairing code shown in the status window for a token
+async fn handle_pair(
+    headers: HeaderMap,
+    request: Result<Json<PairRequest>, JsonRejection>,
+) -> Result<Json<PairResponse>, (StatusCode, Json<PrintResponse>)> {
+    let Json(request) = request.map_err(json_error)?;
+    let origin = headers.get(header::ORIGIN).and_then(|o| o.to_str().ok());
+    let (app_id, token) = access::pair(&request.code, request.name.as_deref(), origin, request.signing_secret.as_deref())
+        .map_err(PrintResponse::rejected)?;
+    Ok(Json(PairResponse { app_id, token }))
 }
 
 /// Handle /ping - health check and version info
@@ -179,93 +815,1045 @@ async fn handle_ping(State(state): State<Arc<ServerState>>) -> Json<PingResponse
         .version
         .to_string();
 
+    let status = server_status();
     Json(PingResponse {
         app: "anymobile-print-helper",
         version,
         printers: vec![], // Empty - fetch via /printers separately
+        ports: PingPorts {
+            https: status.https.port(),
+            http: status.http.port(),
+        },
     })
 }
 
 /// Handle /printers - list available printers
-async fn handle_printers() -> Json<serde_json::Value> {
-    let printers = printer::list_printers().unwrap_or_default();
-    Json(serde_json::json!({ "printers": printers }))
+/// A spooler that can't be asked is reported as an error rather than as an empty list
+async fn handle_printers() -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    let printers = printer::list_printers().map_err(error_json)?;
+    Ok(Json(serde_json::json!({ "printers": printers })))
+}
+
+/// Handle /printers/{name}/capabilities - media sizes, media types, resolutions, trays, color and duplex support
+async fn handle_printer_capabilities(
+    Path(name): Path<String>,
+) -> Result<Json<PrinterCapabilities>, (StatusCode, Json<serde_json::Value>)> {
+    printer::get_capabilities(Some(&name))
+        .map(Json)
+        .ok_or_else(|| error_json(HelperError::PrinterNotFound(format!("Printer {} not found", name))))
 }
 
+/// Error for a /print request with more than one document
+const ONE_DOCUMENT_ONLY: &str = "Send one pdf or image per request; use /print/batch for several documents";
+
 /// Handle /print - receive PDF and print it
+/// The PDF is streamed into the spool directory as it arrives, so an oversized upload is refused
+/// without being held in memory.
 async fn handle_print(
     State(_state): State<Arc<ServerState>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
     mut multipart: Multipart,
-) -> Result<Json<PrintResponse>, (StatusCode, Json<PrintResponse>)> {
-    let mut pdf_data: Option<Bytes> = None;
+) -> Result<(StatusCode, Json<PrintResponse>), (StatusCode, Json<PrintResponse>)> {
+    let limit = config::get_config().limits.document_bytes();
+    let mut upload: Option<spool::SpoolFile> = None;
+    let mut image: Option<spool::SpoolFile> = None;
     let mut options = PrintOptions::default();
+    let mut signed = SignedContent::default();
 
     // Parse multipart form data
-    while let Some(field) = multipart.next_field().await.map_err(|e| {
-        (
-            StatusCode::BAD_REQUEST,
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| PrintResponse::rejected(form_error(e, "Failed to parse form data")))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+
+        match name.as_str() {
+            "pdf" | "image" => {
+                // Printing one of two documents and dropping the other would go unnoticed
+                if upload.is_some() || image.is_some() {
+                    return Err(PrintResponse::rejected(HelperError::InvalidRequest(ONE_DOCUMENT_ONLY.to_string())));
+                }
+                let kind = if name == "pdf" { UploadKind::Pdf } else { UploadKind::Image };
+                let received = receive_upload(field, limit, kind.label(), kind, |_| {})
+                    .await
+                    .map_err(PrintResponse::rejected)?;
+                signed.add_document_digest(received.digest());
+                match kind {
+                    UploadKind::Pdf => upload = Some(received),
+                    _ => image = Some(received),
+                }
+            }
+            _ => {
+                if let Ok(text) = field.text().await {
+                    signed.add_field(&name, &text);
+                    options
+                        .set_field(&name, &text)
+                        .map_err(|e| PrintResponse::rejected(HelperError::InvalidRequest(e)))?;
+                }
+            }
+        }
+    }
+    signing::verify(&caller, &headers, &signed).map_err(PrintResponse::rejected)?;
+
+    // Images are laid out on a PDF page of their own size, then printed like any PDF
+    if let Some(image) = image {
+        let pdf = spooled_image_pdf(&image, &options.image).await.map_err(PrintResponse::rejected)?;
+        return print_document(options, pdf).await;
+    }
+
+    // Ensure we have PDF data
+    let upload = upload.ok_or_else(|| {
+        PrintResponse::rejected(HelperError::InvalidRequest("No PDF or image data provided".to_string()))
+    })?;
+
+    print_document(options, upload).await
+}
+
+/// What an uploaded file field should hold
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum UploadKind {
+    Pdf,
+    Image,
+    /// Printer commands, checked against their format once the whole form is read (see `RawScan`)
+    Raw,
+}
+
+impl UploadKind {
+    fn extension(self) -> &'static str {
+        match self {
+            UploadKind::Pdf => "pdf",
+            UploadKind::Image => "image",
+            UploadKind::Raw => "raw",
+        }
+    }
+
+    /// Name used in error messages
+    fn label(self) -> &'static str {
+        match self {
+            UploadKind::Pdf => "PDF",
+            UploadKind::Image => "image",
+            UploadKind::Raw => "raw",
+        }
+    }
+
+    /// Check the first bytes of an upload are the kind of document expected
+    fn check_head(self, head: &[u8]) -> Result<(), HelperError> {
+        match self {
+            UploadKind::Pdf => pdf::check_header(head).map_err(HelperError::InvalidDocument),
+            UploadKind::Image => images::check_format(head).map(drop).map_err(HelperError::InvalidDocument),
+            UploadKind::Raw => Ok(()),
+        }
+    }
+}
+
+/// Stream an uploaded file into the spool directory, hashing it on the way
+/// It is refused once it passes `limit` bytes, or as soon as its first bytes show it isn't the
+/// kind of document expected. `on_chunk` sees every chunk as it arrives.
+async fn receive_upload(
+    mut field: Field<'_>,
+    limit: u64,
+    what: &str,
+    kind: UploadKind,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> Result<spool::SpoolFile, HelperError> {
+    let write_error = |e: std::io::Error| HelperError::SpoolFailed(format!("Could not write upload file: {}", e));
+    let mut upload = spool::Upload::create(kind.extension())
+        .await
+        .map_err(|e| HelperError::SpoolFailed(format!("Could not create upload file: {}", e)))?;
+
+    let mut checked = false;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| form_error(e, &format!("Failed to read {} data", what)))?
+    {
+        check_size(upload.size() + chunk.len() as u64, limit, what)?;
+        upload.append(&chunk).await.map_err(write_error)?;
+        on_chunk(&chunk);
+        if !checked && upload.head().len() >= spool::HEAD_BYTES {
+            kind.check_head(upload.head())?;
+            checked = true;
+        }
+    }
+    // Shorter than the head; an empty field is refused later as missing data
+    if !checked && upload.size() > 0 {
+        kind.check_head(upload.head())?;
+    }
+
+    upload.finish().await.map_err(write_error)
+}
+
+/// Write a document received in memory to the spool directory
+async fn spool_data(extension: &str, data: &[u8]) -> Result<spool::SpoolFile, HelperError> {
+    spool::SpoolFile::from_data(extension, data)
+        .await
+        .map_err(|e| HelperError::SpoolFailed(format!("Could not write job file: {}", e)))
+}
+
+/// Lay out a spooled image as a spooled PDF
+async fn spooled_image_pdf(
+    image: &spool::SpoolFile,
+    placement: &ImagePlacement,
+) -> Result<spool::SpoolFile, HelperError> {
+    let image_data = image
+        .read()
+        .await
+        .map_err(|e| HelperError::SpoolFailed(format!("Could not read uploaded image: {}", e)))?;
+    let pdf_data = image_pdf(&image_data, placement)?;
+    spool_data("pdf", &pdf_data).await
+}
+
+/// Reject a batch request because of one of its documents
+fn rejected_document(index: usize, error: HelperError) -> (StatusCode, Json<PrintResponse>) {
+    let (status, Json(mut response)) = PrintResponse::rejected(error);
+    response.error = response.error.map(|e| format!("documents[{}]: {}", index, e));
+    (status, Json(response))
+}
+
+/// Make a received document the spool file of its job; the job fails if the file can't be kept
+fn adopt_document(document: spool::SpoolFile, job_id: &str) -> Result<PathBuf, HelperError> {
+    document.adopt(job_id).map_err(|e| {
+        let e = HelperError::SpoolFailed(format!("Could not keep the job file: {}", e));
+        jobs::fail_job(job_id, &e);
+        e
+    })
+}
+
+/// Refuse a document over the size limit
+fn check_size(size: u64, limit: u64, what: &str) -> Result<(), HelperError> {
+    if size > limit {
+        return Err(HelperError::DocumentTooLarge(format!(
+            "The {} is larger than the {} MB limit",
+            what,
+            limit / (1024 * 1024)
+        )));
+    }
+    Ok(())
+}
+
+/// Body limit for a request carrying up to `document_bytes` of documents, base64-encoded in JSON
+fn body_limit(document_bytes: u64) -> usize {
+    let encoded = document_bytes.saturating_add(2) / 3 * 4;
+    usize::try_from(encoded.saturating_add(REQUEST_OVERHEAD_BYTES)).unwrap_or(usize::MAX)
+}
+
+/// A form that couldn't be read; going over the request body limit is answered with 413
+fn form_error(error: MultipartError, context: &str) -> HelperError {
+    if error.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        HelperError::DocumentTooLarge(format!("{}: request body is over the size limit", context))
+    } else {
+        HelperError::InvalidRequest(format!("{}: {}", context, error))
+    }
+}
+
+/// A JSON body that couldn't be read, answered like any other rejected request
+fn json_error(rejection: JsonRejection) -> (StatusCode, Json<PrintResponse>) {
+    let error = if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        HelperError::DocumentTooLarge("Request body is over the size limit".to_string())
+    } else {
+        HelperError::InvalidRequest(rejection.body_text())
+    };
+    PrintResponse::rejected(error)
+}
+
+/// Handle /print/json - same as /print, with the PDF or image base64-encoded in a JSON body
+async fn handle_print_json(
+    State(_state): State<Arc<ServerState>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    request: Result<Json<serde_json::Map<String, serde_json::Value>>, JsonRejection>,
+) -> Result<(StatusCode, Json<PrintResponse>), (StatusCode, Json<PrintResponse>)> {
+    let Json(fields) = request.map_err(json_error)?;
+    let mut signed = SignedContent::default();
+    signed.add_json_fields(&fields, "", &["pdf", "image"]);
+    let request: JsonPrintRequest = parse_json_fields(fields).map_err(PrintResponse::rejected)?;
+    let limit = config::get_config().limits.document_bytes();
+    if request.pdf.is_some() && request.image.is_some() {
+        return Err(PrintResponse::rejected(HelperError::InvalidRequest(ONE_DOCUMENT_ONLY.to_string())));
+    }
+
+    if let Some(image) = request.image {
+        let image_data = BASE64.decode(image.trim()).map_err(|e| {
+            PrintResponse::rejected(HelperError::InvalidRequest(format!("Invalid base64 image data: {}", e)))
+        })?;
+        check_size(image_data.len() as u64, limit, "image").map_err(PrintResponse::rejected)?;
+        signed.add_document(&image_data);
+        signing::verify(&caller, &headers, &signed).map_err(PrintResponse::rejected)?;
+        let pdf_data = image_pdf(&image_data, &request.options.image).map_err(PrintResponse::rejected)?;
+        let pdf = spool_data("pdf", &pdf_data).await.map_err(PrintResponse::rejected)?;
+        return print_document(request.options, pdf).await;
+    }
+
+    let pdf = request.pdf.ok_or_else(|| {
+        PrintResponse::rejected(HelperError::InvalidRequest("No PDF or image data provided".to_string()))
+    })?;
+    let pdf_data = BASE64.decode(pdf.trim()).map_err(|e| {
+        PrintResponse::rejected(HelperError::InvalidRequest(format!("Invalid base64 PDF data: {}", e)))
+    })?;
+    check_size(pdf_data.len() as u64, limit, "PDF").map_err(PrintResponse::rejected)?;
+    signed.add_document(&pdf_data);
+    signing::verify(&caller, &headers, &signed).map_err(PrintResponse::rejected)?;
+    let pdf = spool_data("pdf", &pdf_data).await.map_err(PrintResponse::rejected)?;
+
+    print_document(request.options, pdf).await
+}
+
+/// Read a JSON body whose fields were already collected for its signature
+fn parse_json_fields<T: serde::de::DeserializeOwned>(
+    fields: serde_json::Map<String, serde_json::Value>,
+) -> Result<T, HelperError> {
+    serde_json::from_value(serde_json::Value::Object(fields))
+        .map_err(|e| HelperError::InvalidRequest(format!("Invalid request: {}", e)))
+}
+
+/// Lay an uploaded image out as a PDF at its physical size
+fn image_pdf(
+    image_data: &[u8],
+    placement: &ImagePlacement,
+) -> Result<Vec<u8>, HelperError> {
+    if image_data.is_empty() {
+        return Err(HelperError::InvalidRequest("No image data provided".to_string()));
+    }
+    images::image_to_pdf(image_data, placement).map_err(HelperError::InvalidDocument)
+}
+
+/// Validate options, record the job and print it
+/// The document, already in the spool directory, becomes the job's spool file.
+async fn print_document(
+    options: PrintOptions,
+    pdf: spool::SpoolFile,
+) -> Result<(StatusCode, Json<PrintResponse>), (StatusCode, Json<PrintResponse>)> {
+    if pdf.size() == 0 {
+        return Err(PrintResponse::rejected(HelperError::InvalidRequest("No PDF data provided".to_string())));
+    }
+
+    // Reject settings the printer can't honour before anything is queued
+    let printer_name = options.printer.as_deref();
+    printer::validate_settings(printer_name, &options.settings).map_err(PrintResponse::rejected)?;
+    let (document, warnings) = check_document(pdf.path(), &options).await.map_err(PrintResponse::rejected)?;
+
+    // Record the job, then queue it for printing; a repeat of a recent job isn't printed again
+    let copies = options.copies.unwrap_or(1);
+    let job = match record_job(printer_name, copies, &pdf, &(&options.settings, &options.image), options.force) {
+        Ok(job) => job,
+        Err(duplicate) if config::get_config().duplicates.action == DuplicateAction::RequireForce => {
+            return Err(PrintResponse::duplicate_refused(*duplicate));
+        }
+        Err(duplicate) => {
+            return Ok((StatusCode::OK, Json(PrintResponse::deduplicated(*duplicate, Some(document), warnings))));
+        }
+    };
+    let pdf_path = adopt_document(pdf, &job.id).map_err(|e| PrintResponse::failed(Some(job.id.clone()), e))?;
+
+    // Progress is followed through /jobs/{id} or /jobs/{id}/events; failures end up on the job record
+    if options.run_async {
+        let job_id = job.id.clone();
+        tokio::spawn(async move {
+            let _ = printer::print_pdf(&job_id, &pdf_path, options.printer.as_deref(), copies, &options.settings).await;
+        });
+        return Ok((
+            StatusCode::ACCEPTED,
             Json(PrintResponse {
-                success: false,
-                error: Some(format!("Failed to parse form data: {}", e)),
-                job_id: None,
+                success: true,
+                error: None,
+                code: None,
+                job_id: Some(job.id),
+                spooler_job_id: None,
+                document: Some(document),
+                warnings,
+                duplicate_of: None,
             }),
-        )
-    })? {
+        ));
+    }
+
+    match printer::print_pdf(&job.id, &pdf_path, printer_name, copies, &options.settings).await {
+        Ok(spooler_job_id) => Ok((
+            StatusCode::OK,
+            Json(PrintResponse {
+                success: true,
+                error: None,
+                code: None,
+                job_id: Some(job.id),
+                spooler_job_id,
+                document: Some(document),
+                warnings,
+                duplicate_of: None,
+            }),
+        )),
+        Err(e) => Err(PrintResponse::failed(Some(job.id), e)),
+    }
+}
+
+/// Record a job, unless it repeats one received within the duplicate window
+fn record_job(
+    printer: Option<&str>,
+    copies: u32,
+    document: &spool::SpoolFile,
+    options: &impl Serialize,
+    force: bool,
+) -> Result<jobs::PrintJob, Box<jobs::Duplicate>> {
+    let settings = &config::get_config().duplicates;
+    let size = document.size() as usize;
+    let result = if settings.window_secs == 0 {
+        Ok(jobs::create_job(printer, copies, size, None))
+    } else {
+        let window = i64::try_from(settings.window_secs)
+            .ok()
+            .and_then(chrono::Duration::try_seconds)
+            .unwrap_or(chrono::Duration::MAX);
+        let fingerprint = jobs::fingerprint(document.digest(), printer, copies, options);
+        jobs::create_job_unless_duplicate(printer, copies, size, &fingerprint, window, force)
+    };
+    set_last_job(match &result {
+        Ok(job) => &job.id,
+        Err(duplicate) => &duplicate.job.id,
+    });
+    result
+}
+
+/// Check the PDF can be printed and compare its pages with the media it will print on
+/// Unreadable, empty and encrypted documents are rejected; a media mismatch is only a warning.
+async fn check_document(
+    pdf_path: &std::path::Path,
+    options: &PrintOptions,
+) -> Result<(PdfInfo, Vec<String>), HelperError> {
+    let pdf_data = tokio::fs::read(pdf_path)
+        .await
+        .map_err(|e| HelperError::SpoolFailed(format!("Could not read job file: {}", e)))?;
+    let document = pdf::inspect(&pdf_data).map_err(HelperError::InvalidDocument)?;
+    let warnings = printer::media_warnings(options.printer.as_deref(), &options.settings, &document.pages);
+    for warning in &warnings {
+        tracing::warn!("{}", warning);
+    }
+    Ok((document, warnings))
+}
+
+/// Handle /print/batch - several documents printed in order as one batch
+/// Each `pdf`, `image` or `url` field starts a new document. Option fields before the first
+/// document apply to all of them; option fields after a document apply to that document only.
+async fn handle_print_batch(
+    State(_state): State<Arc<ServerState>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Json<BatchResponse>, (StatusCode, Json<PrintResponse>)> {
+    let limit = config::get_config().limits.document_bytes();
+    let mut defaults = PrintOptions::default();
+    let mut documents: Vec<(BatchSource, PrintOptions)> = Vec::new();
+    let mut merge = false;
+    let mut signed = SignedContent::default();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| PrintResponse::rejected(form_error(e, "Failed to parse form data")))?
+    {
         let name = field.name().unwrap_or_default().to_string();
 
         match name.as_str() {
-            "pdf" => {
-                pdf_data = Some(field.bytes().await.map_err(|e| {
-                    (
-                        StatusCode::BAD_REQUEST,
-                        Json(PrintResponse {
-                            success: false,
-                            error: Some(format!("Failed to read PDF data: {}", e)),
-                            job_id: None,
-                        }),
-                    )
-                })?);
+            "pdf" | "image" => {
+                let index = documents.len();
+                let kind = if name == "pdf" { UploadKind::Pdf } else { UploadKind::Image };
+                let received = receive_upload(field, limit, "document", kind, |_| {})
+                    .await
+                    .map_err(|e| rejected_document(index, e))?;
+                signed.add_document_digest(received.digest());
+                let source = match kind {
+                    UploadKind::Pdf => BatchSource::Pdf(received),
+                    _ => BatchSource::Image(received),
+                };
+                documents.push((source, defaults.clone()));
             }
-            "printer" => {
+            "url" => {
                 if let Ok(text) = field.text().await {
-                    options.printer = Some(text);
+                    signed.add_field(&name, &text);
+                    documents.push((BatchSource::Url(text.trim().to_string()), defaults.clone()));
                 }
             }
-            "copies" => {
+            "merge" => {
                 if let Ok(text) = field.text().await {
-                    options.copies = text.parse().ok();
+                    signed.add_field(&name, &text);
+                    merge = is_true(&text);
+                }
+            }
+            _ => {
+                if let Ok(text) = field.text().await {
+                    signed.add_field(&name, &text);
+                    let options = match documents.last_mut() {
+                        Some((_, options)) => options,
+                        None => &mut defaults,
+                    };
+                    options
+                        .set_field(&name, &text)
+                        .map_err(|e| PrintResponse::rejected(HelperError::InvalidRequest(e)))?;
+                }
+            }
+        }
+    }
+    signing::verify(&caller, &headers, &signed).map_err(PrintResponse::rejected)?;
+
+    print_batch_documents(documents, merge).await
+}
+
+/// Handle /print/batch/json - same as /print/batch, with documents as a JSON list
+async fn handle_print_batch_json(
+    State(_state): State<Arc<ServerState>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    request: Result<Json<serde_json::Map<String, serde_json::Value>>, JsonRejection>,
+) -> Result<Json<BatchResponse>, (StatusCode, Json<PrintResponse>)> {
+    let Json(fields) = request.map_err(json_error)?;
+    let mut signed = SignedContent::default();
+    signed.add_json_fields(&fields, "", &["documents"]);
+    let request: JsonBatchRequest = parse_json_fields(fields).map_err(PrintResponse::rejected)?;
+    let mut documents = Vec::with_capacity(request.documents.len());
+
+    for (index, fields) in request.documents.into_iter().enumerate() {
+        let rejected = |e: String| {
+            PrintResponse::rejected(HelperError::InvalidRequest(format!("documents[{}]: {}", index, e)))
+        };
+
+        signed.add_json_fields(&fields, &format!("documents[{}].", index), &["pdf", "image"]);
+
+        // Document fields override the batch-wide ones
+        let mut merged = request.defaults.clone();
+        merged.extend(fields);
+        let document: JsonBatchDocument = serde_json::from_value(serde_json::Value::Object(merged))
+            .map_err(|e| rejected(e.to_string()))?;
+
+        let (kind, encoded) = match (document.pdf, document.image, document.url) {
+            (Some(pdf), None, None) => (UploadKind::Pdf, pdf),
+            (None, Some(image), None) => (UploadKind::Image, image),
+            (None, None, Some(url)) => {
+                documents.push((BatchSource::Url(url), document.options));
+                continue;
+            }
+            _ => return Err(rejected("Exactly one of pdf, image or url is required".to_string())),
+        };
+        let data = BASE64
+            .decode(encoded.trim())
+            .map_err(|e| rejected(format!("Invalid base64 {} data: {}", kind.label(), e)))?;
+        signed.add_document(&data);
+        let spooled = spool_data(kind.extension(), &data).await.map_err(|e| rejected_document(index, e))?;
+        let source = match kind {
+            UploadKind::Pdf => BatchSource::Pdf(spooled),
+            _ => BatchSource::Image(spooled),
+        };
+        documents.push((source, document.options));
+    }
+    signing::verify(&caller, &headers, &signed).map_err(PrintResponse::rejected)?;
+
+    print_batch_documents(documents, request.merge).await
+}
+
+/// Check every document of a batch, record them as jobs and print them in order
+/// Nothing is printed if any document is invalid; once printing starts, each document gets its own result.
+/// With `merge`, the documents are printed as a single job and share its result.
+async fn print_batch_documents(
+    documents: Vec<(BatchSource, PrintOptions)>,
+    merge: bool,
+) -> Result<Json<BatchResponse>, (StatusCode, Json<PrintResponse>)> {
+    if documents.is_empty() {
+        return Err(PrintResponse::rejected(HelperError::InvalidRequest("No documents provided".to_string())));
+    }
+    if documents.len() > MAX_BATCH_DOCUMENTS {
+        return Err(PrintResponse::rejected(HelperError::InvalidRequest(format!(
+            "Too many documents ({}, at most {})",
+            documents.len(),
+            MAX_BATCH_DOCUMENTS
+        ))));
+    }
+
+    let limit = config::get_config().limits.document_bytes();
+    let mut prepared: Vec<PreparedDocument> = Vec::with_capacity(documents.len());
+    let mut checks: Vec<(PdfInfo, Vec<String>)> = Vec::with_capacity(documents.len());
+    for (index, (source, options)) in documents.into_iter().enumerate() {
+        let with_index = |e: HelperError| rejected_document(index, e);
+
+        let pdf = match source {
+            BatchSource::Pdf(pdf) => {
+                check_size(pdf.size(), limit, "document").map_err(with_index)?;
+                pdf
+            }
+            BatchSource::Image(image) => {
+                check_size(image.size(), limit, "document").map_err(with_index)?;
+                spooled_image_pdf(&image, &options.image).await.map_err(with_index)?
+            }
+            BatchSource::Url(url) => {
+                let data = fetch_document(&url, limit).await.map_err(with_index)?;
+                let data = if data.starts_with(b"%PDF") {
+                    data
+                } else {
+                    image_pdf(&data, &options.image).map_err(with_index)?
+                };
+                spool_data("pdf", &data).await.map_err(with_index)?
+            }
+        };
+        if pdf.size() == 0 {
+            return Err(with_index(HelperError::InvalidRequest("No PDF data provided".to_string())));
+        }
+
+        printer::validate_settings(options.printer.as_deref(), &options.settings)
+            .map_err(with_index)?;
+        checks.push(check_document(pdf.path(), &options).await.map_err(with_index)?);
+        prepared.push((pdf, options));
+    }
+
+    let (prepared, page_map) = if merge {
+        let (merged, page_map) = merge_batch(prepared).await.map_err(PrintResponse::rejected)?;
+        (vec![merged], Some(page_map))
+    } else {
+        (prepared, None)
+    };
+
+    // Record every job up front so the whole batch is visible in /jobs while it prints
+    let batch_id = uuid::Uuid::new_v4().to_string();
+    let mut documents: Vec<printer::BatchDocument> = Vec::with_capacity(prepared.len());
+    for (pdf, options) in prepared {
+        let copies = options.copies.unwrap_or(1);
+        // Not checked for repeats (see `config::DuplicateSettings`): a batch prints whole or not at all
+        let job = jobs::create_job(options.printer.as_deref(), copies, pdf.size() as usize, Some(&batch_id));
+        set_last_job(&job.id);
+        let pdf_path = match adopt_document(pdf, &job.id) {
+            Ok(path) => path,
+            Err(e) => {
+                // Nothing has printed yet, so the batch fails as a whole
+                for document in &documents {
+                    jobs::fail_job(&document.job_id, &e);
+                }
+                return Err(PrintResponse::failed(Some(job.id), e));
+            }
+        };
+        documents.push(printer::BatchDocument {
+            job_id: job.id,
+            pdf_path,
+            printer_name: options.printer,
+            copies,
+            settings: options.settings,
+        });
+    }
+    tracing::info!("Batch {} received with {} document(s)", batch_id, documents.len());
+
+    let outcomes: Vec<(Result<Option<String>, HelperError>, String)> = printer::print_batch(&documents)
+        .await
+        .into_iter()
+        .zip(documents)
+        .map(|(result, document)| (result, document.job_id))
+        .collect();
+
+    // Every document of a merged batch went out in the one job
+    let results: Vec<BatchItemResult> = checks
+        .into_iter()
+        .enumerate()
+        .map(|(index, (document, warnings))| {
+            let (result, job_id) = &outcomes[if merge { 0 } else { index }];
+            BatchItemResult {
+                index,
+                success: result.is_ok(),
+                error: result.as_ref().err().map(|e| e.to_string()),
+                code: result.as_ref().err().map(HelperError::code),
+                job_id: job_id.clone(),
+                spooler_job_id: result.as_ref().ok().cloned().flatten(),
+                document,
+                warnings,
+            }
+        })
+        .collect();
+
+    Ok(Json(BatchResponse {
+        success: results.iter().all(|r| r.success),
+        batch_id,
+        results,
+        page_map,
+    }))
+}
+
+/// Combine a batch's documents into one PDF for a single job
+/// The documents must all go to the same printer with the same options, since they print as one job.
+async fn merge_batch(
+    prepared: Vec<PreparedDocument>,
+) -> Result<(PreparedDocument, Vec<PageSource>), HelperError> {
+    let options = prepared[0].1.clone();
+    for (index, (_, other)) in prepared.iter().enumerate().skip(1) {
+        if other.printer != options.printer || other.copies != options.copies || other.settings != options.settings {
+            return Err(HelperError::InvalidOptions(format!(
+                "documents[{}]: merged documents must all use the same printer, copies and print options",
+                index
+            )));
+        }
+    }
+    // Page ranges would apply to the merged document, not to each document
+    if options.settings.page_ranges.is_some() {
+        return Err(HelperError::InvalidOptions("pageRanges can't be combined with merge".to_string()));
+    }
+
+    let mut sources = Vec::with_capacity(prepared.len());
+    for (pdf, _) in &prepared {
+        let data = pdf
+            .read()
+            .await
+            .map_err(|e| HelperError::SpoolFailed(format!("Could not read job file: {}", e)))?;
+        sources.push(data);
+    }
+    let sources: Vec<&[u8]> = sources.iter().map(Vec::as_slice).collect();
+    let merged = pdf::merge_pdfs(&sources).map_err(HelperError::InvalidDocument)?;
+    tracing::info!("Merged {} documents into {} pages", sources.len(), merged.pages.len());
+
+    Ok(((spool_data("pdf", &merged.data).await?, options), merged.pages))
+}
+
+/// Download a batch document given by URL, giving up once it passes `limit` bytes
+/// Only public internet hosts are fetched, so a request can't make the helper read from its own
+/// machine or the local network; redirects are checked the same way.
+async fn fetch_document(url: &str, limit: u64) -> Result<Vec<u8>, HelperError> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| HelperError::InvalidDocument(format!("Invalid document URL '{}': {}", url, e)))?;
+    check_document_url(&parsed)
+        .map_err(|e| HelperError::InvalidDocument(format!("Unsupported document URL '{}': {}", url, e)))?;
+
+    let redirects = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_DOCUMENT_REDIRECTS {
+            return attempt.error(format!("more than {} redirects", MAX_DOCUMENT_REDIRECTS));
+        }
+        match check_document_url(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => {
+                let message = format!("redirected to {}: {}", attempt.url(), e);
+                attempt.error(message)
+            }
+        }
+    });
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(DOCUMENT_DOWNLOAD_TIMEOUT_SECS))
+        .redirect(redirects)
+        .dns_resolver(Arc::new(PublicHostResolver))
+        // A proxy would resolve the host itself, past the resolver
+        .no_proxy()
+        .build()
+        .map_err(|e| HelperError::Internal(e.to_string()))?;
+    let download_error =
+        |e: reqwest::Error| HelperError::InvalidDocument(format!("Could not download {}: {}", url, error_chain(&e)));
+    let mut response = client.get(parsed).send().await.map_err(download_error)?;
+    if !response.status().is_success() {
+        return Err(HelperError::InvalidDocument(format!(
+            "Could not download {}: HTTP {}",
+            url,
+            response.status()
+        )));
+    }
+
+    check_size(response.content_length().unwrap_or(0), limit, "document")?;
+    let mut data = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(download_error)? {
+        check_size((data.len() + chunk.len()) as u64, limit, "document")?;
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Check a document URL (or a redirect) is http(s) and, when its host is an IP address, a public one
+/// Host names are checked once resolved, by `PublicHostResolver`.
+fn check_document_url(url: &reqwest::Url) -> Result<(), String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("http or https expected".to_string());
+    }
+    let host = url.host_str().ok_or("no host")?;
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(address) if !is_public_address(address) => Err(format!("{} is not a public internet address", address)),
+        _ => Ok(()),
+    }
+}
+
+/// Whether an address is on the public internet, rather than this machine (loopback), the local
+/// network (private, link-local, shared/CGNAT, unique local) or a special-purpose range
+fn is_public_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => {
+            let [first, second, ..] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_multicast()
+                || v4.is_documentation()
+                || first == 0
+                || (first == 100 && (second & 0xc0) == 64))
+        }
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_public_address(IpAddr::V4(v4)),
+            None => {
+                let first = v6.segments()[0];
+                !(v6.is_loopback()
+                    || v6.is_unspecified()
+                    || v6.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolves the hosts of document URLs to their public addresses only
+/// Filtering the addresses the connection is made to (rather than checking the name up front)
+/// also covers redirects and DNS answers that change between a check and the download.
+struct PublicHostResolver;
+
+impl reqwest::dns::Resolve for PublicHostResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|address| is_public_address(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} is not a public internet host", host).into());
+            }
+            let addresses: reqwest::dns::Addrs = Box::new(addresses.into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+/// An error with its causes, which reqwest keeps out of its own message
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message = format!("{}: {}", message, cause);
+        source = cause.source();
+    }
+    message
+}
+
+/// Handle /print/raw - send ZPL/EPL/ESC-POS (multipart `data` field) to a printer as-is
+async fn handle_print_raw(
+    State(_state): State<Arc<ServerState>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Json<PrintResponse>, (StatusCode, Json<PrintResponse>)> {
+    let limit = config::get_config().limits.document_bytes();
+    let mut signed = SignedContent::default();
+    let mut data: Option<spool::SpoolFile> = None;
+    let mut scan = RawScan::default();
+    let mut printer: Option<String> = None;
+    let mut copies: Option<u32> = None;
+    let mut format = RawFormat::default();
+    let mut force = false;
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| PrintResponse::rejected(form_error(e, "Failed to parse form data")))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+
+        match name.as_str() {
+            "data" => {
+                if data.is_some() {
+                    return Err(PrintResponse::rejected(HelperError::InvalidRequest(
+                        "Send one data field per request".to_string(),
+                    )));
+                }
+                // The format field may still follow, so the data is checked once the form is read
+                scan = RawScan::default();
+                let received = receive_upload(field, limit, "raw", UploadKind::Raw, |chunk| scan.update(chunk))
+                    .await
+                    .map_err(PrintResponse::rejected)?;
+                signed.add_document_digest(received.digest());
+                data = Some(received);
+            }
+            "printer" | "copies" | "format" | "force" => {
+                let Ok(text) = field.text().await else { continue };
+                signed.add_field(&name, &text);
+                match name.as_str() {
+                    "printer" => printer = Some(text),
+                    "copies" => {
+                        copies = parse_copies(&text)
+                            .map_err(|e| PrintResponse::rejected(HelperError::InvalidRequest(e)))?;
+                    }
+                    "force" => force = is_true(&text),
+                    _ => {
+                        format = RawFormat::parse(&text)
+                            .map_err(|e| PrintResponse::rejected(HelperError::InvalidRequest(e)))?;
+                    }
                 }
             }
             _ => {}
         }
     }
+    signing::verify(&caller, &headers, &signed).map_err(PrintResponse::rejected)?;
 
-    // Ensure we have PDF data
-    let pdf_data = pdf_data.ok_or_else(|| {
-        (
-            StatusCode::BAD_REQUEST,
-            Json(PrintResponse {
-                success: false,
-                error: Some("No PDF data provided".to_string()),
-                job_id: None,
-            }),
-        )
+    let data = data.ok_or_else(|| {
+        PrintResponse::rejected(HelperError::InvalidRequest("No data provided".to_string()))
+    })?;
+
+    print_raw_document(printer, copies, format, force, data, &scan).await
+}
+
+/// Handle /print/raw/json - same as /print/raw, with the data base64-encoded in a JSON body
+async fn handle_print_raw_json(
+    State(_state): State<Arc<ServerState>>,
+    Extension(caller): Extension<Caller>,
+    headers: HeaderMap,
+    request: Result<Json<serde_json::Map<String, serde_json::Value>>, JsonRejection>,
+) -> Result<Json<PrintResponse>, (StatusCode, Json<PrintResponse>)> {
+    let Json(fields) = request.map_err(json_error)?;
+    let mut signed = SignedContent::default();
+    signed.add_json_fields(&fields, "", &["data"]);
+    let request: JsonRawPrintRequest = parse_json_fields(fields).map_err(PrintResponse::rejected)?;
+    let data = BASE64.decode(request.data.trim()).map_err(|e| {
+        PrintResponse::rejected(HelperError::InvalidRequest(format!("Invalid base64 data: {}", e)))
     })?;
+    check_size(data.len() as u64, config::get_config().limits.document_bytes(), "raw")
+        .map_err(PrintResponse::rejected)?;
+    signed.add_document(&data);
+    signing::verify(&caller, &headers, &signed).map_err(PrintResponse::rejected)?;
+    let scan = RawScan::new(&data);
+    let data = spool_data("raw", &data).await.map_err(PrintResponse::rejected)?;
 
-    // Save PDF to temp file and print
-    match printer::print_pdf(&pdf_data, options.printer.as_deref(), options.copies.unwrap_or(1)).await {
-        Ok(job_id) => Ok(Json(PrintResponse {
+    let format = request.format.unwrap_or_default();
+    print_raw_document(request.printer, request.copies, format, request.force, data, &scan).await
+}
+
+/// Check a raw job (its spooled data and what `scan` found in it), record it and send it
+async fn print_raw_document(
+    printer: Option<String>,
+    copies: Option<u32>,
+    format: RawFormat,
+    force: bool,
+    data: spool::SpoolFile,
+    scan: &RawScan,
+) -> Result<Json<PrintResponse>, (StatusCode, Json<PrintResponse>)> {
+    if data.size() == 0 {
+        return Err(PrintResponse::rejected(HelperError::InvalidRequest("No data provided".to_string())));
+    }
+
+    // Printer commands only make sense for the printer they were written for, so never fall back to the default
+    let printer = printer.filter(|p| !p.trim().is_empty()).ok_or_else(|| {
+        PrintResponse::rejected(HelperError::InvalidRequest("A printer is required for raw jobs".to_string()))
+    })?;
+    format
+        .check(scan)
+        .map_err(|e| PrintResponse::rejected(HelperError::InvalidDocument(e)))?;
+    printer::check_printer_exists(Some(&printer)).map_err(PrintResponse::rejected)?;
+
+    let copies = copies.unwrap_or(1);
+    let job = match record_job(Some(&printer), copies, &data, &format, force) {
+        Ok(job) => job,
+        Err(duplicate) if config::get_config().duplicates.action == DuplicateAction::RequireForce => {
+            return Err(PrintResponse::duplicate_refused(*duplicate));
+        }
+        Err(duplicate) => return Ok(Json(PrintResponse::deduplicated(*duplicate, None, vec![]))),
+    };
+
+    let data_path = adopt_document(data, &job.id).map_err(|e| PrintResponse::failed(Some(job.id.clone()), e))?;
+
+    match printer::print_raw(&job.id, &data_path, &printer, copies, format).await {
+        Ok(spooler_job_id) => Ok(Json(PrintResponse {
             success: true,
             error: None,
-            job_id: Some(job_id),
+            code: None,
+            job_id: Some(job.id),
+            spooler_job_id,
+            document: None,
+            warnings: vec![],
+            duplicate_of: None,
         })),
-        Err(e) => Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(PrintResponse {
-                success: false,
-                error: Some(e.to_string()),
-                job_id: None,
-            }),
-        )),
+        Err(e) => Err(PrintResponse::failed(Some(job.id), e)),
+    }
+}
+
+/// Error body for endpoints that don't answer with a PrintResponse
+fn error_json(error: HelperError) -> (StatusCode, Json<serde_json::Value>) {
+    (
+        error.status(),
+        Json(serde_json::json!({ "error": error.to_string(), "code": error.code() })),
+    )
+}
+
+/// Handle /jobs - recent print jobs, newest first
+async fn handle_jobs(Query(query): Query<JobsQuery>) -> Json<serde_json::Value> {
+    let jobs = jobs::list_jobs(query.limit, query.batch.as_deref());
+    Json(serde_json::json!({ "jobs": jobs }))
+}
+
+/// Handle /jobs/{id} - status of a single print job
+async fn handle_job(
+    Path(id): Path<String>,
+) -> Result<Json<jobs::PrintJob>, (StatusCode, Json<serde_json::Value>)> {
+    jobs::get_job(&id)
+        .map(Json)
+        .ok_or_else(|| error_json(HelperError::JobNotFound(format!("Job {} not found", id))))
+}
+
+/// Position in a job's event stream
+struct JobEventStream {
+    job_id: String,
+    updates: broadcast::Receiver<jobs::PrintJob>,
+    /// State to send before waiting for changes (the job as it was when the client connected)
+    pending: Option<jobs::PrintJob>,
+    last_status: Option<jobs::JobStatus>,
+    finished: bool,
+}
+
+/// Handle /jobs/{id}/events - server-sent events for each status change of a job
+/// The current state is sent first; the stream ends once the job reaches its last state.
+async fn handle_job_events(
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, Json<serde_json::Value>)> {
+    // Subscribe before reading the job so no change falls in between
+    let updates = jobs::subscribe();
+    let job = jobs::get_job(&id)
+        .ok_or_else(|| error_json(HelperError::JobNotFound(format!("Job {} not found", id))))?;
+
+    let state = JobEventStream {
+        job_id: id,
+        updates,
+        pending: Some(job),
+        last_status: None,
+        finished: false,
+    };
+    Ok(Sse::new(stream::unfold(state, next_job_event)).keep_alive(KeepAlive::default()))
+}
+
+/// Wait for the job's next status change and turn it into an event named after the status
+async fn next_job_event(mut state: JobEventStream) -> Option<(Result<Event, Infallible>, JobEventStream)> {
+    if state.finished {
+        return None;
+    }
+
+    loop {
+        let job = match state.pending.take() {
+            Some(job) => job,
+            None => match state.updates.recv().await {
+                Ok(job) if job.id == state.job_id => job,
+                Ok(_) => continue,
+                // Missed some updates; the stored record has the latest state
+                Err(broadcast::error::RecvError::Lagged(_)) => jobs::get_job(&state.job_id)?,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            },
+        };
+
+        if state.last_status == Some(job.status) {
+            continue;
+        }
+        state.last_status = Some(job.status);
+        state.finished = job.status.is_final()
+            || (job.status == jobs::JobStatus::Submitted && !printer::is_followed(&job));
+
+        let name = serde_json::to_value(job.status)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let event = Event::default()
+            .event(name)
+            .json_data(&job)
+            .unwrap_or_default();
+        return Some((Ok(event), state));
     }
 }
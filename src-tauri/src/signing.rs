@@ -0,0 +1,182 @@
+//! Signed print requests - HMAC-SHA256 over the documents, a timestamp and the print options
+//!
+//! A web app that was paired with a signing secret sends two headers with each print request:
+//! `X-Print-Timestamp` (Unix seconds) and `X-Print-Signature` (hex HMAC-SHA256 of the message
+//! below, keyed with the secret). The message has one line each for the timestamp, the SHA-256
+//! of every document (hex, comma separated, in request order), and each option field as
+//! `name=value`:
+//!
+//! ```text
+//! 1760000000
+//! 9f86d081884c7d65...,60303ae22b998861...
+//! printer=Zebra
+//! copies=2
+//! ```
+//!
+//! Multipart fields are listed in the order they were sent. JSON fields are listed sorted by
+//! name, strings as they are and other values as JSON; the fields of batch documents follow,
+//! document by document, as `documents[0].copies=2`. Backslashes and line breaks in values are
+//! written as `\\` and `\n`. Signatures older (or newer) than the window, and signatures
+//! already seen, are refused.
+
+use crate::access::{self, Caller};
+use crate::error::HelperError;
+use axum::http::HeaderMap;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Header with the request's Unix timestamp
+pub const TIMESTAMP_HEADER: &str = "x-print-timestamp";
+
+/// Header with the hex HMAC-SHA256 signature
+pub const SIGNATURE_HEADER: &str = "x-print-signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signatures accepted within the window, with their timestamps, to refuse replays
+static SEEN_SIGNATURES: Lazy<RwLock<HashMap<String, i64>>> = Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// The parts of a print request a signature covers, collected while the request is read
+#[derive(Default, Debug)]
+pub struct SignedContent {
+    documents: Vec<String>,
+    fields: Vec<(String, String)>,
+}
+
+impl SignedContent {
+    /// Add a document (PDF, image or raw data) by its hash
+    pub fn add_document(&mut self, data: &[u8]) {
+        self.documents.push(hex(&Sha256::digest(data)));
+    }
+
+    /// Add a document by the hex SHA-256 digest taken while it streamed into the spool directory
+    pub fn add_document_digest(&mut self, digest: &str) {
+        self.documents.push(digest.to_string());
+    }
+
+    /// Add an option field as it was sent
+    pub fn add_field(&mut self, name: &str, value: &str) {
+        self.fields.push((name.to_string(), value.to_string()));
+    }
+
+    /// Add the fields of a JSON object, sorted by name, leaving out the document fields
+    /// (added by their content instead); `prefix` names fields of batch documents.
+    pub fn add_json_fields(
+        &mut self,
+        fields: &serde_json::Map<String, serde_json::Value>,
+        prefix: &str,
+        document_fields: &[&str],
+    ) {
+        let mut names: Vec<&String> = fields.keys().collect();
+        names.sort();
+        for name in names {
+            if document_fields.contains(&name.as_str()) {
+                continue;
+            }
+            let value = match &fields[name] {
+                serde_json::Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            self.add_field(&format!("{}{}", prefix, name), &value);
+        }
+    }
+
+    /// The message that is signed
+    fn message(&self, timestamp: &str) -> String {
+        let mut message = format!("{}\n{}", timestamp, self.documents.join(","));
+        for (name, value) in &self.fields {
+            message.push('\n');
+            message.push_str(&escape(name));
+            message.push('=');
+            message.push_str(&escape(value));
+        }
+        message
+    }
+}
+
+/// Check a print request's signature against the caller's secret
+/// Unsigned requests pass unless signing is required. Refusals are logged, so they show up in
+/// the status window's log.
+pub fn verify(caller: &Caller, headers: &HeaderMap, content: &SignedContent) -> Result<(), HelperError> {
+    let settings = access::signing_settings();
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).map(str::trim);
+
+    let result = match (header(SIGNATURE_HEADER), &caller.signing_secret) {
+        (None, _) if !settings.required => return Ok(()),
+        (None, _) => Err(HelperError::InvalidSignature("Print requests must be signed".to_string())),
+        (Some(_), None) => Err(HelperError::InvalidSignature(
+            "This app was paired without a signing secret; pair it again with one".to_string(),
+        )),
+        (Some(signature), Some(secret)) => check(secret, header(TIMESTAMP_HEADER), signature, content, settings.window_secs),
+    };
+
+    if let Err(e) = &result {
+        tracing::warn!("Rejected print request from '{}' ({}): {}", caller.name, caller.app_id, e);
+    }
+    result
+}
+
+/// Check the timestamp window, the signature itself, and that it wasn't used before
+fn check(
+    secret: &str,
+    timestamp: Option<&str>,
+    signature: &str,
+    content: &SignedContent,
+    window_secs: u64,
+) -> Result<(), HelperError> {
+    let timestamp = timestamp
+        .ok_or_else(|| HelperError::InvalidSignature("Missing X-Print-Timestamp header".to_string()))?;
+    let time: i64 = timestamp
+        .parse()
+        .map_err(|_| HelperError::InvalidSignature(format!("Invalid timestamp '{}'", timestamp)))?;
+    let now = chrono::Utc::now().timestamp();
+    let window = i64::try_from(window_secs).unwrap_or(i64::MAX);
+    if now.abs_diff(time) > window.unsigned_abs() {
+        return Err(HelperError::InvalidSignature(format!(
+            "Timestamp is {} seconds off the helper's clock (at most {} allowed)",
+            now - time,
+            window
+        )));
+    }
+
+    let mismatch = || HelperError::InvalidSignature("Signature doesn't match the request".to_string());
+    let signature = decode_hex(signature).ok_or_else(mismatch)?;
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+        .map_err(|e| HelperError::Internal(format!("Invalid signing secret: {}", e)))?;
+    mac.update(content.message(timestamp).as_bytes());
+    mac.verify_slice(&signature).map_err(|_| mismatch())?;
+
+    // Only signatures inside the window need remembering; older ones fail the timestamp check
+    let mut seen = SEEN_SIGNATURES
+        .write()
+        .map_err(|_| HelperError::Internal("Signature cache is unavailable".to_string()))?;
+    seen.retain(|_, seen_time| now.abs_diff(*seen_time) <= window.unsigned_abs());
+    if seen.insert(hex(&signature), time).is_some() {
+        return Err(HelperError::InvalidSignature("Signature was already used".to_string()));
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode a hex signature (either case); None if it isn't hex
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let digit = |byte: u8| char::from(byte).to_digit(16);
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Some((digit(*high)? * 16 + digit(*low)?) as u8),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Keep a name or value on its own line
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n")
+}
@@ -0,0 +1,228 @@
+//! Spool directory - job files handed to the print backends
+//!
+//! Each job's document is kept as `spool/<job id>.pdf` (or `.raw`) in the app data dir. Uploads
+//! are streamed there as `upload-<id>.<extension>` while they are received, and renamed once
+//! they become a job. The file is removed once the backend has the data: right after submission
+//! for backends that read the file while submitting (`lp`, IPP, Windows), or when a followed
+//! spooler job reaches a final state. Files of failed jobs, and anything else left behind, are
+//! swept after a retention period and on startup.
+
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+
+/// How long a job file may stay in the spool directory
+const RETENTION_SECS: u64 = 60 * 60;
+
+/// How often old job files are swept
+const SWEEP_INTERVAL_SECS: u64 = 10 * 60;
+
+/// Get the spool directory
+pub fn get_spool_dir() -> PathBuf {
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("anymobile-print-helper")
+        .join("spool")
+}
+
+/// Create the spool directory and remove files left by a previous run
+pub fn init() {
+    let dir = get_spool_dir();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        tracing::warn!("Could not create spool directory {:?}: {}", dir, e);
+        return;
+    }
+
+    let removed = remove_files(|_| true);
+    if removed > 0 {
+        tracing::info!("Removed {} leftover spool file(s)", removed);
+    }
+}
+
+/// Path of a job's document in the spool directory
+fn job_path(job_id: &str, extension: &str) -> PathBuf {
+    get_spool_dir().join(format!("{}.{}", job_id, extension))
+}
+
+/// Write a job's document to the spool directory
+pub fn write(job_id: &str, extension: &str, data: &[u8]) -> io::Result<PathBuf> {
+    fs::create_dir_all(get_spool_dir())?;
+    let path = job_path(job_id, extension);
+    fs::write(&path, data)?;
+    Ok(path)
+}
+
+/// How much of the start of an upload is kept to check what it is
+pub const HEAD_BYTES: usize = 1024;
+
+/// A document received into the spool directory, before it belongs to a job
+/// The file is removed when this is dropped, unless a job adopted it.
+pub struct SpoolFile {
+    path: PathBuf,
+    size: u64,
+    digest: String,
+    adopted: bool,
+}
+
+impl SpoolFile {
+    /// Write a document received in memory (a JSON body, a download, a rendered image)
+    pub async fn from_data(extension: &str, data: &[u8]) -> io::Result<SpoolFile> {
+        let mut upload = Upload::create(extension).await?;
+        upload.append(data).await?;
+        upload.finish().await
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// SHA-256 of the document, hex-encoded
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    /// Read the document, for the steps that need all of it (decoding an image, merging PDFs)
+    pub async fn read(&self) -> io::Result<Vec<u8>> {
+        tokio::fs::read(&self.path).await
+    }
+
+    /// Make the file the spool file of a job (`<job id>.<extension>`), so it isn't written again
+    pub fn adopt(mut self, job_id: &str) -> io::Result<PathBuf> {
+        let extension = self.path.extension().unwrap_or_default().to_string_lossy().to_string();
+        let path = job_path(job_id, &extension);
+        fs::rename(&self.path, &path)?;
+        self.adopted = true;
+        Ok(path)
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        if self.adopted {
+            return;
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            tracing::debug!("Could not remove upload {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// A document being streamed into the spool directory as `upload-<id>.<extension>`
+/// It is hashed as it arrives and the first bytes are kept, so it can be checked without
+/// reading it back. Dropping an unfinished upload removes the file.
+pub struct Upload {
+    // Closed before the file is removed: Windows doesn't remove open files
+    file: tokio::fs::File,
+    target: SpoolFile,
+    hasher: Sha256,
+    head: Vec<u8>,
+}
+
+impl Upload {
+    /// Start a new upload file
+    pub async fn create(extension: &str) -> io::Result<Upload> {
+        let dir = get_spool_dir();
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(format!("upload-{}.{}", uuid::Uuid::new_v4(), extension));
+        let file = tokio::fs::File::create(&path).await?;
+        Ok(Upload {
+            file,
+            target: SpoolFile {
+                path,
+                size: 0,
+                digest: String::new(),
+                adopted: false,
+            },
+            hasher: Sha256::new(),
+            head: Vec::new(),
+        })
+    }
+
+    /// Bytes received so far
+    pub fn size(&self) -> u64 {
+        self.target.size
+    }
+
+    /// The first bytes received (up to `HEAD_BYTES`)
+    pub fn head(&self) -> &[u8] {
+        &self.head
+    }
+
+    /// Append a chunk of the upload
+    pub async fn append(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.file.write_all(chunk).await?;
+        self.hasher.update(chunk);
+        let wanted = HEAD_BYTES.saturating_sub(self.head.len()).min(chunk.len());
+        self.head.extend_from_slice(&chunk[..wanted]);
+        self.target.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    /// Close the file once everything is received
+    pub async fn finish(self) -> io::Result<SpoolFile> {
+        let Upload { mut file, mut target, hasher, .. } = self;
+        file.flush().await?;
+        drop(file);
+        target.digest = format!("{:x}", hasher.finalize());
+        Ok(target)
+    }
+}
+
+/// Remove a job's files once the spooler no longer needs them
+pub fn release(job_id: &str) {
+    let prefix = format!("{}.", job_id);
+    let removed = remove_files(|name| name.starts_with(&prefix));
+    if removed > 0 {
+        tracing::debug!("Released spool file(s) of job {}", job_id);
+    }
+}
+
+/// Remove files older than the retention period, every few minutes
+pub async fn sweep_periodically() {
+    loop {
+        tokio::time::sleep(Duration::from_secs(SWEEP_INTERVAL_SECS)).await;
+
+        let cutoff = SystemTime::now() - Duration::from_secs(RETENTION_SECS);
+        let removed = tokio::task::spawn_blocking(move || {
+            remove_files_where(|_, modified| modified.is_some_and(|m| m < cutoff))
+        })
+        .await
+        .unwrap_or(0);
+        if removed > 0 {
+            tracing::info!("Removed {} expired spool file(s)", removed);
+        }
+    }
+}
+
+/// Remove spool files whose name matches, returning how many were removed
+fn remove_files(matches: impl Fn(&str) -> bool) -> usize {
+    remove_files_where(|name, _| matches(name))
+}
+
+/// Remove spool files matching on name and modification time
+fn remove_files_where(matches: impl Fn(&str, Option<SystemTime>) -> bool) -> usize {
+    let Ok(entries) = fs::read_dir(get_spool_dir()) else {
+        return 0;
+    };
+
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let modified = entry.metadata().and_then(|m| m.modified()).ok();
+        if !matches(&name, modified) {
+            continue;
+        }
+        match fs::remove_file(entry.path()) {
+            Ok(()) => removed += 1,
+            Err(e) => tracing::warn!("Could not remove spool file {}: {}", name, e),
+        }
+    }
+    removed
+}
@@ -0,0 +1,183 @@
+//! PDF-to-ZPL encoding for thermal label printers
+//!
+//! Rendered pages are dithered to 1-bit and sent as a `^GF` graphic field using Zebra's
+//! ASCII compression. Encoding is deterministic (same image in, same ZPL out), so output can
+//! be compared byte-for-byte against known-good labels.
+
+use image::GrayImage;
+
+/// Floyd-Steinberg dither a grayscale image to 1-bit rows (true = black dot)
+fn dither(image: &GrayImage) -> Vec<Vec<bool>> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let mut levels: Vec<f32> = image.pixels().map(|p| p.0[0] as f32).collect();
+    let mut rows = Vec::with_capacity(height);
+
+    for y in 0..height {
+        let mut row = Vec::with_capacity(width);
+        for x in 0..width {
+            let old = levels[y * width + x];
+            let black = old < 128.0;
+            let error = old - if black { 0.0 } else { 255.0 };
+            row.push(black);
+
+            // Spread the error to the unvisited neighbours
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                if nx >= 0 && (nx as usize) < width && y + dy < height {
+                    levels[(y + dy) * width + nx as usize] += error * weight;
+                }
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+        rows.push(row);
+    }
+
+    rows
+}
+
+/// Pack a row of dots into bytes, most significant bit first
+fn pack_row(row: &[bool]) -> Vec<u8> {
+    row.chunks(8)
+        .map(|dots| {
+            dots.iter()
+                .enumerate()
+                .fold(0u8, |byte, (i, &black)| if black { byte | (0x80 >> i) } else { byte })
+        })
+        .collect()
+}
+
+/// Repeat-count prefix for a run of 2-400 identical hex digits
+/// G-Y are 1-19 and g-z are 20-400 in steps of 20 (ZPL II ASCII compression); they add up.
+fn run_length_prefix(count: usize) -> String {
+    let (twenties, units) = (count / 20, count % 20);
+    let mut prefix = String::new();
+    if twenties > 0 {
+        prefix.push((b'f' + twenties as u8) as char);
+    }
+    if units > 0 {
+        prefix.push((b'F' + units as u8) as char);
+    }
+    prefix
+}
+
+/// Compress one row of hex digits
+/// `,` fills the rest of the row with 0s, `!` with 1s, and a row identical to the previous one is just `:`.
+fn compress_row(hex: &str, previous: Option<&str>) -> String {
+    if previous == Some(hex) {
+        return ":".to_string();
+    }
+
+    let trimmed_zeros = hex.trim_end_matches('0');
+    let trimmed_ones = hex.trim_end_matches('F');
+    let (body, fill) = if trimmed_zeros.len() < hex.len() {
+        (trimmed_zeros, Some(','))
+    } else if trimmed_ones.len() < hex.len() {
+        (trimmed_ones, Some('!'))
+    } else {
+        (hex, None)
+    };
+
+    let mut out = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut count = 1;
+        while chars.peek() == Some(&c) {
+            chars.next();
+            count += 1;
+        }
+        while count > 400 {
+            out.push_str(&run_length_prefix(400));
+            out.push(c);
+            count -= 400;
+        }
+        if count > 1 {
+            out.push_str(&run_length_prefix(count));
+        }
+        out.push(c);
+    }
+
+    if let Some(fill) = fill {
+        out.push(fill);
+    }
+    out
+}
+
+/// Encode one page as a complete ZPL label (`^XA` ... `^XZ`)
+/// The image is printed at 1:1 - one pixel per printer dot - from the top-left corner.
+pub fn encode_label(image: &GrayImage, darkness: Option<i32>) -> String {
+    let rows = dither(image);
+    let bytes_per_row = (image.width() as usize).div_ceil(8);
+    let total_bytes = bytes_per_row * rows.len();
+
+    let mut data = String::new();
+    let mut previous: Option<String> = None;
+    for row in &rows {
+        let hex: String = pack_row(row).iter().map(|b| format!("{:02X}", b)).collect();
+        data.push_str(&compress_row(&hex, previous.as_deref()));
+        previous = Some(hex);
+    }
+
+    let mut zpl = String::from("^XA\n");
+    zpl.push_str(&format!("^PW{}\n^LL{}\n", image.width(), image.height()));
+    if let Some(darkness) = darkness {
+        zpl.push_str(&format!("^MD{}\n", darkness));
+    }
+    zpl.push_str(&format!(
+        "^FO0,0^GFA,{},{},{},{}^FS\n",
+        total_bytes, total_bytes, bytes_per_row, data
+    ));
+    zpl.push_str("^XZ\n");
+    zpl
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A checked-in bitmap and the ZPL it must encode to, from `tests/fixtures/zpl`
+    macro_rules! golden {
+        ($name:literal) => {
+            (
+                $name,
+                include_bytes!(concat!("../tests/fixtures/zpl/", $name, ".png")).as_slice(),
+                include_str!(concat!("../tests/fixtures/zpl/", $name, ".zpl")),
+            )
+        };
+    }
+
+    #[test]
+    fn labels_match_golden_files() {
+        let cases = [
+            (golden!("blank"), None),
+            (golden!("solid"), None),
+            (golden!("bars"), None),     // width not a multiple of 8
+            (golden!("box"), Some(10)),  // repeated rows, ^MD
+            (golden!("gradient"), None), // dithering
+            (golden!("wide"), None),     // runs longer than 400 digits
+        ];
+        for ((name, png, expected), darkness) in cases {
+            let image = image::load_from_memory(png).expect(name).to_luma8();
+            assert_eq!(encode_label(&image, darkness), expected, "{}.zpl", name);
+        }
+    }
+
+    #[test]
+    fn run_lengths_add_up() {
+        assert_eq!(run_length_prefix(2), "H");
+        assert_eq!(run_length_prefix(19), "Y");
+        assert_eq!(run_length_prefix(20), "g");
+        assert_eq!(run_length_prefix(41), "hG");
+        assert_eq!(run_length_prefix(400), "z");
+    }
+
+    #[test]
+    fn rows_are_compressed() {
+        assert_eq!(compress_row("FF0000", None), "HF,");
+        assert_eq!(compress_row("80FFFF", None), "80!");
+        assert_eq!(compress_row("80FFFF", Some("80FFFF")), ":");
+        assert_eq!(compress_row("A5A5", None), "A5A5");
+    }
+}